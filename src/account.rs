@@ -0,0 +1,610 @@
+//! Account values and portfolio positions, as streamed by account update subscriptions, along
+//! with account summaries and positions
+
+use crate::contract::{Contract, ContractId};
+use crate::subscription::Subscription;
+use anyhow::*;
+use rust_decimal::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// One account value, such as `NetLiquidation` or `BuyingPower`. Most values are numbers, but
+/// some, like `AccountType`, are not. `currency` is empty for values that have none, and
+/// `model_code` is only set for values from account updates by model.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountValue {
+    pub key: String,
+    pub value: String,
+    pub currency: String,
+    pub account: String,
+    pub model_code: String,
+}
+
+/// A position in an account, valued at the latest market price
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortfolioItem {
+    pub contract: Contract,
+    pub position: Decimal,
+    pub market_price: f64,
+    pub market_value: f64,
+    pub average_cost: f64,
+    pub unrealized_pnl: f64,
+    pub realized_pnl: f64,
+    pub account: String,
+}
+
+/// What an account update subscription delivers
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccountEvent {
+    Value(AccountValue),
+    Portfolio(Box<PortfolioItem>),
+    /// The time of the last update, as `hh:mm`
+    UpdateTime(String),
+    /// Everything in the account, or the account and model, has been sent once
+    DownloadEnd {
+        account: String,
+        model_code: String,
+    },
+}
+
+/// An account's values and positions, built up from account updates
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountState {
+    /// Keyed by account, model code, value key and currency, since updates by model may cover
+    /// several accounts and models
+    pub values: HashMap<(String, String, String, String), AccountValue>,
+    pub portfolio: HashMap<ContractId, PortfolioItem>,
+    pub update_time: String,
+    /// Whether the initial download of the account has finished
+    pub download_complete: bool,
+}
+
+impl AccountState {
+    /// Applies an event. Returns whether anything changed.
+    pub fn apply(&mut self, event: &AccountEvent) -> bool {
+        match event {
+            AccountEvent::Value(v) => {
+                let key = (
+                    v.account.clone(),
+                    v.model_code.clone(),
+                    v.key.clone(),
+                    v.currency.clone(),
+                );
+                if self.values.get(&key) == Some(v) {
+                    return false;
+                }
+                self.values.insert(key, v.clone());
+            }
+            AccountEvent::Portfolio(item) => {
+                let contract_id = item.contract.contract_id;
+                // Closed positions are sent once more with a position of 0
+                if item.position.is_zero() {
+                    return self.portfolio.remove(&contract_id).is_some();
+                }
+                if self.portfolio.get(&contract_id) == Some(&**item) {
+                    return false;
+                }
+                self.portfolio.insert(contract_id, (**item).clone());
+            }
+            AccountEvent::UpdateTime(time) => {
+                if self.update_time == *time {
+                    return false;
+                }
+                self.update_time = time.clone();
+            }
+            AccountEvent::DownloadEnd { .. } => {
+                if self.download_complete {
+                    return false;
+                }
+                self.download_complete = true;
+            }
+        }
+        true
+    }
+
+    /// The value for a key, in the given currency or `""` for values without one. Returns `None`
+    /// if several accounts or models have the key; use `account_value` for those.
+    pub fn value(&self, key: &str, currency: &str) -> Option<&str> {
+        let mut values = self
+            .values
+            .values()
+            .filter(|v| v.key == key && v.currency == currency);
+        match (values.next(), values.next()) {
+            (Some(v), None) => Some(v.value.as_str()),
+            _ => None,
+        }
+    }
+
+    /// The value for a key in one account and model
+    pub fn account_value(
+        &self,
+        account: &str,
+        model_code: &str,
+        key: &str,
+        currency: &str,
+    ) -> Option<&str> {
+        self.values
+            .get(&(
+                account.to_string(),
+                model_code.to_string(),
+                key.to_string(),
+                currency.to_string(),
+            ))
+            .map(|v| v.value.as_str())
+    }
+
+    /// Like `value`, for values that are numbers
+    pub fn value_f64(&self, key: &str, currency: &str) -> Option<f64> {
+        self.value(key, currency)?.parse().ok()
+    }
+}
+
+/// A subscription to the values and positions of one account, or the values of one account and
+/// model, with the state built from them.
+/// Events are applied on the caller's thread as it reads them, so `state()` is always a
+/// consistent view. Dropping it stops the updates.
+pub struct AccountUpdates {
+    subscription: Subscription<AccountEvent>,
+    state: AccountState,
+    /// The account and model asked for, which the end of a download by model doesn't repeat
+    account: String,
+    model_code: String,
+}
+
+impl AccountUpdates {
+    pub(crate) fn new(
+        subscription: Subscription<AccountEvent>,
+        account: &str,
+        model_code: &str,
+    ) -> AccountUpdates {
+        AccountUpdates {
+            subscription,
+            state: AccountState::default(),
+            account: account.to_string(),
+            model_code: model_code.to_string(),
+        }
+    }
+
+    /// The id of the request, or -1 for the single-account subscription
+    pub fn request_id(&self) -> i32 {
+        self.subscription.request_id()
+    }
+
+    /// The state as of the last event read
+    pub fn state(&self) -> &AccountState {
+        &self.state
+    }
+
+    /// Waits up to `timeout` for an event that changes the state, applies it and returns it
+    pub fn next_change(&mut self, timeout: Duration) -> Option<Result<AccountEvent, Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.subscription.next_timeout(remaining)? {
+                Ok(event) => {
+                    let event = self.label(event);
+                    if self.state.apply(&event) {
+                        return Some(Ok(event));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Applies every event that has arrived. Returns the ones that changed the state.
+    pub fn poll(&mut self) -> Result<Vec<AccountEvent>, Error> {
+        let mut changes = vec![];
+        while let Some(event) = self.subscription.try_next() {
+            let event = self.label(event?);
+            if self.state.apply(&event) {
+                changes.push(event);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Applies events until the initial download of the account has finished
+    pub fn wait_for_download(&mut self, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+        while !self.state.download_complete {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.subscription.next_timeout(remaining) {
+                Some(event) => {
+                    let event = self.label(event?);
+                    self.state.apply(&event);
+                }
+                None => return Err(anyhow!("Timed out waiting for account download")),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        self.subscription.cancel()
+    }
+
+    /// Fills in the account and model on a download end that arrived without them
+    fn label(&self, event: AccountEvent) -> AccountEvent {
+        match event {
+            AccountEvent::DownloadEnd {
+                account,
+                model_code,
+            } if account.is_empty() && model_code.is_empty() => AccountEvent::DownloadEnd {
+                account: self.account.clone(),
+                model_code: self.model_code.clone(),
+            },
+            other => other,
+        }
+    }
+}
+
+/// A value `req_account_summary` can report. The ledger tags report cash balances and other
+/// ledger values in the base currency, in every currency, or in one currency.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AccountSummaryTag {
+    AccountType,
+    NetLiquidation,
+    TotalCashValue,
+    SettledCash,
+    AccruedCash,
+    BuyingPower,
+    EquityWithLoanValue,
+    PreviousEquityWithLoanValue,
+    GrossPositionValue,
+    RegTEquity,
+    RegTMargin,
+    Sma,
+    InitMarginReq,
+    MaintMarginReq,
+    AvailableFunds,
+    ExcessLiquidity,
+    Cushion,
+    FullInitMarginReq,
+    FullMaintMarginReq,
+    FullAvailableFunds,
+    FullExcessLiquidity,
+    LookAheadNextChange,
+    LookAheadInitMarginReq,
+    LookAheadMaintMarginReq,
+    LookAheadAvailableFunds,
+    LookAheadExcessLiquidity,
+    HighestSeverity,
+    DayTradesRemaining,
+    Leverage,
+    Ledger,
+    LedgerAll,
+    LedgerCurrency(String),
+}
+
+impl AccountSummaryTag {
+    /// Every tag other than the ledger ones
+    pub const ALL: [AccountSummaryTag; 29] = [
+        AccountSummaryTag::AccountType,
+        AccountSummaryTag::NetLiquidation,
+        AccountSummaryTag::TotalCashValue,
+        AccountSummaryTag::SettledCash,
+        AccountSummaryTag::AccruedCash,
+        AccountSummaryTag::BuyingPower,
+        AccountSummaryTag::EquityWithLoanValue,
+        AccountSummaryTag::PreviousEquityWithLoanValue,
+        AccountSummaryTag::GrossPositionValue,
+        AccountSummaryTag::RegTEquity,
+        AccountSummaryTag::RegTMargin,
+        AccountSummaryTag::Sma,
+        AccountSummaryTag::InitMarginReq,
+        AccountSummaryTag::MaintMarginReq,
+        AccountSummaryTag::AvailableFunds,
+        AccountSummaryTag::ExcessLiquidity,
+        AccountSummaryTag::Cushion,
+        AccountSummaryTag::FullInitMarginReq,
+        AccountSummaryTag::FullMaintMarginReq,
+        AccountSummaryTag::FullAvailableFunds,
+        AccountSummaryTag::FullExcessLiquidity,
+        AccountSummaryTag::LookAheadNextChange,
+        AccountSummaryTag::LookAheadInitMarginReq,
+        AccountSummaryTag::LookAheadMaintMarginReq,
+        AccountSummaryTag::LookAheadAvailableFunds,
+        AccountSummaryTag::LookAheadExcessLiquidity,
+        AccountSummaryTag::HighestSeverity,
+        AccountSummaryTag::DayTradesRemaining,
+        AccountSummaryTag::Leverage,
+    ];
+}
+
+/// Shown as the name the server uses for the tag
+impl fmt::Display for AccountSummaryTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountSummaryTag::AccountType => f.write_str("AccountType"),
+            AccountSummaryTag::NetLiquidation => f.write_str("NetLiquidation"),
+            AccountSummaryTag::TotalCashValue => f.write_str("TotalCashValue"),
+            AccountSummaryTag::SettledCash => f.write_str("SettledCash"),
+            AccountSummaryTag::AccruedCash => f.write_str("AccruedCash"),
+            AccountSummaryTag::BuyingPower => f.write_str("BuyingPower"),
+            AccountSummaryTag::EquityWithLoanValue => f.write_str("EquityWithLoanValue"),
+            AccountSummaryTag::PreviousEquityWithLoanValue => {
+                f.write_str("PreviousEquityWithLoanValue")
+            }
+            AccountSummaryTag::GrossPositionValue => f.write_str("GrossPositionValue"),
+            AccountSummaryTag::RegTEquity => f.write_str("RegTEquity"),
+            AccountSummaryTag::RegTMargin => f.write_str("RegTMargin"),
+            AccountSummaryTag::Sma => f.write_str("SMA"),
+            AccountSummaryTag::InitMarginReq => f.write_str("InitMarginReq"),
+            AccountSummaryTag::MaintMarginReq => f.write_str("MaintMarginReq"),
+            AccountSummaryTag::AvailableFunds => f.write_str("AvailableFunds"),
+            AccountSummaryTag::ExcessLiquidity => f.write_str("ExcessLiquidity"),
+            AccountSummaryTag::Cushion => f.write_str("Cushion"),
+            AccountSummaryTag::FullInitMarginReq => f.write_str("FullInitMarginReq"),
+            AccountSummaryTag::FullMaintMarginReq => f.write_str("FullMaintMarginReq"),
+            AccountSummaryTag::FullAvailableFunds => f.write_str("FullAvailableFunds"),
+            AccountSummaryTag::FullExcessLiquidity => f.write_str("FullExcessLiquidity"),
+            AccountSummaryTag::LookAheadNextChange => f.write_str("LookAheadNextChange"),
+            AccountSummaryTag::LookAheadInitMarginReq => f.write_str("LookAheadInitMarginReq"),
+            AccountSummaryTag::LookAheadMaintMarginReq => f.write_str("LookAheadMaintMarginReq"),
+            AccountSummaryTag::LookAheadAvailableFunds => f.write_str("LookAheadAvailableFunds"),
+            AccountSummaryTag::LookAheadExcessLiquidity => f.write_str("LookAheadExcessLiquidity"),
+            AccountSummaryTag::HighestSeverity => f.write_str("HighestSeverity"),
+            AccountSummaryTag::DayTradesRemaining => f.write_str("DayTradesRemaining"),
+            AccountSummaryTag::Leverage => f.write_str("Leverage-S"),
+            AccountSummaryTag::Ledger => f.write_str("$LEDGER"),
+            AccountSummaryTag::LedgerAll => f.write_str("$LEDGER:ALL"),
+            AccountSummaryTag::LedgerCurrency(currency) => write!(f, "$LEDGER:{}", currency),
+        }
+    }
+}
+
+/// One value from an account summary. Ledger tags report values under their own names, such as
+/// `CashBalance`, once per currency.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountSummaryValue {
+    pub tag: String,
+    pub value: String,
+    /// The value parsed as a number, if it is one
+    pub number: Option<f64>,
+    pub currency: String,
+}
+
+impl AccountSummaryValue {
+    pub fn new(tag: String, value: String, currency: String) -> AccountSummaryValue {
+        AccountSummaryValue {
+            number: value.parse().ok(),
+            tag,
+            value,
+            currency,
+        }
+    }
+}
+
+/// The summary values reported for one account
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountSummary {
+    pub account: String,
+    pub values: Vec<AccountSummaryValue>,
+}
+
+impl AccountSummary {
+    /// The value for a tag, in the given currency or `""` for values without one
+    pub fn get(&self, tag: &str, currency: &str) -> Option<&AccountSummaryValue> {
+        self.values
+            .iter()
+            .find(|v| v.tag == tag && v.currency == currency)
+    }
+
+    pub fn number(&self, tag: &str, currency: &str) -> Option<f64> {
+        self.get(tag, currency)?.number
+    }
+}
+
+/// A position held in an account. `model_code` is only set for positions requested by model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub account: String,
+    pub contract: Contract,
+    pub position: Decimal,
+    pub average_cost: f64,
+    pub model_code: String,
+}
+
+/// What a positions subscription delivers: a position, or `None` once every position has been
+/// sent once
+pub type PositionEvent = Option<Position>;
+
+/// A subscription to positions, with the positions built up from it. Positions are keyed by
+/// account, model code and contract id, and closed positions are removed. Dropping it stops the
+/// updates.
+pub struct Positions {
+    subscription: Subscription<PositionEvent>,
+    positions: HashMap<(String, String, ContractId), Position>,
+    initial_complete: bool,
+}
+
+impl Positions {
+    pub(crate) fn new(subscription: Subscription<PositionEvent>) -> Positions {
+        Positions {
+            subscription,
+            positions: HashMap::new(),
+            initial_complete: false,
+        }
+    }
+
+    pub fn request_id(&self) -> i32 {
+        self.subscription.request_id()
+    }
+
+    /// The positions as of the last update read
+    pub fn positions(&self) -> Vec<&Position> {
+        self.positions.values().collect()
+    }
+
+    /// Whether every position has been sent once
+    pub fn initial_complete(&self) -> bool {
+        self.initial_complete
+    }
+
+    /// Waits up to `timeout` for the next position update, applies it and returns it
+    pub fn next_update(&mut self, timeout: Duration) -> Option<Result<Position, Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.subscription.next_timeout(remaining)? {
+                Ok(Some(position)) => {
+                    self.apply(position.clone());
+                    return Some(Ok(position));
+                }
+                Ok(None) => self.initial_complete = true,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Applies every update that has arrived. Returns the positions that changed.
+    pub fn poll(&mut self) -> Result<Vec<Position>, Error> {
+        let mut updates = vec![];
+        while let Some(event) = self.subscription.try_next() {
+            match event? {
+                Some(position) => {
+                    self.apply(position.clone());
+                    updates.push(position);
+                }
+                None => self.initial_complete = true,
+            }
+        }
+        Ok(updates)
+    }
+
+    /// Applies updates until every position has been sent once
+    pub fn wait_for_initial(&mut self, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+        while !self.initial_complete {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.subscription.next_timeout(remaining) {
+                Some(event) => match event? {
+                    Some(position) => self.apply(position),
+                    None => self.initial_complete = true,
+                },
+                None => return Err(anyhow!("Timed out waiting for positions")),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, position: Position) {
+        let key = (
+            position.account.clone(),
+            position.model_code.clone(),
+            position.contract.contract_id,
+        );
+        if position.position.is_zero() {
+            self.positions.remove(&key);
+        } else {
+            self.positions.insert(key, position);
+        }
+    }
+
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        self.subscription.cancel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(contract_id: ContractId, position: i64) -> PortfolioItem {
+        PortfolioItem {
+            contract: Contract {
+                contract_id,
+                ..Default::default()
+            },
+            position: Decimal::from(position),
+            market_price: 150.0,
+            market_value: 150.0 * position as f64,
+            average_cost: 140.0,
+            unrealized_pnl: 10.0 * position as f64,
+            realized_pnl: 0.0,
+            account: "DU123".to_string(),
+        }
+    }
+
+    #[test]
+    fn apply_account_events() {
+        let mut state = AccountState::default();
+        let value = AccountEvent::Value(AccountValue {
+            key: "NetLiquidation".to_string(),
+            value: "100000.5".to_string(),
+            currency: "USD".to_string(),
+            account: "DU123".to_string(),
+            model_code: String::new(),
+        });
+        assert!(state.apply(&value));
+        assert!(!state.apply(&value));
+        assert_eq!(state.value_f64("NetLiquidation", "USD"), Some(100_000.5));
+
+        assert!(state.apply(&AccountEvent::Portfolio(Box::new(item(265_598, 100)))));
+        assert!(state.apply(&AccountEvent::Portfolio(Box::new(item(265_598, 0)))));
+        assert!(state.portfolio.is_empty());
+
+        assert!(state.apply(&AccountEvent::DownloadEnd {
+            account: "DU123".to_string(),
+            model_code: String::new(),
+        }));
+        assert!(state.download_complete);
+    }
+
+    #[test]
+    fn values_from_several_accounts_and_models_are_kept_apart() {
+        let mut state = AccountState::default();
+        let value = |account: &str, model_code: &str, value: &str| {
+            AccountEvent::Value(AccountValue {
+                key: "NetLiquidation".to_string(),
+                value: value.to_string(),
+                currency: "USD".to_string(),
+                account: account.to_string(),
+                model_code: model_code.to_string(),
+            })
+        };
+        assert!(state.apply(&value("DU123", "", "1000")));
+        assert_eq!(state.value("NetLiquidation", "USD"), Some("1000"));
+        assert!(state.apply(&value("DU456", "", "2000")));
+        assert!(state.apply(&value("DU456", "GROWTH", "500")));
+
+        assert_eq!(state.values.len(), 3);
+        assert_eq!(state.value("NetLiquidation", "USD"), None);
+        assert_eq!(
+            state.account_value("DU123", "", "NetLiquidation", "USD"),
+            Some("1000")
+        );
+        assert_eq!(
+            state.account_value("DU456", "GROWTH", "NetLiquidation", "USD"),
+            Some("500")
+        );
+    }
+
+    #[test]
+    fn summary_tags_and_values() {
+        let tags: Vec<String> = [
+            AccountSummaryTag::Leverage,
+            AccountSummaryTag::LedgerCurrency("EUR".to_string()),
+        ]
+        .iter()
+        .map(|t| t.to_string())
+        .collect();
+        assert_eq!(tags, vec!["Leverage-S", "$LEDGER:EUR"]);
+
+        let summary = AccountSummary {
+            account: "DU123".to_string(),
+            values: vec![
+                AccountSummaryValue::new(
+                    "AccountType".to_string(),
+                    "INDIVIDUAL".to_string(),
+                    String::new(),
+                ),
+                AccountSummaryValue::new("Cushion".to_string(), "0.452".to_string(), String::new()),
+            ],
+        };
+        assert_eq!(summary.number("Cushion", ""), Some(0.452));
+        assert_eq!(summary.number("AccountType", ""), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! Support for downloading long ranges of historical bars. The server limits how much history
+//! one request may cover for each bar size and how often historical requests may be made, so a
+//! backfill walks backwards from the end time in chunks, spacing the requests out.
+
+use crate::bars::{Bar, BarSize, Duration, WhatToShow};
+use crate::decoder::TwsError;
+use anyhow::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// At most this many historical requests may be made in `PACING_WINDOW`
+const PACING_LIMIT: usize = 60;
+const PACING_WINDOW: std::time::Duration = std::time::Duration::from_secs(600);
+/// At most this many requests for the same contract may be made in `BURST_WINDOW`
+const BURST_LIMIT: usize = 5;
+const BURST_WINDOW: std::time::Duration = std::time::Duration::from_secs(2);
+/// How long to back off after the server reports a pacing violation anyway
+pub(crate) const PACING_VIOLATION_BACKOFF: std::time::Duration = std::time::Duration::from_secs(60);
+pub(crate) const PACING_VIOLATION_RETRIES: usize = 3;
+
+/// A range of bars to download. Bar times in the result are seconds since the epoch, except for
+/// daily and longer bars, which the server always sends as `yyyymmdd`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackfillRequest {
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub bar_size: BarSize,
+    pub what_to_show: WhatToShow,
+    pub use_rth: bool,
+}
+
+/// The longest duration one request may cover for a bar size, and how many seconds to step
+/// back when a chunk returns no bars. Steps are a little shorter than months and years so
+/// consecutive chunks overlap rather than leave gaps.
+pub(crate) fn chunk(bar_size: BarSize) -> (Duration, i64) {
+    const DAY: i64 = 86_400;
+    match bar_size {
+        BarSize::Sec1 => (Duration::Seconds(1800), 1800),
+        BarSize::Sec5 => (Duration::Seconds(3600), 3600),
+        BarSize::Sec10 | BarSize::Sec15 => (Duration::Seconds(14_400), 14_400),
+        BarSize::Sec30 => (Duration::Seconds(28_800), 28_800),
+        BarSize::Min1 => (Duration::Days(1), DAY),
+        BarSize::Min2 => (Duration::Days(2), 2 * DAY),
+        BarSize::Min3 | BarSize::Min5 | BarSize::Min10 | BarSize::Min15 | BarSize::Min20 => {
+            (Duration::Weeks(1), 7 * DAY)
+        }
+        BarSize::Min30
+        | BarSize::Hour1
+        | BarSize::Hour2
+        | BarSize::Hour3
+        | BarSize::Hour4
+        | BarSize::Hour8 => (Duration::Months(1), 28 * DAY),
+        BarSize::Day1 | BarSize::Week1 | BarSize::Month1 => (Duration::Years(1), 360 * DAY),
+    }
+}
+
+/// Keeps historical requests within the server's pacing limits
+#[derive(Debug, Default)]
+pub(crate) struct HistoricalPacer {
+    sent: VecDeque<Instant>,
+}
+
+impl HistoricalPacer {
+    /// Blocks until another request may be sent, and records it as sent
+    pub(crate) fn wait(&mut self) {
+        let delay = self.delay(Instant::now());
+        if delay > std::time::Duration::from_secs(0) {
+            thread::sleep(delay);
+        }
+        self.sent.push_back(Instant::now());
+    }
+
+    fn delay(&mut self, now: Instant) -> std::time::Duration {
+        while let Some(first) = self.sent.front() {
+            if now.duration_since(*first) >= PACING_WINDOW {
+                self.sent.pop_front();
+            } else {
+                break;
+            }
+        }
+        let mut ready = now;
+        for (limit, window) in &[(PACING_LIMIT, PACING_WINDOW), (BURST_LIMIT, BURST_WINDOW)] {
+            if self.sent.len() >= *limit {
+                ready = ready.max(self.sent[self.sent.len() - limit] + *window);
+            }
+        }
+        ready - now
+    }
+}
+
+/// Adds the bars that fall within `start..=end` to `series`, replacing bars already there with
+/// the same time. Returns the time of the earliest bar received, if there were any.
+pub(crate) fn merge_bars(
+    series: &mut BTreeMap<i64, Bar>,
+    bars: Vec<Bar>,
+    start: i64,
+    end: i64,
+) -> Result<Option<i64>, Error> {
+    let mut earliest = None;
+    for bar in bars {
+        let time = parse_bar_time(&bar.time)?;
+        earliest = Some(earliest.map_or(time, |e: i64| e.min(time)));
+        if time >= start && time <= end {
+            series.insert(time, bar);
+        }
+    }
+    Ok(earliest)
+}
+
+/// Whether the server rejected a request because the period held no data. The server reports
+/// this as an error rather than sending an empty list of bars.
+pub(crate) fn is_no_data(e: &Error) -> bool {
+    match e.downcast_ref::<TwsError>() {
+        Some(e) => e.code == 162 && e.message.contains("returned no data"),
+        None => false,
+    }
+}
+
+pub(crate) fn is_pacing_violation(e: &Error) -> bool {
+    match e.downcast_ref::<TwsError>() {
+        Some(e) => e.code == 162 && e.message.contains("pacing violation"),
+        None => false,
+    }
+}
+
+pub(crate) fn epoch_seconds(time: SystemTime) -> Result<i64, Error> {
+    Ok(time.duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+/// Reads a bar time sent as seconds since the epoch, or as `yyyymmdd` for daily and longer bars
+pub(crate) fn parse_bar_time(time: &str) -> Result<i64, Error> {
+    let time = time.trim();
+    if time.len() == 8 {
+        let number = |range: std::ops::Range<usize>| -> Result<i64, Error> {
+            time[range]
+                .parse::<i64>()
+                .map_err(|e| anyhow!("Invalid bar date {}: {}", time, e))
+        };
+        return Ok(days_from_civil(number(0..4)?, number(4..6)?, number(6..8)?) * 86_400);
+    }
+    time.parse::<i64>()
+        .map_err(|e| anyhow!("Invalid bar time {}: {}", time, e))
+}
+
+/// Formats seconds since the epoch as a date and time the server accepts, in UTC
+pub(crate) fn format_utc_date_time(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+    format!(
+        "{:04}{:02}{:02} {:02}:{:02}:{:02} GMT",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(time: &str, close: f64) -> Bar {
+        Bar {
+            time: time.to_string(),
+            close,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_overlapping_chunks() {
+        let mut series = BTreeMap::new();
+        let later = vec![bar("1603200660", 2.0), bar("1603200720", 3.0)];
+        let earlier = vec![bar("1603200600", 1.0), bar("1603200660", 2.5)];
+        assert_eq!(
+            merge_bars(&mut series, later, 1_603_200_600, 1_603_200_720).unwrap(),
+            Some(1_603_200_660)
+        );
+        assert_eq!(
+            merge_bars(&mut series, earlier, 1_603_200_600, 1_603_200_720).unwrap(),
+            Some(1_603_200_600)
+        );
+        let closes: Vec<f64> = series.values().map(|b| b.close).collect();
+        assert_eq!(closes, vec![1.0, 2.5, 3.0]);
+    }
+
+    #[test]
+    fn bar_times_round_trip() {
+        assert_eq!(parse_bar_time("20201020").unwrap(), 1_603_152_000);
+        assert_eq!(parse_bar_time("19691231").unwrap(), -86_400);
+        assert_eq!(
+            format_utc_date_time(1_603_209_600 + 3723),
+            "20201020 17:02:03 GMT"
+        );
+        assert_eq!(format_utc_date_time(951_782_400), "20000229 00:00:00 GMT");
+    }
+
+    #[test]
+    fn pacer_spaces_out_bursts() {
+        let mut pacer = HistoricalPacer::default();
+        let now = Instant::now();
+        for _ in 0..BURST_LIMIT {
+            assert_eq!(pacer.delay(now), std::time::Duration::from_secs(0));
+            pacer.sent.push_back(now);
+        }
+        assert_eq!(pacer.delay(now), BURST_WINDOW);
+    }
+}
@@ -0,0 +1,291 @@
+//! Price bars, from real-time bar subscriptions and historical data requests, and histograms of
+//! traded volume by price
+
+use crate::subscription::Subscription;
+use anyhow::*;
+use rust_decimal::Decimal;
+use std::fmt;
+
+/// One bar. `time` is the start of the bar; for real-time bars it is in seconds since the
+/// epoch, and for historical bars it follows the request's `DateFormat`. `wap` is the
+/// volume-weighted average price and `count` the number of trades.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bar {
+    pub time: String,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: Decimal,
+    pub wap: Decimal,
+    pub count: i32,
+}
+
+/// The volume traded at one price, from `req_histogram_data`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramEntry {
+    pub price: f64,
+    pub size: Decimal,
+}
+
+/// The data a bar is built from. Real-time bars only support `Trades`, `Midpoint`, `Bid` and
+/// `Ask`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WhatToShow {
+    Trades,
+    Midpoint,
+    Bid,
+    Ask,
+    BidAsk,
+    AdjustedLast,
+    HistoricalVolatility,
+    OptionImpliedVolatility,
+}
+
+impl WhatToShow {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WhatToShow::Trades => "TRADES",
+            WhatToShow::Midpoint => "MIDPOINT",
+            WhatToShow::Bid => "BID",
+            WhatToShow::Ask => "ASK",
+            WhatToShow::BidAsk => "BID_ASK",
+            WhatToShow::AdjustedLast => "ADJUSTED_LAST",
+            WhatToShow::HistoricalVolatility => "HISTORICAL_VOLATILITY",
+            WhatToShow::OptionImpliedVolatility => "OPTION_IMPLIED_VOLATILITY",
+        }
+    }
+}
+
+impl fmt::Display for WhatToShow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How far back from the end time a historical request reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Duration {
+    Seconds(u32),
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    Years(u32),
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Duration::Seconds(n) => write!(f, "{} S", n),
+            Duration::Days(n) => write!(f, "{} D", n),
+            Duration::Weeks(n) => write!(f, "{} W", n),
+            Duration::Months(n) => write!(f, "{} M", n),
+            Duration::Years(n) => write!(f, "{} Y", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BarSize {
+    Sec1,
+    Sec5,
+    Sec10,
+    Sec15,
+    Sec30,
+    Min1,
+    Min2,
+    Min3,
+    Min5,
+    Min10,
+    Min15,
+    Min20,
+    Min30,
+    Hour1,
+    Hour2,
+    Hour3,
+    Hour4,
+    Hour8,
+    Day1,
+    Week1,
+    Month1,
+}
+
+impl BarSize {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BarSize::Sec1 => "1 secs",
+            BarSize::Sec5 => "5 secs",
+            BarSize::Sec10 => "10 secs",
+            BarSize::Sec15 => "15 secs",
+            BarSize::Sec30 => "30 secs",
+            BarSize::Min1 => "1 min",
+            BarSize::Min2 => "2 mins",
+            BarSize::Min3 => "3 mins",
+            BarSize::Min5 => "5 mins",
+            BarSize::Min10 => "10 mins",
+            BarSize::Min15 => "15 mins",
+            BarSize::Min20 => "20 mins",
+            BarSize::Min30 => "30 mins",
+            BarSize::Hour1 => "1 hour",
+            BarSize::Hour2 => "2 hours",
+            BarSize::Hour3 => "3 hours",
+            BarSize::Hour4 => "4 hours",
+            BarSize::Hour8 => "8 hours",
+            BarSize::Day1 => "1 day",
+            BarSize::Week1 => "1 week",
+            BarSize::Month1 => "1 month",
+        }
+    }
+}
+
+impl fmt::Display for BarSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How the server formats bar times in historical data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DateFormat {
+    /// `yyyymmdd hh:mm:ss` for intraday bars, `yyyymmdd` for daily and longer bars
+    Text,
+    /// Seconds since the epoch
+    Epoch,
+}
+
+impl DateFormat {
+    pub fn id(self) -> i32 {
+        match self {
+            DateFormat::Text => 1,
+            DateFormat::Epoch => 2,
+        }
+    }
+}
+
+/// A historical bars request. An empty `end_date_time` means now; otherwise it is
+/// `yyyymmdd hh:mm:ss` followed by an optional time zone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalDataRequest {
+    pub end_date_time: String,
+    pub duration: Duration,
+    pub bar_size: BarSize,
+    pub what_to_show: WhatToShow,
+    pub use_rth: bool,
+    pub format_date: DateFormat,
+}
+
+impl HistoricalDataRequest {
+    /// Bars up to now, regular trading hours only, with text times
+    pub fn new(duration: Duration, bar_size: BarSize, what_to_show: WhatToShow) -> Self {
+        HistoricalDataRequest {
+            end_date_time: String::new(),
+            duration,
+            bar_size,
+            what_to_show,
+            use_rth: true,
+            format_date: DateFormat::Text,
+        }
+    }
+}
+
+/// What a historical data subscription delivers
+#[derive(Debug, Clone, PartialEq)]
+pub enum BarEvent {
+    /// The requested bars, oldest first
+    Bars(Vec<Bar>),
+    /// The latest state of the bar in progress, sent while keeping up to date
+    Update(Bar),
+}
+
+/// Historical bars that are kept up to date as new data arrives. Updates are applied on the
+/// caller's thread when it polls. Dropping it cancels the subscription.
+pub struct HistoricalBars {
+    subscription: Subscription<BarEvent>,
+    bars: Vec<Bar>,
+}
+
+impl HistoricalBars {
+    pub(crate) fn new(subscription: Subscription<BarEvent>, bars: Vec<Bar>) -> HistoricalBars {
+        HistoricalBars { subscription, bars }
+    }
+
+    pub fn request_id(&self) -> i32 {
+        self.subscription.request_id()
+    }
+
+    /// The bars as of the last `poll` or `wait`, oldest first. The last bar may be incomplete.
+    pub fn bars(&self) -> &[Bar] {
+        &self.bars
+    }
+
+    /// Applies every update that has arrived. Returns how many were applied.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        let mut applied = 0;
+        while let Some(event) = self.subscription.try_next() {
+            self.apply(event?);
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Waits up to `timeout` for at least one update, then applies everything that has arrived.
+    /// Returns how many updates were applied.
+    pub fn wait(&mut self, timeout: std::time::Duration) -> Result<usize, Error> {
+        match self.subscription.next_timeout(timeout) {
+            Some(event) => {
+                self.apply(event?);
+                Ok(1 + self.poll()?)
+            }
+            None => Ok(0),
+        }
+    }
+
+    pub(crate) fn apply(&mut self, event: BarEvent) {
+        match event {
+            BarEvent::Bars(bars) => self.bars = bars,
+            BarEvent::Update(bar) => apply_update(&mut self.bars, bar),
+        }
+    }
+
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        self.subscription.cancel()
+    }
+}
+
+/// Updates repeat the bar in progress until its period ends, so an update with the same time
+/// as the last bar replaces it and any other starts a new bar
+fn apply_update(bars: &mut Vec<Bar>, bar: Bar) {
+    match bars.last_mut() {
+        Some(last) if last.time == bar.time => *last = bar,
+        _ => bars.push(bar),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(time: &str, close: f64) -> Bar {
+        Bar {
+            time: time.to_string(),
+            close,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn updates_replace_bar_in_progress() {
+        let mut bars = vec![bar("20201020 09:30:00", 150.0)];
+        apply_update(&mut bars, bar("20201020 09:31:00", 150.2));
+        apply_update(&mut bars, bar("20201020 09:31:00", 150.4));
+        assert_eq!(
+            bars,
+            vec![
+                bar("20201020 09:30:00", 150.0),
+                bar("20201020 09:31:00", 150.4)
+            ]
+        );
+        assert_eq!(Duration::Days(5).to_string(), "5 D");
+        assert_eq!(BarSize::Min1.as_str(), "1 min");
+    }
+}
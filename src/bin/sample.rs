@@ -1,5 +1,3 @@
-use env_logger;
-use ibkrust;
 use log::{error, info};
 
 pub fn main() {
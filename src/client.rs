@@ -3,68 +3,140 @@
 
 use anyhow::*;
 
-use crate::socket::IBSocket;
-use std::collections::VecDeque;
+use crate::account::{
+    AccountEvent, AccountSummary, AccountSummaryTag, AccountUpdates, PositionEvent, Positions,
+};
+use crate::backfill::{self, BackfillRequest, HistoricalPacer};
+use crate::bars::{
+    Bar, BarEvent, DateFormat, HistogramEntry, HistoricalBars, HistoricalDataRequest, WhatToShow,
+};
+use crate::contract::{add_contract_fields, Contract, SecurityType};
+use crate::decoder::{Decoder, Response};
+use crate::depth::{DepthEvent, DepthMktDataDescription, MarketDepth};
+use crate::dispatcher::{Dispatcher, RouteKey};
+use crate::execution::{ExecutionFilter, ExecutionReport};
+use crate::market_data::{
+    HistoricalTickType, HistoricalTicks, MarketDataType, OptionGreeks, Tick, TickByTick,
+    TickByTickType,
+};
 use crate::message;
-use crate::message::IBField;
+use crate::message::{Message, OutboundMessages};
+use crate::options::{ChainFilter, ExerciseAction, OptionCalculation, OptionParameters};
+use crate::order::{
+    encode_tag_values, CompletedOrder, OpenOrder, Order, OrderId, OrderStatus, PermId, TagValue,
+};
+use crate::order_encoder;
+use crate::order_tracker::{OrderEvent, TrackedOrder};
+use crate::quote::{LiveQuote, Quote};
+use crate::server_versions::*;
+use crate::socket::IBSocket;
+use crate::subscription::Subscription;
+use crossbeam_channel::{select, Receiver, Sender};
 use log::*;
-use std::convert::TryInto;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 type ClientID = u32;
-type Message = String;
 
-static V100_PREFIX: &'static str = "API\x00";
+static V100_PREFIX: &str = "API\x00";
 static MIN_CLIENT_VER: u32 = 100;
 static MAX_CLIENT_VER: u32 = 157;
 static VERSION: u32 = 2;
-static DELIMITER: u8 = 0;
+static DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The server disconnects clients that send more than 50 messages a second
+static SNAPSHOT_PACING: Duration = Duration::from_millis(25);
+/// How many ticks to ask for per page when downloading historical ticks over a range; the most
+/// the server returns for one request
+static HISTORICAL_TICKS_PAGE: i32 = 1000;
+/// Request ids start well above order ids, since the server reports errors for both by id
+static FIRST_REQUEST_ID: i32 = 100_000_000;
 /// EClient is a struct representing a client that is connected to the server. It handles the message queue, sending messages, and other lower-level
 /// details related to sending/receiving messages. It contains the TCP connection struct.
 pub struct EClient {
-    msg_queue: VecDeque<Message>,
     host: String,
     port: u16,
     conn: IBSocket,
-    extra_auth: bool,
     client_id: ClientID,
-    server_version: Option<String>,
+    server_version: Option<usize>,
     connection_state: ConnectionState,
-    asynchronous: bool,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    outbound: Option<Sender<Message>>,
+    next_request_id: AtomicI32,
+    timeout: Duration,
+    /// Shared by every historical data request, since the server paces them per connection
+    historical_pacer: Mutex<HistoricalPacer>,
 }
 
 impl EClient {
     pub fn new<S: Into<String>>(hostname: S, port: u16, client_id: u32) -> EClient {
         let hostname = hostname.into();
         EClient {
-            msg_queue: VecDeque::new(),
             host: hostname.clone(),
-            port: port,
-            conn: IBSocket::new(hostname, port as u16),
-            extra_auth: false,
-            client_id: client_id,
+            port,
+            conn: IBSocket::new(hostname, port),
+            client_id,
             server_version: None,
-            asynchronous: false,
             connection_state: ConnectionState::Disconnected,
+            dispatcher: Arc::new(Mutex::new(Dispatcher::new(client_id as i32))),
+            outbound: None,
+            next_request_id: AtomicI32::new(FIRST_REQUEST_ID),
+            timeout: DEFAULT_TIMEOUT,
+            historical_pacer: Mutex::new(HistoricalPacer::default()),
         }
     }
 
-    /// Starts the API layer. Negotiates the server version, etc. 
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn client_id(&self) -> ClientID {
+        self.client_id
+    }
+
+    /// The version negotiated with the server, once connected
+    pub fn server_version(&self) -> Option<usize> {
+        self.server_version
+    }
+
+    /// How long blocking requests wait for the server to answer
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Starts the API layer. Negotiates the server version, etc. Blocks until the server sends the
+    /// first valid order id, which signals that it is ready for requests.
     pub fn start_api(&mut self) -> Result<(), Error> {
-        let mut msg: message::Message = message::Message::new_outbound();
-        let f: u32 = message::OutboundMessages::StartApi{}.into();
-        msg.add_field(IBField::IBInteger(f));
-        msg.add_field(IBField::IBInteger(VERSION));
-        msg.add_field(IBField::IBInteger(self.client_id));
-
-        self.conn.send(&msg)?;
-        match self.conn.receive_once() {
+        let server_version = self.require_server_version()?;
+        self.conn.start()?;
+        self.outbound = Some(self.conn.sender()?);
+        self.start_dispatcher(server_version)?;
+
+        let ready = self.dispatcher()?.register(RouteKey::NextValidId);
+        let mut msg = Message::outbound(OutboundMessages::StartApi);
+        msg.add_field(VERSION);
+        msg.add_field(self.client_id);
+        if server_version >= MIN_SERVER_VER_OPTIONAL_CAPABILITIES {
+            msg.add_field("");
+        }
+        self.send(msg)?;
+
+        let result = ready.recv_timeout(self.timeout);
+        self.dispatcher()?.unregister(RouteKey::NextValidId);
+        match result {
             Ok(r) => {
-                info!("Response bytes are: {:?}", r);
+                info!("API started: {:?}", r);
                 Ok(())
-            },
+            }
             Err(e) => {
                 error!("Error starting API: {:?}", e);
-                Err(e)
+                Err(anyhow!("Timed out waiting for the API to start"))
             }
         }
     }
@@ -74,7 +146,7 @@ impl EClient {
         match self.connection_state {
             ConnectionState::Connected => {
                 return Err(anyhow!("Client is already connected"));
-            },
+            }
             ConnectionState::Connecting => {
                 return Err(anyhow!("Client is currently connecting"));
             }
@@ -84,7 +156,7 @@ impl EClient {
         match self.conn.connect() {
             Ok(r) => {
                 info!("Socket connected: {:?}", r);
-            },
+            }
             Err(e) => {
                 error!("{:?}", e);
                 return Err(e);
@@ -93,37 +165,34 @@ impl EClient {
         self.connection_state = ConnectionState::Connecting;
 
         let header = V100_PREFIX.as_bytes();
-        let conn_message = format!("v{}..{}", MIN_CLIENT_VER, MAX_CLIENT_VER).as_bytes().to_vec();
+        let conn_message = format!("v{}..{}", MIN_CLIENT_VER, MAX_CLIENT_VER)
+            .as_bytes()
+            .to_vec();
         let l = conn_message.len() as u32;
         let l = l.to_be_bytes().to_vec();
 
-        
         info!("Sending connection message: {:?}", conn_message);
-        self.conn.send_raw(&header)?;
+        self.conn.send_raw(header)?;
         self.conn.send_raw(&l)?;
         self.conn.send_raw(&conn_message)?;
-        match self.conn.receive_once() {
+        match self.conn.receive_message() {
             Ok(r) => {
                 info!("Connection response received: {:?}", r);
-                self.connection_state = ConnectionState::Connected;
-                let msg_size: [u8; 4] = r[0..4].try_into()?;
-                let msg_size: u32 = u32::from_be_bytes(msg_size);
-                let msg: Vec<u8> = r[4..msg_size as usize].to_vec();
-
-                let fields = msg.split(|field| {
-                    *field == DELIMITER
-                });
-
+                let msg = message::InboundMessage::from_bytes(&r)?;
                 let mut results: Vec<String> = vec![];
-                for field in fields {
-                    let s_field = String::from_utf8(field.to_vec())?;
+                for field in msg.fields() {
+                    let s_field = field.to_text();
                     debug!("Got field: {:#?}", s_field);
                     results.push(s_field);
                 }
+                if results.is_empty() {
+                    return Err(anyhow!("Empty connection response"));
+                }
 
-                self.server_version = Some(results.remove(0));
+                self.server_version = Some(results.remove(0).parse()?);
+                self.connection_state = ConnectionState::Connected;
                 Ok(())
-            },
+            }
             Err(e) => {
                 error!("Error receiving response from connection attempt: {:?}", e);
                 Err(e)
@@ -131,9 +200,2184 @@ impl EClient {
         }
     }
 
+    /// Decodes everything the socket receives on a background thread and hands it to the dispatcher
+    fn start_dispatcher(&self, server_version: usize) -> Result<(), Error> {
+        let inbound = self.conn.receiver()?;
+        let dispatcher = self.dispatcher.clone();
+        let decoder = Decoder::new(server_version);
+        thread::spawn(move || {
+            for msg in inbound.iter() {
+                let msg = match msg {
+                    Message::Inbound(msg) => msg,
+                    Message::Outbound(_) => continue,
+                };
+                match decoder.decode(&msg) {
+                    Ok(response) => match dispatcher.lock() {
+                        Ok(mut d) => d.dispatch(response),
+                        Err(e) => {
+                            error!("Error locking dispatcher: {}", e);
+                            break;
+                        }
+                    },
+                    Err(e) => {
+                        error!("Error decoding message {:?}: {}", msg.fields().first(), e);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Queues a message to be written to the server
+    fn send(&self, msg: Message) -> Result<(), Error> {
+        match &self.outbound {
+            Some(tx) => match tx.send(msg) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!("Error queueing message: {}", e)),
+            },
+            None => Err(anyhow!("API has not been started")),
+        }
+    }
+
+    fn next_request_id(&self) -> i32 {
+        self.next_request_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Wraps a registered route in a subscription that sends `cancel` when dropped
+    fn subscribe<T>(
+        &self,
+        request_id: i32,
+        responses: Receiver<Response>,
+        cancel: Option<Message>,
+        decode: fn(Response) -> Option<T>,
+        is_end: fn(&T) -> bool,
+    ) -> Result<Subscription<T>, Error> {
+        let outbound = match &self.outbound {
+            Some(tx) => tx.clone(),
+            None => return Err(anyhow!("API has not been started")),
+        };
+        Ok(Subscription::new(
+            request_id,
+            responses,
+            cancel,
+            outbound,
+            self.dispatcher.clone(),
+            decode,
+            is_end,
+        ))
+    }
+
+    fn require_server_version(&self) -> Result<usize, Error> {
+        match self.server_version {
+            Some(v) => Ok(v),
+            None => Err(anyhow!("Client is not connected")),
+        }
+    }
+
+    /// Blocks until another historical data request may be sent without breaking the server's
+    /// pacing limits
+    fn pace_historical_request(&self) -> Result<(), Error> {
+        match self.historical_pacer.lock() {
+            Ok(mut pacer) => {
+                pacer.wait();
+                Ok(())
+            }
+            Err(e) => Err(anyhow!("Error locking historical pacer: {}", e)),
+        }
+    }
+
+    fn dispatcher(&self) -> Result<MutexGuard<'_, Dispatcher>, Error> {
+        match self.dispatcher.lock() {
+            Ok(d) => Ok(d),
+            Err(e) => Err(anyhow!("Error locking dispatcher: {}", e)),
+        }
+    }
+
+    /// The accounts this login can trade, as sent by the server when the API starts
+    pub fn managed_accounts(&self) -> Result<Vec<String>, Error> {
+        Ok(self.dispatcher()?.managed_accounts().to_vec())
+    }
+
+    /// Snapshot of every order seen on this connection
+    pub fn orders(&self) -> Result<Vec<TrackedOrder>, Error> {
+        Ok(self.dispatcher()?.orders().orders())
+    }
+
+    /// Snapshot of one of this client's orders
+    pub fn order(&self, order_id: OrderId) -> Result<Option<TrackedOrder>, Error> {
+        Ok(self.dispatcher()?.orders().get(order_id))
+    }
+
+    /// The perm id the server assigned to an API order id. Use client id 0 for orders placed in
+    /// TWS and bound to the API.
+    pub fn perm_id(&self, client_id: i32, order_id: OrderId) -> Result<Option<PermId>, Error> {
+        Ok(self.dispatcher()?.orders().perm_id(client_id, order_id))
+    }
+
+    /// Returns a channel that receives every subsequent change to the orders seen on this connection
+    pub fn order_events(&self) -> Result<Receiver<OrderEvent>, Error> {
+        Ok(self.dispatcher()?.orders_mut().subscribe())
+    }
+
+    /// Reserves the next unused order id
+    pub fn next_order_id(&self) -> Result<OrderId, Error> {
+        match self.dispatcher()?.take_order_id() {
+            Some(id) => Ok(id),
+            None => Err(anyhow!("The server hasn't sent a valid order id yet")),
+        }
+    }
+
+    /// Places an order. Returns as soon as the order is queued; follow its progress with
+    /// `order_events` or `order`.
+    pub fn place_order(
+        &self,
+        order_id: OrderId,
+        contract: &Contract,
+        order: &Order,
+    ) -> Result<(), Error> {
+        let server_version = self.require_server_version()?;
+        self.dispatcher()?
+            .orders_mut()
+            .track(order_id, contract, order);
+        self.send(order_encoder::encode_place_order(
+            server_version,
+            order_id,
+            contract,
+            order,
+        ))
+    }
+
+    /// Modifies a working order in place by sending it again under the same id. The contract must
+    /// be the one the order was placed for. Blocks until the server reports the new terms, and
+    /// returns the updated order or the error the server rejected the change with.
+    pub fn modify_order(
+        &self,
+        order_id: OrderId,
+        contract: &Contract,
+        order: &Order,
+    ) -> Result<TrackedOrder, Error> {
+        let current = match self.order(order_id)? {
+            Some(o) => o,
+            None => return Err(anyhow!("Order {} is not known to this client", order_id)),
+        };
+        if current.status.is_done() {
+            return Err(anyhow!("Order {} is already {}", order_id, current.status));
+        }
+        if let Some(placed) = &current.contract {
+            if !same_contract(placed, contract) {
+                return Err(anyhow!(
+                    "Order {} was placed for {} {}, not {} {}",
+                    order_id,
+                    placed.security_type,
+                    placed.symbol,
+                    contract.security_type,
+                    contract.symbol
+                ));
+            }
+        }
+
+        let events = self.order_events()?;
+        let errors = self.dispatcher()?.register(RouteKey::Request(order_id));
+        let result = self.place_order(order_id, contract, order).and_then(|_| {
+            self.wait_for_order(order_id, &events, &errors, |o| match o.status {
+                OrderStatus::Filled => Some(Err(anyhow!(
+                    "Order {} filled before the modification took effect",
+                    order_id
+                ))),
+                ref status if status.is_done() => Some(Err(anyhow!(
+                    "Order {} became {} while being modified",
+                    order_id,
+                    status
+                ))),
+                // Wait for an OpenOrder sent after the modification that shows its new terms
+                _ => match &o.order {
+                    Some(echoed)
+                        if o.revision > current.revision
+                            && modification_applied(current.order.as_ref(), order, echoed) =>
+                    {
+                        Some(Ok(o.clone()))
+                    }
+                    _ => None,
+                },
+            })
+        });
+        self.dispatcher()?.unregister(RouteKey::Request(order_id));
+        result
+    }
+
+    /// Cancels an order and blocks until the server confirms it
+    pub fn cancel_order(&self, order_id: OrderId) -> Result<TrackedOrder, Error> {
+        if let Some(current) = self.order(order_id)? {
+            if current.status.is_done() {
+                return Err(anyhow!("Order {} is already {}", order_id, current.status));
+            }
+        }
+        let mut msg = Message::outbound(OutboundMessages::CancelOrder);
+        msg.add_field(1);
+        msg.add_field(order_id);
+
+        let events = self.order_events()?;
+        let errors = self.dispatcher()?.register(RouteKey::Request(order_id));
+        let result = self.send(msg).and_then(|_| {
+            self.wait_for_order(order_id, &events, &errors, |o| match o.status {
+                OrderStatus::Cancelled | OrderStatus::ApiCancelled => Some(Ok(o.clone())),
+                OrderStatus::Filled => Some(Err(anyhow!(
+                    "Order {} filled before it could be cancelled",
+                    order_id
+                ))),
+                _ => None,
+            })
+        });
+        self.dispatcher()?.unregister(RouteKey::Request(order_id));
+        result
+    }
+
+    /// Cancels every open order, including those placed by other clients or in TWS. Blocks until
+    /// the server has handled the request and this client's own orders are no longer working,
+    /// and returns the first error the server reports for it. Orders of other clients are left
+    /// to those clients to follow, since this connection may never hear about them finishing.
+    pub fn global_cancel(&self) -> Result<(), Error> {
+        let events = self.order_events()?;
+        // Errors for our own orders come through the order events; -1 is the request itself
+        let keys = [RouteKey::Request(-1), RouteKey::CurrentTime];
+        let responses = self.dispatcher()?.register_vacant(&keys).ok_or_else(|| {
+            anyhow!("Another request is already waiting on the server's current time")
+        })?;
+
+        let mut msg = Message::outbound(OutboundMessages::ReqGlobalCancel);
+        msg.add_field(1);
+        let result = self
+            .send(msg)
+            .and_then(|_| self.send(req_current_time()))
+            .and_then(|_| self.wait_for_global_cancel(&events, &responses));
+        let mut dispatcher = self.dispatcher()?;
+        for key in keys {
+            dispatcher.unregister(key);
+        }
+        result
+    }
+
+    /// The server answers requests in order, so once the current time asked for after the global
+    /// cancel arrives, the cancel has been handled and any error for it has arrived too
+    fn wait_for_global_cancel(
+        &self,
+        events: &Receiver<OrderEvent>,
+        responses: &Receiver<Response>,
+    ) -> Result<(), Error> {
+        let deadline = Instant::now() + self.timeout;
+        let mut handled = false;
+        loop {
+            if handled && self.working_orders()?.is_empty() {
+                return Ok(());
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            select! {
+                recv(events) -> event => match event? {
+                    // 202 accompanies each cancelled order
+                    OrderEvent::Error { error, .. } if !error.is_warning() && error.code != 202 => {
+                        return Err(error.into());
+                    }
+                    _ => {}
+                },
+                recv(responses) -> response => match response? {
+                    Response::Error(e) if !e.is_warning() && e.code != 202 => {
+                        return Err(e.into());
+                    }
+                    Response::CurrentTime(_) => handled = true,
+                    _ => {}
+                },
+                default(timeout) => {
+                    if !handled {
+                        return Err(anyhow!("Timed out waiting for the global cancel"));
+                    }
+                    let working: Vec<OrderId> =
+                        self.working_orders()?.iter().map(|o| o.order_id).collect();
+                    return Err(anyhow!(
+                        "Timed out waiting for orders {:?} to be cancelled",
+                        working
+                    ));
+                }
+            }
+        }
+    }
+
+    /// This client's orders that the server has acknowledged and that haven't finished yet.
+    /// Orders it never acknowledged have nothing to cancel.
+    fn working_orders(&self) -> Result<Vec<TrackedOrder>, Error> {
+        Ok(self
+            .orders()?
+            .into_iter()
+            .filter(|o| {
+                o.client_id == self.client_id as i32 && o.perm_id != 0 && !o.status.is_done()
+            })
+            .collect())
+    }
+
+    /// Requests this client's open orders and blocks until the server has sent them all. With
+    /// client id 0 this also includes orders placed in TWS.
+    pub fn req_open_orders(&self) -> Result<Vec<OpenOrder>, Error> {
+        self.collect_open_orders(OutboundMessages::ReqOpenOrders)
+    }
+
+    /// Requests the open orders of every client connected to the server, and of TWS
+    pub fn req_all_open_orders(&self) -> Result<Vec<OpenOrder>, Error> {
+        self.collect_open_orders(OutboundMessages::ReqAllOpenOrders)
+    }
+
+    /// Asks the server to send orders placed in TWS as they are created. With `auto_bind` the
+    /// server also assigns them an API order id, which arrives as an `OrderBound` event, so they
+    /// can be modified and cancelled from here. Only allowed for client id 0.
+    pub fn req_auto_open_orders(&self, auto_bind: bool) -> Result<(), Error> {
+        if self.client_id != 0 {
+            return Err(anyhow!(
+                "Automatic open orders are only available to client id 0, not {}",
+                self.client_id
+            ));
+        }
+        let mut msg = Message::outbound(OutboundMessages::ReqAutoOpenOrders);
+        msg.add_field(1);
+        msg.add_field(auto_bind);
+        self.send(msg)
+    }
+
+    /// Sends an open orders request and collects the replies up to `OpenOrderEnd`. Only one such
+    /// request can be outstanding at a time, since the replies carry no request id.
+    fn collect_open_orders(&self, kind: OutboundMessages) -> Result<Vec<OpenOrder>, Error> {
+        let responses = self.dispatcher()?.register(RouteKey::OpenOrders);
+        let mut msg = Message::outbound(kind);
+        msg.add_field(1);
+        let result = self.send(msg).and_then(|_| {
+            let deadline = Instant::now() + self.timeout;
+            let mut orders = vec![];
+            loop {
+                match responses.recv_deadline(deadline) {
+                    Ok(Response::OpenOrder(o)) => orders.push(*o),
+                    Ok(Response::OpenOrderEnd) => return Ok(orders),
+                    Ok(_) => {}
+                    Err(_) => return Err(anyhow!("Timed out waiting for open orders")),
+                }
+            }
+        });
+        self.dispatcher()?.unregister(RouteKey::OpenOrders);
+        result
+    }
+
+    /// Requests the orders that were filled or cancelled today, optionally only those placed
+    /// through the API. Blocks until the server has sent them all.
+    pub fn req_completed_orders(&self, api_only: bool) -> Result<Vec<CompletedOrder>, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_COMPLETED_ORDERS {
+            return Err(anyhow!(
+                "Server version {} does not support completed orders",
+                server_version
+            ));
+        }
+        let responses = self.dispatcher()?.register(RouteKey::CompletedOrders);
+        let mut msg = Message::outbound(OutboundMessages::ReqCompletedOrders);
+        msg.add_field(api_only);
+        let result = self.send(msg).and_then(|_| {
+            let deadline = Instant::now() + self.timeout;
+            let mut orders = vec![];
+            loop {
+                match responses.recv_deadline(deadline) {
+                    Ok(Response::CompletedOrder(o)) => orders.push(*o),
+                    Ok(Response::CompletedOrdersEnd) => return Ok(orders),
+                    Ok(_) => {}
+                    Err(_) => return Err(anyhow!("Timed out waiting for completed orders")),
+                }
+            }
+        });
+        self.dispatcher()?.unregister(RouteKey::CompletedOrders);
+        result
+    }
+
+    /// Requests today's executions matching `filter`, each joined with its commission report.
+    /// Blocks until the server has sent them all.
+    pub fn req_executions(&self, filter: &ExecutionFilter) -> Result<Vec<ExecutionReport>, Error> {
+        let request_id = self.next_request_id();
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let mut msg = Message::outbound(OutboundMessages::ReqExecutions);
+        msg.add_field(3);
+        msg.add_field(request_id);
+        msg.add_field(filter.client_id);
+        msg.add_field(&filter.account_code);
+        msg.add_field(&filter.time);
+        msg.add_field(&filter.symbol);
+        msg.add_field(&filter.security_type);
+        msg.add_field(&filter.exchange);
+        msg.add_field(&filter.side);
+        let result = self.send(msg).and_then(|_| {
+            let deadline = Instant::now() + self.timeout;
+            let mut executions = vec![];
+            loop {
+                match responses.recv_deadline(deadline) {
+                    Ok(Response::ExecutionData(details)) => executions.push(*details),
+                    Ok(Response::ExecutionDataEnd(_)) => return Ok(executions),
+                    Ok(Response::Error(e)) if !e.is_warning() => return Err(e.into()),
+                    Ok(_) => {}
+                    Err(_) => return Err(anyhow!("Timed out waiting for executions")),
+                }
+            }
+        });
+        let mut d = self.dispatcher()?;
+        d.unregister(RouteKey::Request(request_id));
+
+        // The server sends each commission report right after its execution, so by the end of
+        // the executions the dispatcher has seen all of them
+        Ok(result?
+            .into_iter()
+            .map(|details| ExecutionReport {
+                commission_report: d.commission_report(&details.execution.exec_id),
+                contract: details.contract,
+                execution: details.execution,
+            })
+            .collect())
+    }
+
+    /// Switches the kind of data later market data requests receive. Without a subscription for
+    /// an instrument, `Delayed` makes the server fall back to delayed data instead of failing;
+    /// each subscription reports which kind it actually gets with `Tick::MarketDataType`.
+    pub fn req_market_data_type(&self, data_type: MarketDataType) -> Result<(), Error> {
+        let mut msg = Message::outbound(OutboundMessages::ReqMarketDataType);
+        msg.add_field(1);
+        msg.add_field(data_type.id());
+        self.send(msg)
+    }
+
+    /// Subscribes to top-of-book market data. `generic_tick_list` is a comma-separated list of
+    /// extra tick ids, such as "236" for shortable. A `snapshot` sends the current values once and
+    /// ends with `Tick::SnapshotEnd`; a `regulatory_snapshot` is a snapshot for US stocks that
+    /// incurs a fee but needs no market data subscription.
+    pub fn req_mkt_data(
+        &self,
+        contract: &Contract,
+        generic_tick_list: &str,
+        snapshot: bool,
+        regulatory_snapshot: bool,
+    ) -> Result<Subscription<Tick>, Error> {
+        let request_id = self.next_request_id();
+        let msg = self.encode_req_mkt_data(
+            request_id,
+            contract,
+            generic_tick_list,
+            snapshot,
+            regulatory_snapshot,
+        )?;
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel_mkt_data(request_id)),
+            |r| match r {
+                Response::Tick { tick, .. } => Some(tick),
+                _ => None,
+            },
+            |t| *t == Tick::SnapshotEnd,
+        )?;
+        self.send(msg)?;
+        Ok(subscription)
+    }
+
+    /// Subscribes to the implied volatility and greeks of an option, computed from the bid, ask,
+    /// last price and the server's model
+    pub fn req_option_greeks(
+        &self,
+        contract: &Contract,
+        generic_tick_list: &str,
+    ) -> Result<Subscription<OptionGreeks>, Error> {
+        let request_id = self.next_request_id();
+        let msg =
+            self.encode_req_mkt_data(request_id, contract, generic_tick_list, false, false)?;
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel_mkt_data(request_id)),
+            option_computation,
+            |_| false,
+        )?;
+        self.send(msg)?;
+        Ok(subscription)
+    }
+
+    /// Subscribes to market data and merges the ticks into a single quote that is kept up to
+    /// date in the background
+    pub fn req_quote(
+        &self,
+        contract: &Contract,
+        generic_tick_list: &str,
+    ) -> Result<LiveQuote, Error> {
+        let request_id = self.next_request_id();
+        let msg =
+            self.encode_req_mkt_data(request_id, contract, generic_tick_list, false, false)?;
+        let outbound = match &self.outbound {
+            Some(tx) => tx.clone(),
+            None => return Err(anyhow!("API has not been started")),
+        };
+        let (quote, changed) = self.dispatcher()?.quotes_mut().insert(request_id);
+        let live = LiveQuote::new(
+            request_id,
+            quote,
+            changed,
+            cancel_mkt_data(request_id),
+            outbound,
+            self.dispatcher.clone(),
+        );
+        self.send(msg)?;
+        Ok(live)
+    }
+
+    /// Takes a one-off snapshot of an instrument's quote. Returns once the server has sent
+    /// everything, or with whatever arrived when the timeout runs out.
+    pub fn snapshot(&self, contract: &Contract) -> Result<Quote, Error> {
+        let mut subscription = self.req_mkt_data(contract, "", true, false)?;
+        let deadline = Instant::now() + self.timeout;
+        let mut quote = Quote::default();
+        while Instant::now() < deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match subscription.next_timeout(remaining) {
+                Some(Ok(Tick::SnapshotEnd)) | None => break,
+                Some(Ok(tick)) => {
+                    quote.apply(&tick, SystemTime::now());
+                }
+                Some(Err(e)) => return Err(e),
+            }
+        }
+        Ok(quote)
+    }
+
+    /// Snapshots many instruments, keeping at most `max_in_flight` requests outstanding and
+    /// spacing requests out to stay under the server's message rate limit. Results are in the
+    /// same order as `contracts`; each snapshot gets the full timeout from when it was sent.
+    pub fn snapshots(
+        &self,
+        contracts: &[Contract],
+        max_in_flight: usize,
+    ) -> Vec<Result<Quote, Error>> {
+        struct InFlight {
+            index: usize,
+            subscription: Subscription<Tick>,
+            quote: Quote,
+            deadline: Instant,
+        }
+
+        let max_in_flight = max_in_flight.max(1);
+        let mut results: Vec<Option<Result<Quote, Error>>> =
+            contracts.iter().map(|_| None).collect();
+        let mut pending = contracts.iter().enumerate();
+        let mut in_flight: Vec<InFlight> = vec![];
+        let mut last_sent: Option<Instant> = None;
+        let mut exhausted = false;
+
+        while !exhausted || !in_flight.is_empty() {
+            if !exhausted && in_flight.len() < max_in_flight {
+                if let Some(sent) = last_sent {
+                    let next_send = sent + SNAPSHOT_PACING;
+                    let now = Instant::now();
+                    if next_send > now {
+                        thread::sleep(next_send - now);
+                    }
+                }
+                match pending.next() {
+                    Some((index, contract)) => {
+                        last_sent = Some(Instant::now());
+                        match self.req_mkt_data(contract, "", true, false) {
+                            Ok(subscription) => in_flight.push(InFlight {
+                                index,
+                                subscription,
+                                quote: Quote::default(),
+                                deadline: Instant::now() + self.timeout,
+                            }),
+                            Err(e) => results[index] = Some(Err(e)),
+                        }
+                    }
+                    None => exhausted = true,
+                }
+                continue;
+            }
+
+            let mut progressed = false;
+            let mut i = 0;
+            while i < in_flight.len() {
+                let entry = &mut in_flight[i];
+                let mut done = None;
+                while let Some(item) = entry.subscription.try_next() {
+                    progressed = true;
+                    match item {
+                        Ok(Tick::SnapshotEnd) => {
+                            done = Some(Ok(()));
+                            break;
+                        }
+                        Ok(tick) => {
+                            entry.quote.apply(&tick, SystemTime::now());
+                        }
+                        Err(e) => {
+                            done = Some(Err(e));
+                            break;
+                        }
+                    }
+                }
+                if done.is_none() && Instant::now() >= entry.deadline {
+                    done = Some(Ok(()));
+                }
+                match done {
+                    Some(result) => {
+                        let InFlight { index, quote, .. } = in_flight.swap_remove(i);
+                        results[index] = Some(result.map(|_| quote));
+                    }
+                    None => i += 1,
+                }
+            }
+            if !progressed {
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+        results
+            .into_iter()
+            .map(|r| r.unwrap_or_else(|| Err(anyhow!("Snapshot was not taken"))))
+            .collect()
+    }
+
+    /// Subscribes to level 2 market depth, maintaining an order book of up to `num_rows` rows a
+    /// side. Smart depth aggregates the books of every exchange the contract trades on.
+    pub fn req_mkt_depth(
+        &self,
+        contract: &Contract,
+        num_rows: i32,
+        is_smart_depth: bool,
+    ) -> Result<MarketDepth, Error> {
+        let server_version = self.require_server_version()?;
+        if is_smart_depth && server_version < MIN_SERVER_VER_SMART_DEPTH {
+            return Err(anyhow!(
+                "Server version {} does not support smart depth",
+                server_version
+            ));
+        }
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqMarketDepth);
+        msg.add_field(5);
+        msg.add_field(request_id);
+        add_contract_fields(
+            &mut msg,
+            contract,
+            server_version >= MIN_SERVER_VER_MKT_DEPTH_PRIM_EXCHANGE,
+        );
+        msg.add_field(&contract.trading_class);
+        msg.add_field(num_rows);
+        if server_version >= MIN_SERVER_VER_SMART_DEPTH {
+            msg.add_field(is_smart_depth);
+        }
+        // mktDepthOptions, reserved for internal use
+        msg.add_field("");
+
+        let mut cancel = Message::outbound(OutboundMessages::CancelMarketDepth);
+        cancel.add_field(1);
+        cancel.add_field(request_id);
+        if server_version >= MIN_SERVER_VER_SMART_DEPTH {
+            cancel.add_field(is_smart_depth);
+        }
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel),
+            |r| match r {
+                Response::MarketDepth { update, .. } => Some(DepthEvent::Update(update)),
+                Response::MarketDepthReset(_) => Some(DepthEvent::Reset),
+                _ => None,
+            },
+            |_| false,
+        )?;
+        self.send(msg)?;
+        Ok(MarketDepth::new(subscription))
+    }
+
+    /// Lists the exchanges that offer market depth, and whether each is a single-exchange or
+    /// consolidated book
+    pub fn req_mkt_depth_exchanges(&self) -> Result<Vec<DepthMktDataDescription>, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_REQ_MKT_DEPTH_EXCHANGES {
+            return Err(anyhow!(
+                "Server version {} does not support market depth exchanges",
+                server_version
+            ));
+        }
+        let responses = self.dispatcher()?.register(RouteKey::MarketDepthExchanges);
+        let result = self
+            .send(Message::outbound(OutboundMessages::ReqMarketDepthExchanges))
+            .and_then(|_| match responses.recv_timeout(self.timeout) {
+                Ok(Response::MarketDepthExchanges(descriptions)) => Ok(descriptions),
+                Ok(other) => Err(anyhow!("Unexpected response: {:?}", other)),
+                Err(_) => Err(anyhow!("Timed out waiting for market depth exchanges")),
+            });
+        self.dispatcher()?
+            .unregister(RouteKey::MarketDepthExchanges);
+        result
+    }
+
+    /// Subscribes to every trade, quote change or midpoint change as it happens. With
+    /// `number_of_ticks` above 0 the server first sends that many historical ticks. `ignore_size`
+    /// skips bid/ask ticks where only the size changed.
+    pub fn req_tick_by_tick_data(
+        &self,
+        contract: &Contract,
+        tick_type: TickByTickType,
+        number_of_ticks: i32,
+        ignore_size: bool,
+    ) -> Result<Subscription<TickByTick>, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_TICK_BY_TICK {
+            return Err(anyhow!(
+                "Server version {} does not support tick-by-tick data",
+                server_version
+            ));
+        }
+        if (number_of_ticks != 0 || ignore_size)
+            && server_version < MIN_SERVER_VER_TICK_BY_TICK_IGNORE_SIZE
+        {
+            return Err(anyhow!(
+                "Server version {} does not support historical ticks or ignoring size with tick-by-tick data",
+                server_version
+            ));
+        }
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqTickByTickData);
+        msg.add_field(request_id);
+        add_contract_fields(&mut msg, contract, true);
+        msg.add_field(&contract.trading_class);
+        msg.add_field(tick_type.as_str());
+        if server_version >= MIN_SERVER_VER_TICK_BY_TICK_IGNORE_SIZE {
+            msg.add_field(number_of_ticks);
+            msg.add_field(ignore_size);
+        }
+
+        let mut cancel = Message::outbound(OutboundMessages::CancelTickByTickData);
+        cancel.add_field(request_id);
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel),
+            |r| match r {
+                Response::TickByTick { tick, .. } => Some(tick),
+                _ => None,
+            },
+            |_| false,
+        )?;
+        self.send(msg)?;
+        Ok(subscription)
+    }
+
+    /// Subscribes to 5 second bars of trades, midpoints, bids or asks. `use_rth` limits the bars
+    /// to regular trading hours.
+    pub fn req_real_time_bars(
+        &self,
+        contract: &Contract,
+        what_to_show: WhatToShow,
+        use_rth: bool,
+        options: &[TagValue],
+    ) -> Result<Subscription<Bar>, Error> {
+        let server_version = self.require_server_version()?;
+        if !matches!(
+            what_to_show,
+            WhatToShow::Trades | WhatToShow::Midpoint | WhatToShow::Bid | WhatToShow::Ask
+        ) {
+            return Err(anyhow!(
+                "Real-time bars can't show {}; only TRADES, MIDPOINT, BID and ASK are supported",
+                what_to_show
+            ));
+        }
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqRealTimeBars);
+        msg.add_field(3);
+        msg.add_field(request_id);
+        add_contract_fields(&mut msg, contract, true);
+        msg.add_field(&contract.trading_class);
+        // the only bar size the server supports
+        msg.add_field(5);
+        msg.add_field(what_to_show.as_str());
+        msg.add_field(use_rth);
+        if server_version >= MIN_SERVER_VER_LINKING {
+            msg.add_field(encode_tag_values(options));
+        }
+
+        let mut cancel = Message::outbound(OutboundMessages::CancelRealTimeBars);
+        cancel.add_field(1);
+        cancel.add_field(request_id);
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel),
+            |r| match r {
+                Response::RealTimeBar { bar, .. } => Some(bar),
+                _ => None,
+            },
+            |_| false,
+        )?;
+        self.send(msg)?;
+        Ok(subscription)
+    }
+
+    /// Subscribes to the values and positions of an account. Only one account can be subscribed
+    /// to at a time; subscribing to another replaces the previous subscription. The account may
+    /// be empty when only one account is managed.
+    pub fn subscribe_account_updates(&self, account: &str) -> Result<AccountUpdates, Error> {
+        self.require_server_version()?;
+        let mut msg = Message::outbound(OutboundMessages::ReqAccountData);
+        msg.add_field(2);
+        msg.add_field(true);
+        msg.add_field(account);
+
+        let mut cancel = Message::outbound(OutboundMessages::ReqAccountData);
+        cancel.add_field(2);
+        cancel.add_field(false);
+        cancel.add_field(account);
+
+        let outbound = match &self.outbound {
+            Some(tx) => tx.clone(),
+            None => return Err(anyhow!("API has not been started")),
+        };
+        let subscription = Subscription::for_route(
+            RouteKey::AccountUpdates,
+            Some(cancel),
+            outbound,
+            self.dispatcher.clone(),
+            account_event,
+            |_| false,
+        )?;
+        self.send(msg)?;
+        Ok(AccountUpdates::new(subscription, account, ""))
+    }
+
+    /// Subscribes to the values of one account and model. Unlike `subscribe_account_updates`,
+    /// any number of these can be open at once. With `ledger_and_nlv` set, only the ledger
+    /// values and net liquidation value are sent. Portfolio positions aren't part of these
+    /// updates; see `req_positions_multi`.
+    pub fn subscribe_account_updates_multi(
+        &self,
+        account: &str,
+        model_code: &str,
+        ledger_and_nlv: bool,
+    ) -> Result<AccountUpdates, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_MODELS_SUPPORT {
+            return Err(anyhow!(
+                "Server version {} does not support account updates by model",
+                server_version
+            ));
+        }
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqAccountUpdatesMulti);
+        msg.add_field(1);
+        msg.add_field(request_id);
+        msg.add_field(account);
+        msg.add_field(model_code);
+        msg.add_field(ledger_and_nlv);
+
+        let mut cancel = Message::outbound(OutboundMessages::CancelAccountUpdatesMulti);
+        cancel.add_field(1);
+        cancel.add_field(request_id);
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel),
+            account_multi_event,
+            |_| false,
+        )?;
+        self.send(msg)?;
+        Ok(AccountUpdates::new(subscription, account, model_code))
+    }
+
+    /// Summarizes the accounts in `group`, which is `All` or the name of an advisor group, and
+    /// waits for the values of every account. The accounts are in the order the server sent them.
+    pub fn req_account_summary(
+        &self,
+        group: &str,
+        tags: &[AccountSummaryTag],
+    ) -> Result<Vec<AccountSummary>, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_ACCOUNT_SUMMARY {
+            return Err(anyhow!(
+                "Server version {} does not support account summaries",
+                server_version
+            ));
+        }
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqAccountSummary);
+        msg.add_field(1);
+        msg.add_field(request_id);
+        msg.add_field(group);
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        msg.add_field(tags.join(","));
+
+        let mut cancel = Message::outbound(OutboundMessages::CancelAccountSummary);
+        cancel.add_field(1);
+        cancel.add_field(request_id);
+
+        // The server keeps sending changes after the end message until the request is
+        // cancelled, which dropping the subscription does
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let mut subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel),
+            |r| match r {
+                Response::AccountSummary { account, value, .. } => Some(Some((account, value))),
+                Response::AccountSummaryEnd(_) => Some(None),
+                _ => None,
+            },
+            |_| false,
+        )?;
+        self.send(msg)?;
+
+        let mut summaries: Vec<AccountSummary> = vec![];
+        loop {
+            match subscription.next_timeout(self.timeout) {
+                Some(Ok(Some((account, value)))) => {
+                    match summaries.iter_mut().find(|s| s.account == account) {
+                        Some(summary) => summary.values.push(value),
+                        None => summaries.push(AccountSummary {
+                            account,
+                            values: vec![value],
+                        }),
+                    }
+                }
+                Some(Ok(None)) => return Ok(summaries),
+                Some(Err(e)) => return Err(e),
+                None => {
+                    return Err(anyhow!(
+                        "Timed out waiting for account summary for request {}",
+                        request_id
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Subscribes to the positions in every account this client can see. Only one such
+    /// subscription can be open at a time, and a new one takes over from the previous one; use
+    /// `wait_for_initial` on the result to get the current positions before following updates.
+    pub fn req_positions(&self) -> Result<Positions, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_POSITIONS {
+            return Err(anyhow!(
+                "Server version {} does not support positions",
+                server_version
+            ));
+        }
+        let mut msg = Message::outbound(OutboundMessages::ReqPositions);
+        msg.add_field(1);
+        let mut cancel = Message::outbound(OutboundMessages::CancelPositions);
+        cancel.add_field(1);
+
+        let outbound = match &self.outbound {
+            Some(tx) => tx.clone(),
+            None => return Err(anyhow!("API has not been started")),
+        };
+        let subscription = Subscription::for_route(
+            RouteKey::Positions,
+            Some(cancel),
+            outbound,
+            self.dispatcher.clone(),
+            position_event,
+            |_| false,
+        )?;
+        self.send(msg)?;
+        Ok(Positions::new(subscription))
+    }
+
+    /// Subscribes to the positions in one account and model. An empty account means every
+    /// account, and an empty model code means positions not held by any model.
+    pub fn req_positions_multi(&self, account: &str, model_code: &str) -> Result<Positions, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_MODELS_SUPPORT {
+            return Err(anyhow!(
+                "Server version {} does not support positions by model",
+                server_version
+            ));
+        }
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqPositionsMulti);
+        msg.add_field(1);
+        msg.add_field(request_id);
+        msg.add_field(account);
+        msg.add_field(model_code);
+
+        let mut cancel = Message::outbound(OutboundMessages::CancelPositionsMulti);
+        cancel.add_field(1);
+        cancel.add_field(request_id);
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel),
+            |r| match r {
+                Response::PositionMulti { position, .. } => Some(Some(*position)),
+                Response::PositionMultiEnd(_) => Some(None),
+                _ => None,
+            },
+            |_| false,
+        )?;
+        self.send(msg)?;
+        Ok(Positions::new(subscription))
+    }
+
+    /// Requests historical bars and waits for them, oldest first. Like every historical request,
+    /// it first waits if sending it now would break the server's pacing limits.
+    pub fn req_historical_data(
+        &self,
+        contract: &Contract,
+        request: &HistoricalDataRequest,
+    ) -> Result<Vec<Bar>, Error> {
+        let mut subscription = self.subscribe_historical_data(contract, request, false)?;
+        match subscription.next_timeout(self.timeout) {
+            Some(Ok(BarEvent::Bars(bars))) => Ok(bars),
+            Some(Ok(BarEvent::Update(_))) => Err(anyhow!("Unexpected historical data update")),
+            Some(Err(e)) => Err(e),
+            None => Err(anyhow!(
+                "Timed out waiting for historical data for request {}",
+                subscription.request_id()
+            )),
+        }
+    }
+
+    /// Requests historical bars up to now and keeps the last bar up to date as new data arrives.
+    /// Waits for the initial bars before returning.
+    pub fn req_historical_data_keep_up_to_date(
+        &self,
+        contract: &Contract,
+        request: &HistoricalDataRequest,
+    ) -> Result<HistoricalBars, Error> {
+        if !request.end_date_time.is_empty() {
+            return Err(anyhow!(
+                "Historical data can only be kept up to date when the end time is now"
+            ));
+        }
+        if self.require_server_version()? < MIN_SERVER_VER_SYNT_REALTIME_BARS {
+            return Err(anyhow!(
+                "Server version does not support keeping historical data up to date"
+            ));
+        }
+        let mut subscription = self.subscribe_historical_data(contract, request, true)?;
+        let deadline = Instant::now() + self.timeout;
+        let mut updates = vec![];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match subscription.next_timeout(remaining) {
+                Some(Ok(BarEvent::Bars(bars))) => {
+                    let mut history = HistoricalBars::new(subscription, bars);
+                    for bar in updates {
+                        history.apply(BarEvent::Update(bar));
+                    }
+                    return Ok(history);
+                }
+                Some(Ok(BarEvent::Update(bar))) => updates.push(bar),
+                Some(Err(e)) => return Err(e),
+                None => {
+                    return Err(anyhow!(
+                        "Timed out waiting for historical data for request {}",
+                        subscription.request_id()
+                    ))
+                }
+            }
+        }
+    }
+
+    /// The time of the earliest data available for a contract
+    pub fn req_head_timestamp(
+        &self,
+        contract: &Contract,
+        what_to_show: WhatToShow,
+        use_rth: bool,
+    ) -> Result<SystemTime, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_REQ_HEAD_TIMESTAMP {
+            return Err(anyhow!(
+                "Server version {} does not support head timestamps",
+                server_version
+            ));
+        }
+        self.pace_historical_request()?;
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqHeadTimestamp);
+        msg.add_field(request_id);
+        add_contract_fields(&mut msg, contract, true);
+        msg.add_field(&contract.trading_class);
+        msg.add_field(contract.include_expired);
+        msg.add_field(use_rth);
+        msg.add_field(what_to_show.as_str());
+        msg.add_field(DateFormat::Epoch.id());
+
+        let cancel = if server_version >= MIN_SERVER_VER_CANCEL_HEADTIMESTAMP {
+            let mut cancel = Message::outbound(OutboundMessages::CancelHeadTimestamp);
+            cancel.add_field(request_id);
+            Some(cancel)
+        } else {
+            None
+        };
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let mut subscription = self.subscribe(
+            request_id,
+            responses,
+            cancel,
+            |r| match r {
+                Response::HeadTimestamp { timestamp, .. } => Some(timestamp),
+                _ => None,
+            },
+            |_| true,
+        )?;
+        self.send(msg)?;
+        match subscription.next_timeout(self.timeout) {
+            Some(result) => {
+                let seconds = backfill::parse_bar_time(&result?)?;
+                Ok(UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64))
+            }
+            None => Err(anyhow!(
+                "Timed out waiting for head timestamp for request {}",
+                request_id
+            )),
+        }
+    }
+
+    /// How much has traded at each price over a period such as `3 days` or `1 week`
+    pub fn req_histogram_data(
+        &self,
+        contract: &Contract,
+        use_rth: bool,
+        period: &str,
+    ) -> Result<Vec<HistogramEntry>, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_REQ_HISTOGRAM {
+            return Err(anyhow!(
+                "Server version {} does not support histogram data",
+                server_version
+            ));
+        }
+        self.pace_historical_request()?;
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqHistogramData);
+        msg.add_field(request_id);
+        add_contract_fields(&mut msg, contract, true);
+        msg.add_field(&contract.trading_class);
+        msg.add_field(contract.include_expired);
+        msg.add_field(use_rth);
+        msg.add_field(period);
+
+        let mut cancel = Message::outbound(OutboundMessages::CancelHistogramData);
+        cancel.add_field(request_id);
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let mut subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel),
+            |r| match r {
+                Response::HistogramData { entries, .. } => Some(entries),
+                _ => None,
+            },
+            |_| true,
+        )?;
+        self.send(msg)?;
+        match subscription.next_timeout(self.timeout) {
+            Some(result) => result,
+            None => Err(anyhow!(
+                "Timed out waiting for histogram data for request {}",
+                request_id
+            )),
+        }
+    }
+
+    /// Downloads bars over a range longer than one request may cover. Walks backwards from the
+    /// end time one chunk at a time, stopping at the start time or the earliest data available,
+    /// and returns one series with overlapping bars removed, oldest first. Requests are spaced
+    /// out to stay within the server's historical data pacing limits, so long ranges of small
+    /// bars take a while.
+    pub fn backfill_historical_data(
+        &self,
+        contract: &Contract,
+        request: &BackfillRequest,
+    ) -> Result<Vec<Bar>, Error> {
+        let head = self.req_head_timestamp(contract, request.what_to_show, request.use_rth)?;
+        let start = backfill::epoch_seconds(request.start.max(head))?;
+        let end = backfill::epoch_seconds(request.end)?;
+        let (duration, step) = backfill::chunk(request.bar_size);
+
+        let mut series = BTreeMap::new();
+        let mut cursor = end;
+        let mut retries = 0;
+        while cursor > start {
+            let chunk = HistoricalDataRequest {
+                end_date_time: backfill::format_utc_date_time(cursor),
+                duration,
+                bar_size: request.bar_size,
+                what_to_show: request.what_to_show,
+                use_rth: request.use_rth,
+                format_date: DateFormat::Epoch,
+            };
+            let bars = match self.req_historical_data(contract, &chunk) {
+                Ok(bars) => bars,
+                Err(e) if backfill::is_no_data(&e) => vec![],
+                Err(e)
+                    if backfill::is_pacing_violation(&e)
+                        && retries < backfill::PACING_VIOLATION_RETRIES =>
+                {
+                    warn!(
+                        "{}; retrying in {:?}",
+                        e,
+                        backfill::PACING_VIOLATION_BACKOFF
+                    );
+                    retries += 1;
+                    thread::sleep(backfill::PACING_VIOLATION_BACKOFF);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            retries = 0;
+            cursor = match backfill::merge_bars(&mut series, bars, start, end)? {
+                Some(earliest) if earliest < cursor => earliest,
+                _ => cursor - step,
+            };
+        }
+        Ok(series.into_values().collect())
+    }
+
+    /// Downloads up to `count` historical ticks, starting at `start` or ending at `end`, one of
+    /// which should be empty. Times are `yyyymmdd hh:mm:ss` followed by an optional time zone.
+    /// The server may return a few more than `count` to finish the last second.
+    #[allow(clippy::too_many_arguments)]
+    pub fn req_historical_ticks(
+        &self,
+        contract: &Contract,
+        start: &str,
+        end: &str,
+        count: i32,
+        what_to_show: HistoricalTickType,
+        use_rth: bool,
+        ignore_size: bool,
+    ) -> Result<HistoricalTicks, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_HISTORICAL_TICKS {
+            return Err(anyhow!(
+                "Server version {} does not support historical ticks",
+                server_version
+            ));
+        }
+        self.pace_historical_request()?;
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqHistoricalTicks);
+        msg.add_field(request_id);
+        add_contract_fields(&mut msg, contract, true);
+        msg.add_field(&contract.trading_class);
+        msg.add_field(contract.include_expired);
+        msg.add_field(start);
+        msg.add_field(end);
+        msg.add_field(count);
+        msg.add_field(what_to_show.as_str());
+        msg.add_field(use_rth);
+        msg.add_field(ignore_size);
+        // miscOptions, reserved for internal use
+        msg.add_field("");
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let mut subscription = self.subscribe(
+            request_id,
+            responses,
+            None,
+            |r| match r {
+                Response::HistoricalTicks { ticks, done, .. } => Some((ticks, done)),
+                _ => None,
+            },
+            |(_, done)| *done,
+        )?;
+        self.send(msg)?;
+
+        let mut result = HistoricalTicks::empty(what_to_show);
+        loop {
+            match subscription.next_timeout(self.timeout) {
+                Some(Ok((ticks, done))) => {
+                    result.extend(ticks)?;
+                    if done {
+                        return Ok(result);
+                    }
+                }
+                Some(Err(e)) => return Err(e),
+                None => {
+                    return Err(anyhow!(
+                        "Timed out waiting for historical ticks for request {}",
+                        request_id
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Downloads every historical tick between two times, such as a whole trading day, a page at
+    /// a time. Requests are spaced out to stay within the server's historical data pacing limits.
+    /// Fails rather than leave a gap if a single second holds more ticks than fit in a page.
+    pub fn req_historical_ticks_between(
+        &self,
+        contract: &Contract,
+        start: SystemTime,
+        end: SystemTime,
+        what_to_show: HistoricalTickType,
+        use_rth: bool,
+        ignore_size: bool,
+    ) -> Result<HistoricalTicks, Error> {
+        let mut cursor = backfill::epoch_seconds(start)?;
+        let end = backfill::epoch_seconds(end)?;
+        let mut result = HistoricalTicks::empty(what_to_show);
+        while cursor <= end {
+            let page = self.req_historical_ticks(
+                contract,
+                &backfill::format_utc_date_time(cursor),
+                "",
+                HISTORICAL_TICKS_PAGE,
+                what_to_show,
+                use_rth,
+                ignore_size,
+            )?;
+            match result.append_page(page, cursor, end, HISTORICAL_TICKS_PAGE as usize)? {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+        Ok(result)
+    }
+
+    fn subscribe_historical_data(
+        &self,
+        contract: &Contract,
+        request: &HistoricalDataRequest,
+        keep_up_to_date: bool,
+    ) -> Result<Subscription<BarEvent>, Error> {
+        let server_version = self.require_server_version()?;
+        self.pace_historical_request()?;
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqHistoricalData);
+        if server_version < MIN_SERVER_VER_SYNT_REALTIME_BARS {
+            msg.add_field(6);
+        }
+        msg.add_field(request_id);
+        add_contract_fields(&mut msg, contract, true);
+        msg.add_field(&contract.trading_class);
+        msg.add_field(contract.include_expired);
+        msg.add_field(&request.end_date_time);
+        msg.add_field(request.bar_size.as_str());
+        msg.add_field(request.duration.to_string());
+        msg.add_field(request.use_rth);
+        msg.add_field(request.what_to_show.as_str());
+        msg.add_field(request.format_date.id());
+        if contract.security_type == SecurityType::Combo {
+            msg.add_field(contract.combo_legs.len());
+            for leg in &contract.combo_legs {
+                msg.add_field(leg.contract_id);
+                msg.add_field(leg.ratio);
+                msg.add_field(&leg.action);
+                msg.add_field(&leg.exchange);
+            }
+        }
+        if server_version >= MIN_SERVER_VER_SYNT_REALTIME_BARS {
+            msg.add_field(keep_up_to_date);
+        }
+        // chartOptions, reserved for internal use
+        msg.add_field("");
+
+        let mut cancel = Message::outbound(OutboundMessages::CancelHistoricalData);
+        cancel.add_field(1);
+        cancel.add_field(request_id);
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = if keep_up_to_date {
+            self.subscribe(
+                request_id,
+                responses,
+                Some(cancel),
+                |r| match r {
+                    Response::HistoricalData { bars, .. } => Some(BarEvent::Bars(bars)),
+                    Response::HistoricalDataUpdate { bar, .. } => Some(BarEvent::Update(bar)),
+                    _ => None,
+                },
+                |_| false,
+            )?
+        } else {
+            self.subscribe(
+                request_id,
+                responses,
+                Some(cancel),
+                |r| match r {
+                    Response::HistoricalData { bars, .. } => Some(BarEvent::Bars(bars)),
+                    _ => None,
+                },
+                |_| true,
+            )?
+        };
+        self.send(msg)?;
+        Ok(subscription)
+    }
+
+    /// Lists the expirations and strikes of the options on an underlying, per exchange and
+    /// trading class. `fut_fop_exchange` is the exchange of options on futures, and empty for
+    /// options on stocks and indices.
+    pub fn req_sec_def_opt_params(
+        &self,
+        underlying: &Contract,
+        fut_fop_exchange: &str,
+    ) -> Result<Vec<OptionParameters>, Error> {
+        let server_version = self.require_server_version()?;
+        if server_version < MIN_SERVER_VER_SEC_DEF_OPT_PARAMS_REQ {
+            return Err(anyhow!(
+                "Server version {} does not support option parameters",
+                server_version
+            ));
+        }
+        let request_id = self.next_request_id();
+        let mut msg = Message::outbound(OutboundMessages::ReqSecDefOptParams);
+        msg.add_field(request_id);
+        msg.add_field(&underlying.symbol);
+        msg.add_field(fut_fop_exchange);
+        msg.add_field(underlying.security_type.as_str());
+        msg.add_field(underlying.contract_id);
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let mut subscription = self.subscribe(
+            request_id,
+            responses,
+            None,
+            |r| match r {
+                Response::SecurityDefinitionOptionParameter { parameters, .. } => {
+                    Some(Some(parameters))
+                }
+                Response::SecurityDefinitionOptionParameterEnd(_) => Some(None),
+                _ => None,
+            },
+            |p| p.is_none(),
+        )?;
+        self.send(msg)?;
+
+        let mut result = vec![];
+        loop {
+            match subscription.next_timeout(self.timeout) {
+                Some(Ok(Some(parameters))) => result.push(parameters),
+                Some(Ok(None)) => return Ok(result),
+                Some(Err(e)) => return Err(e),
+                None => {
+                    return Err(anyhow!(
+                        "Timed out waiting for option parameters for request {}",
+                        request_id
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Builds the option contracts on an underlying that pass a filter, ready for market data
+    /// requests. The underlying needs its contract id. Options on futures also need
+    /// `fut_fop_exchange`; see `req_sec_def_opt_params`.
+    pub fn option_chain(
+        &self,
+        underlying: &Contract,
+        fut_fop_exchange: &str,
+        filter: &ChainFilter,
+    ) -> Result<Vec<Contract>, Error> {
+        let exchange = filter.exchange();
+        let mut contracts = vec![];
+        for parameters in self.req_sec_def_opt_params(underlying, fut_fop_exchange)? {
+            if parameters.exchange == exchange {
+                contracts.extend(parameters.contracts(underlying, filter));
+            }
+        }
+        Ok(contracts)
+    }
+
+    /// Exercises or lapses `quantity` contracts of an option position in `account`. With
+    /// `override_` set the server does not apply its own exercise rules, such as automatically
+    /// exercising in-the-money options at expiry. The exchange must be set on the contract.
+    ///
+    /// Blocks until the server has handled the request, and returns the error it rejected the
+    /// request with, if any. The order the server creates for the exercise carries no trace of
+    /// this request, so it isn't returned; it shows up in `order_events` and `orders` like any
+    /// other order placed outside this client.
+    pub fn exercise_options(
+        &self,
+        contract: &Contract,
+        action: ExerciseAction,
+        quantity: i32,
+        account: &str,
+        override_: bool,
+    ) -> Result<(), Error> {
+        let request_id = self.next_request_id();
+        let msg =
+            encode_exercise_options(request_id, contract, action, quantity, account, override_)?;
+        self.require_server_version()?;
+
+        let keys = [RouteKey::Request(request_id), RouteKey::CurrentTime];
+        let responses = self.dispatcher()?.register_vacant(&keys).ok_or_else(|| {
+            anyhow!("Another request is already waiting on the server's current time")
+        })?;
+        let result = self
+            .send(msg)
+            .and_then(|_| self.send(req_current_time()))
+            .and_then(|_| {
+                // The server answers requests in order, so any error for the exercise arrives
+                // before the current time asked for after it
+                let deadline = Instant::now() + self.timeout;
+                loop {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    select! {
+                        recv(responses) -> response => match response? {
+                            Response::Error(e) if !e.is_warning() => return Err(e.into()),
+                            Response::CurrentTime(_) => return Ok(()),
+                            _ => {}
+                        },
+                        default(timeout) => {
+                            return Err(anyhow!("Timed out waiting for the exercise request"));
+                        }
+                    }
+                }
+            });
+        let mut dispatcher = self.dispatcher()?;
+        for key in keys {
+            dispatcher.unregister(key);
+        }
+        result
+    }
+
+    /// Asks the server's model for the volatility implied by an option price. The result is a
+    /// custom option computation.
+    pub fn calc_implied_volatility(
+        &self,
+        contract: &Contract,
+        option_price: f64,
+        underlying_price: f64,
+    ) -> Result<OptionCalculation, Error> {
+        self.calc_option(
+            contract,
+            OutboundMessages::ReqCalcImpliedVolat,
+            OutboundMessages::CancelCalcImpliedVolat,
+            option_price,
+            underlying_price,
+        )
+    }
+
+    /// Asks the server's model for the price and greeks of an option at a given volatility
+    pub fn calc_option_price(
+        &self,
+        contract: &Contract,
+        volatility: f64,
+        underlying_price: f64,
+    ) -> Result<OptionCalculation, Error> {
+        self.calc_option(
+            contract,
+            OutboundMessages::ReqCalcOptionPrice,
+            OutboundMessages::CancelCalcOptionPrice,
+            volatility,
+            underlying_price,
+        )
+    }
+
+    fn calc_option(
+        &self,
+        contract: &Contract,
+        request: OutboundMessages,
+        cancel_request: OutboundMessages,
+        value: f64,
+        underlying_price: f64,
+    ) -> Result<OptionCalculation, Error> {
+        self.require_server_version()?;
+        let request_id = self.next_request_id();
+        let msg = encode_calc_option(request, request_id, contract, value, underlying_price);
+
+        let mut cancel = Message::outbound(cancel_request);
+        cancel.add_field(1);
+        cancel.add_field(request_id);
+
+        let responses = self.dispatcher()?.register(RouteKey::Request(request_id));
+        let subscription = self.subscribe(
+            request_id,
+            responses,
+            Some(cancel),
+            option_computation,
+            |_| true,
+        )?;
+        self.send(msg)?;
+        Ok(OptionCalculation::new(subscription))
+    }
+
+    fn encode_req_mkt_data(
+        &self,
+        request_id: i32,
+        contract: &Contract,
+        generic_tick_list: &str,
+        snapshot: bool,
+        regulatory_snapshot: bool,
+    ) -> Result<Message, Error> {
+        let server_version = self.require_server_version()?;
+        let mut msg = Message::outbound(OutboundMessages::ReqMarketData);
+        msg.add_field(11);
+        msg.add_field(request_id);
+        add_contract_fields(&mut msg, contract, true);
+        msg.add_field(&contract.trading_class);
+        if contract.security_type == SecurityType::Combo {
+            msg.add_field(contract.combo_legs.len());
+            for leg in &contract.combo_legs {
+                msg.add_field(leg.contract_id);
+                msg.add_field(leg.ratio);
+                msg.add_field(&leg.action);
+                msg.add_field(&leg.exchange);
+            }
+        }
+        match &contract.delta_neutral_contract {
+            Some(dn) => {
+                msg.add_field(true);
+                msg.add_field(dn.contract_id);
+                msg.add_field(dn.delta);
+                msg.add_field(dn.price);
+            }
+            None => msg.add_field(false),
+        }
+        msg.add_field(generic_tick_list);
+        msg.add_field(snapshot);
+        if server_version >= MIN_SERVER_VER_REQ_SMART_COMPONENTS {
+            msg.add_field(regulatory_snapshot);
+        }
+        // mktDataOptions, reserved for internal use
+        msg.add_field("");
+        Ok(msg)
+    }
+
+    /// Waits for an event about `order_id` that `done` turns into a result, or for the server to
+    /// reject the request with an error
+    fn wait_for_order<F>(
+        &self,
+        order_id: OrderId,
+        events: &Receiver<OrderEvent>,
+        errors: &Receiver<Response>,
+        done: F,
+    ) -> Result<TrackedOrder, Error>
+    where
+        F: Fn(&TrackedOrder) -> Option<Result<TrackedOrder, Error>>,
+    {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            select! {
+                recv(events) -> event => match event? {
+                    OrderEvent::New(o) | OrderEvent::Updated(o) | OrderEvent::StatusChanged { order: o, .. }
+                        if o.order_id == order_id && o.client_id == self.client_id as i32 =>
+                    {
+                        if let Some(result) = done(&o) {
+                            return result;
+                        }
+                    }
+                    _ => {}
+                },
+                recv(errors) -> response => {
+                    // 202 accompanies a successful cancel
+                    if let Response::Error(e) = response? {
+                        if !e.is_warning() && e.code != 202 {
+                            return Err(e.into());
+                        }
+                    }
+                },
+                default(timeout) => {
+                    return Err(anyhow!("Timed out waiting for order {}", order_id));
+                }
+            }
+        }
+    }
 }
+
+fn encode_exercise_options(
+    request_id: i32,
+    contract: &Contract,
+    action: ExerciseAction,
+    quantity: i32,
+    account: &str,
+    override_: bool,
+) -> Result<Message, Error> {
+    match contract.security_type {
+        SecurityType::Option | SecurityType::FutureOption | SecurityType::Warrant => {}
+        ref other => return Err(anyhow!("Cannot exercise a {} contract", other)),
+    }
+    if quantity <= 0 {
+        return Err(anyhow!(
+            "Exercise quantity must be positive, not {}",
+            quantity
+        ));
+    }
+    if contract.exchange.is_empty() {
+        return Err(anyhow!("Exercise requests need the contract's exchange"));
+    }
+    let mut msg = Message::outbound(OutboundMessages::ExerciseOptions);
+    msg.add_field(2);
+    msg.add_field(request_id);
+    add_contract_fields(&mut msg, contract, false);
+    msg.add_field(&contract.trading_class);
+    msg.add_field(action.id());
+    msg.add_field(quantity);
+    msg.add_field(account);
+    msg.add_field(override_);
+    Ok(msg)
+}
+
+/// Whether a newly seen order is the one the server created for an exercise request: same
+/// contract, same quantity, and in the requested account when one was given
+/// Both option calculations send the same fields; `value` is the option price or the volatility
+fn encode_calc_option(
+    request: OutboundMessages,
+    request_id: i32,
+    contract: &Contract,
+    value: f64,
+    underlying_price: f64,
+) -> Message {
+    let mut msg = Message::outbound(request);
+    msg.add_field(3);
+    msg.add_field(request_id);
+    add_contract_fields(&mut msg, contract, true);
+    msg.add_field(&contract.trading_class);
+    msg.add_field(value);
+    msg.add_field(underlying_price);
+    // options, reserved for internal use: a count and the tag=value list
+    msg.add_field(0);
+    msg.add_field("");
+    msg
+}
+
+fn account_event(response: Response) -> Option<AccountEvent> {
+    match response {
+        Response::AccountValue(v) => Some(AccountEvent::Value(v)),
+        Response::PortfolioValue(item) => Some(AccountEvent::Portfolio(item)),
+        Response::AccountUpdateTime(t) => Some(AccountEvent::UpdateTime(t)),
+        Response::AccountDownloadEnd(account) => Some(AccountEvent::DownloadEnd {
+            account,
+            model_code: String::new(),
+        }),
+        _ => None,
+    }
+}
+
+fn account_multi_event(response: Response) -> Option<AccountEvent> {
+    match response {
+        Response::AccountUpdateMulti { value, .. } => Some(AccountEvent::Value(value)),
+        // The handle fills in the account and model it asked for
+        Response::AccountUpdateMultiEnd(_) => Some(AccountEvent::DownloadEnd {
+            account: String::new(),
+            model_code: String::new(),
+        }),
+        _ => None,
+    }
+}
+
+fn position_event(response: Response) -> Option<PositionEvent> {
+    match response {
+        Response::Position(position) => Some(Some(*position)),
+        Response::PositionEnd => Some(None),
+        _ => None,
+    }
+}
+
+fn option_computation(response: Response) -> Option<OptionGreeks> {
+    match response {
+        Response::Tick {
+            tick: Tick::OptionComputation(greeks),
+            ..
+        } => Some(greeks),
+        _ => None,
+    }
+}
+
+fn req_current_time() -> Message {
+    let mut msg = Message::outbound(OutboundMessages::ReqCurrentTime);
+    msg.add_field(1);
+    msg
+}
+
+fn cancel_mkt_data(request_id: i32) -> Message {
+    let mut msg = Message::outbound(OutboundMessages::CancelMarketData);
+    msg.add_field(2);
+    msg.add_field(request_id);
+    msg
+}
+
 enum ConnectionState {
     Disconnected,
     Connecting,
     Connected,
-}
\ No newline at end of file
+}
+
+/// Whether two contracts describe the same instrument. The server fills in details such as the
+/// contract id on the contracts it reports, so only fields set on both sides are compared.
+fn same_contract(placed: &Contract, requested: &Contract) -> bool {
+    if placed.contract_id != 0 && requested.contract_id != 0 {
+        return placed.contract_id == requested.contract_id;
+    }
+    let same_if_set = |a: &str, b: &str| a.is_empty() || b.is_empty() || a == b;
+    placed.symbol == requested.symbol
+        && placed.security_type == requested.security_type
+        && placed.currency == requested.currency
+        && placed.strike == requested.strike
+        && same_if_set(&placed.right, &requested.right)
+        && same_if_set(
+            &placed.last_trade_date_or_contract_month,
+            &requested.last_trade_date_or_contract_month,
+        )
+        && same_if_set(&placed.multiplier, &requested.multiplier)
+}
+
+/// Whether an order the server echoed back carries every term a modification changed. Terms the
+/// modification left alone aren't compared, since the server fills some of them in.
+fn modification_applied(placed: Option<&Order>, requested: &Order, echoed: &Order) -> bool {
+    macro_rules! applied {
+        ($($field:ident),*) => {
+            $((placed.is_some_and(|p| p.$field == requested.$field)
+                || echoed.$field == requested.$field))&&*
+        };
+    }
+    applied!(
+        action,
+        total_quantity,
+        order_type,
+        lmt_price,
+        aux_price,
+        tif,
+        outside_rth,
+        account,
+        good_after_time,
+        good_till_date,
+        trail_stop_price,
+        trailing_percent,
+        display_size,
+        hidden,
+        all_or_none,
+        min_qty,
+        order_ref,
+        cash_qty,
+        model_code
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{AccountValue, Position};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn same_contract_ignores_details_filled_in_by_server() {
+        let requested = Contract::stock("AAPL", "SMART", "USD");
+        let mut placed = requested.clone();
+        placed.contract_id = 265598;
+        placed.local_symbol = "AAPL".to_string();
+        assert!(same_contract(&placed, &requested));
+
+        let other = Contract::stock("MSFT", "SMART", "USD");
+        assert!(!same_contract(&placed, &other));
+
+        let mut with_id = requested.clone();
+        with_id.contract_id = 272093;
+        assert!(!same_contract(&placed, &with_id));
+    }
+
+    #[test]
+    fn modification_applied_compares_changed_terms() {
+        let placed = Order {
+            total_quantity: Decimal::from(100),
+            lmt_price: Some(150.0),
+            tif: "DAY".to_string(),
+            ..Order::default()
+        };
+        let requested = Order {
+            tif: "GTC".to_string(),
+            ..placed.clone()
+        };
+        // The server filled in the account, which the modification didn't touch
+        let mut echoed = Order {
+            account: "DU123456".to_string(),
+            ..placed.clone()
+        };
+        assert!(!modification_applied(Some(&placed), &requested, &echoed));
+        echoed.tif = "GTC".to_string();
+        assert!(modification_applied(Some(&placed), &requested, &echoed));
+        assert!(!modification_applied(None, &requested, &echoed));
+    }
+
+    #[test]
+    fn encode_calc_implied_volatility() {
+        let mut contract =
+            Contract::option("AAPL", "20201218", Decimal::from(120), "C", "SMART", "USD");
+        contract.trading_class = "AAPL".to_string();
+        let msg = encode_calc_option(
+            OutboundMessages::ReqCalcImpliedVolat,
+            100_000_001,
+            &contract,
+            7.25,
+            121.5,
+        );
+        let fields = outbound_fields(&msg);
+        assert_eq!(&fields[..5], &["54", "3", "100000001", "0", "AAPL"]);
+        assert_eq!(&fields[5..9], &["OPT", "20201218", "120", "C"]);
+        // Trading class, option price, underlying price and the empty options
+        assert_eq!(&fields[14..], &["AAPL", "7.25", "121.5", "0", ""]);
+    }
+
+    #[test]
+    fn option_computation_finishes_calculation() {
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new(1)));
+        let responses = dispatcher
+            .lock()
+            .unwrap()
+            .register(RouteKey::Request(100_000_002));
+        let (outbound, sent) = crossbeam_channel::unbounded();
+        let mut cancel = Message::outbound(OutboundMessages::CancelCalcImpliedVolat);
+        cancel.add_field(1);
+        cancel.add_field(100_000_002);
+        let mut calculation = OptionCalculation::new(Subscription::new(
+            100_000_002,
+            responses,
+            Some(cancel),
+            outbound,
+            dispatcher.clone(),
+            option_computation,
+            |_| true,
+        ));
+
+        let mut msg = message::InboundMessage::new();
+        for field in [
+            "21",
+            "100000002",
+            "53",
+            "0",
+            "0.31",
+            "0.55",
+            "7.25",
+            "-1",
+            "0.021",
+            "0.18",
+            "-0.04",
+            "121.5",
+        ] {
+            msg.add_field(message::IBField::IBString(field.to_string()));
+        }
+        let response = Decoder::new(MAX_CLIENT_VER as usize).decode(&msg).unwrap();
+        dispatcher.lock().unwrap().dispatch(response);
+
+        let greeks = calculation.wait(Duration::from_secs(1)).unwrap();
+        assert_eq!(greeks.implied_volatility, Some(0.31));
+        assert!(calculation.try_result().is_none());
+        // A finished calculation has nothing to cancel
+        drop(calculation);
+        assert!(sent.try_recv().is_err());
+    }
+
+    fn outbound_fields(msg: &Message) -> Vec<String> {
+        message::InboundMessage::from_bytes(&msg.to_bytes())
+            .unwrap()
+            .fields()
+            .iter()
+            .map(|f| f.to_text())
+            .collect()
+    }
+
+    #[test]
+    fn encode_exercise_options_fields() {
+        let mut contract =
+            Contract::option("AAPL", "20201218", Decimal::from(120), "C", "SMART", "USD");
+        contract.contract_id = 437_215_932;
+        contract.trading_class = "AAPL".to_string();
+        let msg = encode_exercise_options(
+            100_000_003,
+            &contract,
+            ExerciseAction::Lapse,
+            2,
+            "DU123456",
+            true,
+        )
+        .unwrap();
+        let fields = outbound_fields(&msg);
+        assert_eq!(&fields[..4], &["21", "2", "100000003", "437215932"]);
+        // No primary exchange between the exchange and the currency
+        assert_eq!(
+            &fields[4..13],
+            &["AAPL", "OPT", "20201218", "120", "C", "", "SMART", "USD", ""]
+        );
+        assert_eq!(&fields[13..], &["AAPL", "2", "2", "DU123456", "1"]);
+    }
+
+    #[test]
+    fn exercise_options_rejects_bad_requests() {
+        let option = Contract::option("AAPL", "20201218", Decimal::from(120), "C", "SMART", "USD");
+        let exercise = |contract: &Contract, quantity| {
+            encode_exercise_options(1, contract, ExerciseAction::Exercise, quantity, "", false)
+        };
+        let stock = Contract::stock("AAPL", "SMART", "USD");
+        assert!(exercise(&stock, 1).is_err());
+        assert!(exercise(&option, 0).is_err());
+        assert!(exercise(&option, -1).is_err());
+        let mut no_exchange = option.clone();
+        no_exchange.exchange = String::new();
+        assert!(exercise(&no_exchange, 1).is_err());
+        assert!(exercise(&option, 1).is_ok());
+    }
+
+    fn account_updates(
+        dispatcher: &Arc<Mutex<Dispatcher>>,
+        outbound: Sender<Message>,
+    ) -> AccountUpdates {
+        let mut cancel = Message::outbound(OutboundMessages::ReqAccountData);
+        cancel.add_field(2);
+        cancel.add_field(false);
+        cancel.add_field("DU123456");
+        AccountUpdates::new(
+            Subscription::for_route(
+                RouteKey::AccountUpdates,
+                Some(cancel),
+                outbound,
+                dispatcher.clone(),
+                account_event,
+                |_| false,
+            )
+            .unwrap(),
+            "DU123456",
+            "",
+        )
+    }
+
+    #[test]
+    fn dropping_replaced_account_updates_keeps_the_new_subscription() {
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new(1)));
+        let (outbound, sent) = crossbeam_channel::unbounded();
+        let old = account_updates(&dispatcher, outbound.clone());
+        let mut new = account_updates(&dispatcher, outbound);
+        drop(old);
+        assert!(sent.try_recv().is_err());
+
+        dispatcher
+            .lock()
+            .unwrap()
+            .dispatch(Response::AccountValue(AccountValue {
+                key: "NetLiquidation".to_string(),
+                value: "100000.00".to_string(),
+                currency: "USD".to_string(),
+                account: "DU123456".to_string(),
+                model_code: String::new(),
+            }));
+        assert_eq!(new.poll().unwrap().len(), 1);
+        assert_eq!(
+            new.state().value_f64("NetLiquidation", "USD"),
+            Some(100_000.0)
+        );
+
+        // The subscription that owns the route still cancels it
+        drop(new);
+        assert!(sent.try_recv().is_ok());
+    }
+
+    fn positions(dispatcher: &Arc<Mutex<Dispatcher>>, outbound: Sender<Message>) -> Positions {
+        let mut cancel = Message::outbound(OutboundMessages::CancelPositions);
+        cancel.add_field(1);
+        Positions::new(
+            Subscription::for_route(
+                RouteKey::Positions,
+                Some(cancel),
+                outbound,
+                dispatcher.clone(),
+                position_event,
+                |_| false,
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn dropping_replaced_positions_keeps_the_new_subscription() {
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new(1)));
+        let (outbound, sent) = crossbeam_channel::unbounded();
+        let mut old = positions(&dispatcher, outbound.clone());
+        let mut new = positions(&dispatcher, outbound);
+        old.cancel().unwrap();
+        assert!(sent.try_recv().is_err());
+
+        let mut d = dispatcher.lock().unwrap();
+        d.dispatch(Response::Position(Box::new(Position {
+            account: "DU123456".to_string(),
+            contract: Contract {
+                contract_id: 265_598,
+                ..Contract::stock("AAPL", "SMART", "USD")
+            },
+            position: Decimal::from(100),
+            average_cost: 150.0,
+            model_code: String::new(),
+        })));
+        d.dispatch(Response::PositionEnd);
+        drop(d);
+        assert_eq!(new.poll().unwrap().len(), 1);
+        assert!(new.initial_complete());
+        assert_eq!(new.positions().len(), 1);
+    }
+
+    #[test]
+    fn account_updates_multi_keep_accounts_apart_and_label_the_end() {
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new(1)));
+        let responses = dispatcher
+            .lock()
+            .unwrap()
+            .register(RouteKey::Request(100_000_004));
+        let (outbound, _sent) = crossbeam_channel::unbounded();
+        let mut updates = AccountUpdates::new(
+            Subscription::new(
+                100_000_004,
+                responses,
+                None,
+                outbound,
+                dispatcher.clone(),
+                account_multi_event,
+                |_| false,
+            ),
+            "",
+            "GROWTH",
+        );
+
+        let mut d = dispatcher.lock().unwrap();
+        for (account, value) in [("DU123", "1000"), ("DU456", "2000")] {
+            d.dispatch(Response::AccountUpdateMulti {
+                request_id: 100_000_004,
+                value: AccountValue {
+                    key: "NetLiquidation".to_string(),
+                    value: value.to_string(),
+                    currency: "USD".to_string(),
+                    account: account.to_string(),
+                    model_code: "GROWTH".to_string(),
+                },
+            });
+        }
+        d.dispatch(Response::AccountUpdateMultiEnd(100_000_004));
+        drop(d);
+
+        let changes = updates.poll().unwrap();
+        assert_eq!(
+            changes.last(),
+            Some(&AccountEvent::DownloadEnd {
+                account: String::new(),
+                model_code: "GROWTH".to_string(),
+            })
+        );
+        let state = updates.state();
+        assert!(state.download_complete);
+        assert_eq!(
+            state.account_value("DU123", "GROWTH", "NetLiquidation", "USD"),
+            Some("1000")
+        );
+        assert_eq!(
+            state.account_value("DU456", "GROWTH", "NetLiquidation", "USD"),
+            Some("2000")
+        );
+    }
+}
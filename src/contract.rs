@@ -1,6 +1,8 @@
 //! Contains data structures for contracts of various types
 
+use crate::message::Message;
 use rust_decimal::prelude::*;
+use std::fmt;
 
 /// These are some convenience type wrappers
 pub type ContractId = usize;
@@ -13,13 +15,77 @@ pub type Exchange = String;
 pub type Currency = String;
 pub type TradingClass = String;
 pub type SecurityId = String;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub enum SecurityType {
+    #[default]
     Stock,
     Bond,
     Future,
     Option,
+    FutureOption,
+    Index,
+    Cash,
+    Combo,
+    Warrant,
+    Commodity,
+    News,
+    Fund,
+    Cfd,
+    /// Anything the server sends that we don't have a variant for
+    Other(String),
+}
+
+impl SecurityType {
+    /// The code the API server uses for this security type
+    pub fn as_str(&self) -> &str {
+        match self {
+            SecurityType::Stock => "STK",
+            SecurityType::Bond => "BOND",
+            SecurityType::Future => "FUT",
+            SecurityType::Option => "OPT",
+            SecurityType::FutureOption => "FOP",
+            SecurityType::Index => "IND",
+            SecurityType::Cash => "CASH",
+            SecurityType::Combo => "BAG",
+            SecurityType::Warrant => "WAR",
+            SecurityType::Commodity => "CMDTY",
+            SecurityType::News => "NEWS",
+            SecurityType::Fund => "FUND",
+            SecurityType::Cfd => "CFD",
+            SecurityType::Other(s) => s,
+        }
+    }
+}
+
+impl From<&str> for SecurityType {
+    fn from(s: &str) -> Self {
+        match s {
+            "STK" => SecurityType::Stock,
+            "BOND" => SecurityType::Bond,
+            "FUT" => SecurityType::Future,
+            "OPT" => SecurityType::Option,
+            "FOP" => SecurityType::FutureOption,
+            "IND" => SecurityType::Index,
+            "CASH" => SecurityType::Cash,
+            "BAG" => SecurityType::Combo,
+            "WAR" => SecurityType::Warrant,
+            "CMDTY" => SecurityType::Commodity,
+            "NEWS" => SecurityType::News,
+            "FUND" => SecurityType::Fund,
+            "CFD" => SecurityType::Cfd,
+            other => SecurityType::Other(other.to_string()),
+        }
+    }
 }
 
+impl fmt::Display for SecurityType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SecurityIdType {
     CUSIP,
     SEDOL,
@@ -27,44 +93,150 @@ pub enum SecurityIdType {
     RIC,
 }
 
+impl SecurityIdType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SecurityIdType::CUSIP => "CUSIP",
+            SecurityIdType::SEDOL => "SEDOL",
+            SecurityIdType::ISIN => "ISIN",
+            SecurityIdType::RIC => "RIC",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<SecurityIdType> {
+        match s {
+            "CUSIP" => Some(SecurityIdType::CUSIP),
+            "SEDOL" => Some(SecurityIdType::SEDOL),
+            "ISIN" => Some(SecurityIdType::ISIN),
+            "RIC" => Some(SecurityIdType::RIC),
+            _ => None,
+        }
+    }
+}
+
 /// A combo refers to a complex Options position composed of multiple legs, or individual options strategies
-pub struct ComboLeg {}
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ComboLeg {
+    pub contract_id: ContractId,
+    pub ratio: i32,
+    pub action: String,
+    pub exchange: Exchange,
+    pub open_close: i32,
+    pub short_sale_slot: i32,
+    pub designated_location: String,
+    pub exempt_code: i32,
+}
 
 /// Refers to a contract that tries to keep delta close to 0
-pub struct DeltaNeutralContract {}
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeltaNeutralContract {
+    pub contract_id: ContractId,
+    pub delta: f64,
+    pub price: f64,
+}
 
 /// Contract is a general contract, representing a future, a stock, an option, etc. This is the most commonly used contract
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Contract {
-    contract_id: ContractId,
-    symbol: Symbol,
-    security_type: SecurityType,
-    last_trade_date_or_contract_month: LastTradeDateOrContractMonth,
-    strike: Strike,
-    right: Right,
-    multiplier: Multiplier,
-    exchange: Exchange,
-    primary_exchange: Exchange,
-    currency: Currency,
-    local_symbol: Symbol,
-    trading_class: TradingClass,
-    include_expired: bool,
-    security_id_type: SecurityIdType,
-    security_id: SecurityId,
-    combo_legs_description: Option<String>,
-    combo_legs: Vec<ComboLeg>,
-    delta_neutral_contract: Option<DeltaNeutralContract>,
+    pub contract_id: ContractId,
+    pub symbol: Symbol,
+    pub security_type: SecurityType,
+    pub last_trade_date_or_contract_month: LastTradeDateOrContractMonth,
+    pub strike: Strike,
+    pub right: Right,
+    pub multiplier: Multiplier,
+    pub exchange: Exchange,
+    pub primary_exchange: Exchange,
+    pub currency: Currency,
+    pub local_symbol: Symbol,
+    pub trading_class: TradingClass,
+    pub include_expired: bool,
+    pub security_id_type: Option<SecurityIdType>,
+    pub security_id: SecurityId,
+    pub combo_legs_description: Option<String>,
+    pub combo_legs: Vec<ComboLeg>,
+    pub delta_neutral_contract: Option<DeltaNeutralContract>,
+}
+
+impl Contract {
+    /// A stock routed to the given exchange, e.g. `Contract::stock("AAPL", "SMART", "USD")`
+    pub fn stock<S: Into<String>>(symbol: S, exchange: S, currency: S) -> Contract {
+        Contract {
+            symbol: symbol.into(),
+            security_type: SecurityType::Stock,
+            exchange: exchange.into(),
+            currency: currency.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A futures contract for the given contract month, e.g. `202012`
+    pub fn future<S: Into<String>>(
+        symbol: S,
+        contract_month: S,
+        exchange: S,
+        currency: S,
+    ) -> Contract {
+        Contract {
+            symbol: symbol.into(),
+            security_type: SecurityType::Future,
+            last_trade_date_or_contract_month: contract_month.into(),
+            exchange: exchange.into(),
+            currency: currency.into(),
+            ..Default::default()
+        }
+    }
+
+    /// An option on the given underlying symbol. `right` is `C` or `P`
+    pub fn option<S: Into<String>>(
+        symbol: S,
+        expiry: S,
+        strike: Strike,
+        right: S,
+        exchange: S,
+        currency: S,
+    ) -> Contract {
+        Contract {
+            symbol: symbol.into(),
+            security_type: SecurityType::Option,
+            last_trade_date_or_contract_month: expiry.into(),
+            strike,
+            right: right.into(),
+            exchange: exchange.into(),
+            currency: currency.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Adds the contract fields shared by most requests, from the contract id through the local symbol
+pub fn add_contract_fields(msg: &mut Message, contract: &Contract, include_primary_exchange: bool) {
+    msg.add_field(contract.contract_id);
+    msg.add_field(&contract.symbol);
+    msg.add_field(contract.security_type.as_str());
+    msg.add_field(&contract.last_trade_date_or_contract_month);
+    msg.add_field(contract.strike);
+    msg.add_field(&contract.right);
+    msg.add_field(&contract.multiplier);
+    msg.add_field(&contract.exchange);
+    if include_primary_exchange {
+        msg.add_field(&contract.primary_exchange);
+    }
+    msg.add_field(&contract.currency);
+    msg.add_field(&contract.local_symbol);
 }
 
 pub type Market = String;
-pub type Tick = usize;
+pub type Tick = f64;
 /// Mixin struct that contains much of the ancillary details of a contract
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct ContractDetails {
-    contract: Contract,
-    market_name: Market,
-    min_tick: Tick,
-    order_types: Option<String>,
-    valid_exchanges: Vec<Exchange>,
-    price_magnifier: Option<Decimal>,
+    pub contract: Contract,
+    pub market_name: Market,
+    pub min_tick: Tick,
+    pub order_types: Option<String>,
+    pub valid_exchanges: Vec<Exchange>,
+    pub price_magnifier: Option<Decimal>,
 }
 
 /// TODO: Find out what this is
@@ -1,7 +1,1474 @@
-pub struct Decoder {}
+//! Turns the raw text fields of an `InboundMessage` into typed responses
+
+use crate::account::{AccountSummaryValue, AccountValue, PortfolioItem, Position};
+use crate::bars::{Bar, HistogramEntry};
+use crate::contract::{Contract, ContractId, SecurityType};
+use crate::depth::{DepthMktDataDescription, DepthOperation, DepthSide, DepthUpdate};
+use crate::execution::{CommissionReport, Execution, ExecutionDetails};
+use crate::market_data::{
+    BidAskAttributes, HistoricalTick, HistoricalTickBidAsk, HistoricalTickLast, HistoricalTicks,
+    MarketDataType, OptionGreeks, Tick, TickAttributes, TickByTick, TickByTickType, TickType,
+    TradeAttributes,
+};
+use crate::message::{IBField, InboundMessage, InboundMessages};
+use crate::options::OptionParameters;
+use crate::order::{
+    CompletedOrder, OpenOrder, OrderBound, OrderId, OrderStatus, OrderStatusUpdate,
+};
+use crate::order_decoder;
+use crate::server_versions::*;
+use anyhow::*;
+use rust_decimal::prelude::*;
+use std::convert::TryFrom;
+use std::fmt;
+use std::slice::Iter;
+
+/// A cursor over the fields of an inbound message. The server sends everything as text; these
+/// read the next field and parse it into the expected type.
+pub struct Fields<'a> {
+    iter: Iter<'a, IBField>,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(msg: &'a InboundMessage) -> Fields<'a> {
+        Fields {
+            iter: msg.fields().iter(),
+        }
+    }
+
+    pub fn read_string(&mut self) -> Result<String, Error> {
+        match self.iter.next() {
+            Some(field) => Ok(field.to_text()),
+            None => Err(anyhow!("Message ended before all fields were read")),
+        }
+    }
+
+    /// How many fields are left to read
+    pub fn remaining(&self) -> usize {
+        self.iter.len()
+    }
+
+    pub fn skip(&mut self) -> Result<(), Error> {
+        self.read_string().map(|_| ())
+    }
+
+    /// Empty fields are read as 0
+    pub fn read_int(&mut self) -> Result<i32, Error> {
+        let s = self.read_string()?;
+        match s.as_str() {
+            "" => Ok(0),
+            s => Ok(s.parse()?),
+        }
+    }
+
+    pub fn read_long(&mut self) -> Result<i64, Error> {
+        let s = self.read_string()?;
+        match s.as_str() {
+            "" => Ok(0),
+            s => Ok(s.parse()?),
+        }
+    }
+
+    /// Empty fields are read as 0.0
+    pub fn read_double(&mut self) -> Result<f64, Error> {
+        let s = self.read_string()?;
+        match s.as_str() {
+            "" => Ok(0.0),
+            s => Ok(s.parse()?),
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, Error> {
+        Ok(self.read_int()? != 0)
+    }
+
+    /// Reads an integer the server may leave unset, either as an empty field or as `i32::MAX`
+    pub fn read_int_max(&mut self) -> Result<Option<i32>, Error> {
+        let s = self.read_string()?;
+        match s.as_str() {
+            "" => Ok(None),
+            s => match s.parse()? {
+                i32::MAX => Ok(None),
+                v => Ok(Some(v)),
+            },
+        }
+    }
+
+    /// Reads a double the server may leave unset, either as an empty field or as `f64::MAX`
+    pub fn read_double_max(&mut self) -> Result<Option<f64>, Error> {
+        let s = self.read_string()?;
+        match s.as_str() {
+            "" => Ok(None),
+            s => {
+                let v: f64 = s.parse()?;
+                if v >= f64::MAX {
+                    Ok(None)
+                } else {
+                    Ok(Some(v))
+                }
+            }
+        }
+    }
+
+    /// Reads a quantity. Empty fields are read as 0
+    pub fn read_decimal(&mut self) -> Result<Decimal, Error> {
+        let s = self.read_string()?;
+        parse_decimal(&s)
+    }
+
+    /// Reads a quantity the server may leave unset, either as an empty field or as `f64::MAX`
+    pub fn read_decimal_max(&mut self) -> Result<Option<Decimal>, Error> {
+        let s = self.read_string()?;
+        if s.is_empty() || s.parse::<f64>()? >= f64::MAX {
+            return Ok(None);
+        }
+        Ok(Some(parse_decimal(&s)?))
+    }
+}
+
+fn parse_decimal(s: &str) -> Result<Decimal, Error> {
+    if s.is_empty() {
+        return Ok(Decimal::zero());
+    }
+    match Decimal::from_str(s) {
+        Ok(d) => Ok(d),
+        Err(_) => {
+            Decimal::from_scientific(s).map_err(|e| anyhow!("Invalid decimal {}: {:?}", s, e))
+        }
+    }
+}
+
+fn read_tick_type(fields: &mut Fields) -> Result<TickType, Error> {
+    TickType::try_from(fields.read_int()?)
+}
+
+/// Reads the contract of a position message, which has no primary exchange
+fn read_position_contract(fields: &mut Fields, trading_class: bool) -> Result<Contract, Error> {
+    let mut contract = Contract {
+        contract_id: fields.read_int()? as ContractId,
+        symbol: fields.read_string()?,
+        security_type: SecurityType::from(fields.read_string()?.as_str()),
+        last_trade_date_or_contract_month: fields.read_string()?,
+        strike: fields.read_decimal()?,
+        right: fields.read_string()?,
+        multiplier: fields.read_string()?,
+        exchange: fields.read_string()?,
+        currency: fields.read_string()?,
+        local_symbol: fields.read_string()?,
+        ..Default::default()
+    };
+    if trading_class {
+        contract.trading_class = fields.read_string()?;
+    }
+    Ok(contract)
+}
+
+/// An error or notice sent by the server. `id` is the request or order id it relates to, or -1
+/// when it is not tied to any request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwsError {
+    pub id: i32,
+    pub code: i32,
+    pub message: String,
+}
+
+impl TwsError {
+    /// Notices such as "market data farm connection is OK" arrive as errors, but aren't failures
+    pub fn is_warning(&self) -> bool {
+        (2100..2200).contains(&self.code) || self.code == 399 || self.code == 10167
+    }
+}
+
+impl fmt::Display for TwsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TWS error {} (id {}): {}",
+            self.code, self.id, self.message
+        )
+    }
+}
+
+impl std::error::Error for TwsError {}
+
+/// The typed contents of an inbound message
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    NextValidId(OrderId),
+    ManagedAccounts(Vec<String>),
+    /// The server's clock, in seconds since the epoch
+    CurrentTime(i64),
+    Error(TwsError),
+    OrderStatus(OrderStatusUpdate),
+    OpenOrder(Box<OpenOrder>),
+    OpenOrderEnd,
+    OrderBound(OrderBound),
+    ExecutionData(Box<ExecutionDetails>),
+    ExecutionDataEnd(i32),
+    CommissionReport(CommissionReport),
+    CompletedOrder(Box<CompletedOrder>),
+    CompletedOrdersEnd,
+    Tick {
+        request_id: i32,
+        tick: Tick,
+    },
+    TickByTick {
+        request_id: i32,
+        tick: TickByTick,
+    },
+    RealTimeBar {
+        request_id: i32,
+        bar: Bar,
+    },
+    HistoricalData {
+        request_id: i32,
+        bars: Vec<Bar>,
+    },
+    HistoricalDataUpdate {
+        request_id: i32,
+        bar: Bar,
+    },
+    HistoricalTicks {
+        request_id: i32,
+        ticks: HistoricalTicks,
+        /// Whether this is the last message for the request
+        done: bool,
+    },
+    HeadTimestamp {
+        request_id: i32,
+        timestamp: String,
+    },
+    HistogramData {
+        request_id: i32,
+        entries: Vec<HistogramEntry>,
+    },
+    SecurityDefinitionOptionParameter {
+        request_id: i32,
+        parameters: OptionParameters,
+    },
+    SecurityDefinitionOptionParameterEnd(i32),
+    MarketDepth {
+        request_id: i32,
+        update: DepthUpdate,
+    },
+    /// The server reports this as error 317; the depth book must be emptied
+    MarketDepthReset(i32),
+    MarketDepthExchanges(Vec<DepthMktDataDescription>),
+    AccountValue(AccountValue),
+    PortfolioValue(Box<PortfolioItem>),
+    AccountUpdateTime(String),
+    AccountDownloadEnd(String),
+    AccountSummary {
+        request_id: i32,
+        account: String,
+        value: AccountSummaryValue,
+    },
+    AccountSummaryEnd(i32),
+    Position(Box<Position>),
+    PositionEnd,
+    PositionMulti {
+        request_id: i32,
+        position: Box<Position>,
+    },
+    PositionMultiEnd(i32),
+    AccountUpdateMulti {
+        request_id: i32,
+        value: AccountValue,
+    },
+    AccountUpdateMultiEnd(i32),
+    /// A message we don't decode yet
+    Unhandled(InboundMessages),
+}
+
+/// Decodes inbound messages according to the version the server negotiated at connection time
+pub struct Decoder {
+    server_version: usize,
+}
 
 impl Decoder {
-    pub fn new() -> Decoder {
-        Decoder {}
+    pub fn new(server_version: usize) -> Decoder {
+        Decoder { server_version }
+    }
+
+    pub fn server_version(&self) -> usize {
+        self.server_version
+    }
+
+    pub fn decode(&self, msg: &InboundMessage) -> Result<Response, Error> {
+        let kind = msg.message_type()?;
+        let mut fields = Fields::new(msg);
+        // The message id has already been read by message_type()
+        fields.skip()?;
+        match kind {
+            InboundMessages::NextValidID => {
+                fields.skip()?;
+                Ok(Response::NextValidId(fields.read_int()?))
+            }
+            InboundMessages::CurrentTime => {
+                fields.skip()?;
+                Ok(Response::CurrentTime(fields.read_long()?))
+            }
+            InboundMessages::ManagedAccounts => {
+                fields.skip()?;
+                let accounts = fields.read_string()?;
+                Ok(Response::ManagedAccounts(
+                    accounts
+                        .split(',')
+                        .filter(|a| !a.is_empty())
+                        .map(|a| a.to_string())
+                        .collect(),
+                ))
+            }
+            InboundMessages::ErrMsg => {
+                fields.skip()?;
+                let error = TwsError {
+                    id: fields.read_int()?,
+                    code: fields.read_int()?,
+                    message: fields.read_string()?,
+                };
+                if error.code == 317 {
+                    return Ok(Response::MarketDepthReset(error.id));
+                }
+                Ok(Response::Error(error))
+            }
+            InboundMessages::RealTimeBars => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let bar = Bar {
+                    time: fields.read_string()?,
+                    open: fields.read_double()?,
+                    high: fields.read_double()?,
+                    low: fields.read_double()?,
+                    close: fields.read_double()?,
+                    volume: fields.read_decimal()?,
+                    wap: fields.read_decimal()?,
+                    count: fields.read_int()?,
+                };
+                Ok(Response::RealTimeBar { request_id, bar })
+            }
+            InboundMessages::HistoricalData => {
+                if self.server_version < MIN_SERVER_VER_SYNT_REALTIME_BARS {
+                    fields.skip()?;
+                }
+                let request_id = fields.read_int()?;
+                // start and end of the requested period
+                fields.skip()?;
+                fields.skip()?;
+                let count = fields.read_int()?;
+                let mut bars = vec![];
+                for _ in 0..count {
+                    let mut bar = Bar {
+                        time: fields.read_string()?,
+                        open: fields.read_double()?,
+                        high: fields.read_double()?,
+                        low: fields.read_double()?,
+                        close: fields.read_double()?,
+                        volume: fields.read_decimal()?,
+                        wap: fields.read_decimal()?,
+                        ..Default::default()
+                    };
+                    if self.server_version < MIN_SERVER_VER_SYNT_REALTIME_BARS {
+                        // hasGaps
+                        fields.skip()?;
+                    }
+                    bar.count = fields.read_int()?;
+                    bars.push(bar);
+                }
+                Ok(Response::HistoricalData { request_id, bars })
+            }
+            InboundMessages::HistoricalDataUpdate => {
+                let request_id = fields.read_int()?;
+                let count = fields.read_int()?;
+                let time = fields.read_string()?;
+                let open = fields.read_double()?;
+                let close = fields.read_double()?;
+                let high = fields.read_double()?;
+                let low = fields.read_double()?;
+                let bar = Bar {
+                    time,
+                    open,
+                    high,
+                    low,
+                    close,
+                    wap: fields.read_decimal()?,
+                    volume: fields.read_decimal()?,
+                    count,
+                };
+                Ok(Response::HistoricalDataUpdate { request_id, bar })
+            }
+            InboundMessages::HistoricalTicks => {
+                let request_id = fields.read_int()?;
+                let count = fields.read_int()?;
+                let mut ticks = vec![];
+                for _ in 0..count {
+                    let time = fields.read_long()?;
+                    // unused, sent for consistency with the other kinds
+                    fields.skip()?;
+                    ticks.push(HistoricalTick {
+                        time,
+                        price: fields.read_double()?,
+                        size: fields.read_decimal()?,
+                    });
+                }
+                Ok(Response::HistoricalTicks {
+                    request_id,
+                    ticks: HistoricalTicks::Midpoint(ticks),
+                    done: fields.read_bool()?,
+                })
+            }
+            InboundMessages::HistoricalTicksBidAsk => {
+                let request_id = fields.read_int()?;
+                let count = fields.read_int()?;
+                let mut ticks = vec![];
+                for _ in 0..count {
+                    let time = fields.read_long()?;
+                    let mask = fields.read_int()?;
+                    ticks.push(HistoricalTickBidAsk {
+                        time,
+                        // the reverse of the bit order tick-by-tick uses
+                        attributes: BidAskAttributes {
+                            ask_past_high: mask & 1 != 0,
+                            bid_past_low: mask & 2 != 0,
+                        },
+                        bid_price: fields.read_double()?,
+                        ask_price: fields.read_double()?,
+                        bid_size: fields.read_decimal()?,
+                        ask_size: fields.read_decimal()?,
+                    });
+                }
+                Ok(Response::HistoricalTicks {
+                    request_id,
+                    ticks: HistoricalTicks::BidAsk(ticks),
+                    done: fields.read_bool()?,
+                })
+            }
+            InboundMessages::HistoricalTicksLast => {
+                let request_id = fields.read_int()?;
+                let count = fields.read_int()?;
+                let mut ticks = vec![];
+                for _ in 0..count {
+                    let time = fields.read_long()?;
+                    let mask = fields.read_int()?;
+                    ticks.push(HistoricalTickLast {
+                        time,
+                        attributes: TradeAttributes {
+                            past_limit: mask & 1 != 0,
+                            unreported: mask & 2 != 0,
+                        },
+                        price: fields.read_double()?,
+                        size: fields.read_decimal()?,
+                        exchange: fields.read_string()?,
+                        special_conditions: fields.read_string()?,
+                    });
+                }
+                Ok(Response::HistoricalTicks {
+                    request_id,
+                    ticks: HistoricalTicks::Last(ticks),
+                    done: fields.read_bool()?,
+                })
+            }
+            InboundMessages::HistogramData => {
+                let request_id = fields.read_int()?;
+                let count = fields.read_int()?;
+                let mut entries = vec![];
+                for _ in 0..count {
+                    entries.push(HistogramEntry {
+                        price: fields.read_double()?,
+                        size: fields.read_decimal()?,
+                    });
+                }
+                Ok(Response::HistogramData {
+                    request_id,
+                    entries,
+                })
+            }
+            InboundMessages::HeadTimestamp => Ok(Response::HeadTimestamp {
+                request_id: fields.read_int()?,
+                timestamp: fields.read_string()?,
+            }),
+            InboundMessages::SecurityDefinitionOptionParameter => {
+                let request_id = fields.read_int()?;
+                let mut parameters = OptionParameters {
+                    exchange: fields.read_string()?,
+                    underlying_contract_id: fields.read_int()? as ContractId,
+                    trading_class: fields.read_string()?,
+                    multiplier: fields.read_string()?,
+                    ..Default::default()
+                };
+                for _ in 0..fields.read_int()? {
+                    parameters.expirations.push(fields.read_string()?);
+                }
+                for _ in 0..fields.read_int()? {
+                    parameters.strikes.push(fields.read_decimal()?);
+                }
+                Ok(Response::SecurityDefinitionOptionParameter {
+                    request_id,
+                    parameters,
+                })
+            }
+            InboundMessages::SecurityDefinitionOptionParameterEnd => Ok(
+                Response::SecurityDefinitionOptionParameterEnd(fields.read_int()?),
+            ),
+            InboundMessages::AccountValue => {
+                let version = fields.read_int()?;
+                let mut value = AccountValue {
+                    key: fields.read_string()?,
+                    value: fields.read_string()?,
+                    currency: fields.read_string()?,
+                    ..Default::default()
+                };
+                if version >= 2 {
+                    value.account = fields.read_string()?;
+                }
+                Ok(Response::AccountValue(value))
+            }
+            InboundMessages::PortfolioValue => self.decode_portfolio_value(&mut fields),
+            InboundMessages::AccountUpdateTime => {
+                fields.skip()?;
+                Ok(Response::AccountUpdateTime(fields.read_string()?))
+            }
+            InboundMessages::AccountDownloadEnd => {
+                fields.skip()?;
+                Ok(Response::AccountDownloadEnd(fields.read_string()?))
+            }
+            InboundMessages::AccountSummary => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let account = fields.read_string()?;
+                let tag = fields.read_string()?;
+                let value = fields.read_string()?;
+                let currency = fields.read_string()?;
+                Ok(Response::AccountSummary {
+                    request_id,
+                    account,
+                    value: AccountSummaryValue::new(tag, value, currency),
+                })
+            }
+            InboundMessages::AccountSummaryEnd => {
+                fields.skip()?;
+                Ok(Response::AccountSummaryEnd(fields.read_int()?))
+            }
+            InboundMessages::PositionData => {
+                let version = fields.read_int()?;
+                let account = fields.read_string()?;
+                let contract = read_position_contract(&mut fields, version >= 2)?;
+                let position = if self.server_version >= MIN_SERVER_VER_FRACTIONAL_POSITIONS {
+                    fields.read_decimal()?
+                } else {
+                    Decimal::from(fields.read_int()?)
+                };
+                let average_cost = if version >= 3 {
+                    fields.read_double()?
+                } else {
+                    0.0
+                };
+                Ok(Response::Position(Box::new(Position {
+                    account,
+                    contract,
+                    position,
+                    average_cost,
+                    model_code: String::new(),
+                })))
+            }
+            InboundMessages::PositionEnd => Ok(Response::PositionEnd),
+            InboundMessages::PositionMulti => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let account = fields.read_string()?;
+                let contract = read_position_contract(&mut fields, true)?;
+                let position = Position {
+                    account,
+                    contract,
+                    position: fields.read_decimal()?,
+                    average_cost: fields.read_double()?,
+                    model_code: fields.read_string()?,
+                };
+                Ok(Response::PositionMulti {
+                    request_id,
+                    position: Box::new(position),
+                })
+            }
+            InboundMessages::PositionMultiEnd => {
+                fields.skip()?;
+                Ok(Response::PositionMultiEnd(fields.read_int()?))
+            }
+            InboundMessages::AccountUpdateMulti => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let account = fields.read_string()?;
+                let model_code = fields.read_string()?;
+                let value = AccountValue {
+                    key: fields.read_string()?,
+                    value: fields.read_string()?,
+                    currency: fields.read_string()?,
+                    account,
+                    model_code,
+                };
+                Ok(Response::AccountUpdateMulti { request_id, value })
+            }
+            InboundMessages::AccountUpdateMultiEnd => {
+                fields.skip()?;
+                Ok(Response::AccountUpdateMultiEnd(fields.read_int()?))
+            }
+            InboundMessages::MarketDepth => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let update = DepthUpdate {
+                    position: fields.read_int()? as usize,
+                    market_maker: String::new(),
+                    operation: DepthOperation::from_id(fields.read_int()?)?,
+                    side: DepthSide::from_id(fields.read_int()?)?,
+                    price: fields.read_double()?,
+                    size: fields.read_decimal()?,
+                    is_smart_depth: false,
+                };
+                Ok(Response::MarketDepth { request_id, update })
+            }
+            InboundMessages::MarketDepthExchanges => {
+                let count = fields.read_int()?;
+                let mut descriptions = vec![];
+                for _ in 0..count {
+                    let mut d = DepthMktDataDescription {
+                        exchange: fields.read_string()?,
+                        security_type: fields.read_string()?,
+                        ..Default::default()
+                    };
+                    if self.server_version >= MIN_SERVER_VER_SERVICE_DATA_TYPE {
+                        d.listing_exchange = fields.read_string()?;
+                        d.service_data_type = fields.read_string()?;
+                        d.agg_group = fields.read_int_max()?;
+                    } else {
+                        let is_l2 = fields.read_bool()?;
+                        d.service_data_type = if is_l2 { "Deep2" } else { "Deep" }.to_string();
+                    }
+                    descriptions.push(d);
+                }
+                Ok(Response::MarketDepthExchanges(descriptions))
+            }
+            InboundMessages::MarketDepthL2 => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let mut update = DepthUpdate {
+                    position: fields.read_int()? as usize,
+                    market_maker: fields.read_string()?,
+                    operation: DepthOperation::from_id(fields.read_int()?)?,
+                    side: DepthSide::from_id(fields.read_int()?)?,
+                    price: fields.read_double()?,
+                    size: fields.read_decimal()?,
+                    is_smart_depth: false,
+                };
+                if self.server_version >= MIN_SERVER_VER_SMART_DEPTH {
+                    update.is_smart_depth = fields.read_bool()?;
+                }
+                Ok(Response::MarketDepth { request_id, update })
+            }
+            InboundMessages::OrderStatus => self.decode_order_status(&mut fields),
+            InboundMessages::OpenOrder => Ok(Response::OpenOrder(Box::new(
+                order_decoder::decode_open_order(&mut fields, self.server_version)?,
+            ))),
+            InboundMessages::OpenOrderEnd => Ok(Response::OpenOrderEnd),
+            InboundMessages::OrderBound => Ok(Response::OrderBound(OrderBound {
+                perm_id: fields.read_long()?,
+                api_client_id: fields.read_int()?,
+                api_order_id: fields.read_int()?,
+            })),
+            InboundMessages::ExecutionData => self.decode_execution_data(&mut fields),
+            InboundMessages::ExecutionDataEnd => {
+                fields.skip()?;
+                Ok(Response::ExecutionDataEnd(fields.read_int()?))
+            }
+            InboundMessages::CommissionReport => {
+                fields.skip()?;
+                Ok(Response::CommissionReport(CommissionReport {
+                    exec_id: fields.read_string()?,
+                    commission: fields.read_double()?,
+                    currency: fields.read_string()?,
+                    realized_pnl: fields.read_double_max()?,
+                    yield_amount: fields.read_double_max()?,
+                    yield_redemption_date: fields.read_int()?,
+                }))
+            }
+            InboundMessages::CompletedOrder => Ok(Response::CompletedOrder(Box::new(
+                order_decoder::decode_completed_order(&mut fields, self.server_version)?,
+            ))),
+            InboundMessages::CompletedOrdersEnd => Ok(Response::CompletedOrdersEnd),
+            InboundMessages::TickPrice => self.decode_tick_price(&mut fields),
+            InboundMessages::TickSize => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let tick = Tick::Size {
+                    tick_type: read_tick_type(&mut fields)?,
+                    size: fields.read_decimal()?,
+                };
+                Ok(Response::Tick { request_id, tick })
+            }
+            InboundMessages::TickString => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let tick = Tick::String {
+                    tick_type: read_tick_type(&mut fields)?,
+                    value: fields.read_string()?,
+                };
+                Ok(Response::Tick { request_id, tick })
+            }
+            InboundMessages::TickGeneric => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let tick = Tick::Generic {
+                    tick_type: read_tick_type(&mut fields)?,
+                    value: fields.read_double()?,
+                };
+                Ok(Response::Tick { request_id, tick })
+            }
+            InboundMessages::TickOptionComputation => {
+                self.decode_tick_option_computation(&mut fields)
+            }
+            InboundMessages::TickEfp => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let tick = Tick::Efp {
+                    tick_type: read_tick_type(&mut fields)?,
+                    basis_points: fields.read_double()?,
+                    formatted_basis_points: fields.read_string()?,
+                    implied_futures_price: fields.read_double()?,
+                    hold_days: fields.read_int()?,
+                    future_last_trade_date: fields.read_string()?,
+                    dividend_impact: fields.read_double()?,
+                    dividends_to_last_trade_date: fields.read_double()?,
+                };
+                Ok(Response::Tick { request_id, tick })
+            }
+            InboundMessages::TickReqParams => {
+                let request_id = fields.read_int()?;
+                let tick = Tick::ReqParams {
+                    min_tick: fields.read_double()?,
+                    bbo_exchange: fields.read_string()?,
+                    snapshot_permissions: fields.read_int()?,
+                };
+                Ok(Response::Tick { request_id, tick })
+            }
+            InboundMessages::MarketDataType => {
+                fields.skip()?;
+                let request_id = fields.read_int()?;
+                let tick = Tick::MarketDataType(MarketDataType::try_from(fields.read_int()?)?);
+                Ok(Response::Tick { request_id, tick })
+            }
+            InboundMessages::TickByTick => self.decode_tick_by_tick(&mut fields),
+            InboundMessages::TickSnapshotEnd => {
+                fields.skip()?;
+                Ok(Response::Tick {
+                    request_id: fields.read_int()?,
+                    tick: Tick::SnapshotEnd,
+                })
+            }
+            other => Ok(Response::Unhandled(other)),
+        }
+    }
+
+    fn decode_order_status(&self, fields: &mut Fields) -> Result<Response, Error> {
+        if self.server_version < MIN_SERVER_VER_MARKET_CAP_PRICE {
+            fields.skip()?;
+        }
+        let order_id = fields.read_int()?;
+        let status = OrderStatus::from(fields.read_string()?.as_str());
+        let filled = fields.read_decimal()?;
+        let remaining = fields.read_decimal()?;
+        let avg_fill_price = fields.read_double()?;
+        let perm_id = fields.read_long()?;
+        let parent_id = fields.read_int()?;
+        let last_fill_price = fields.read_double()?;
+        let client_id = fields.read_int()?;
+        let why_held = fields.read_string()?;
+        let market_cap_price = if self.server_version >= MIN_SERVER_VER_MARKET_CAP_PRICE {
+            fields.read_double()?
+        } else {
+            0.0
+        };
+        Ok(Response::OrderStatus(OrderStatusUpdate {
+            order_id,
+            status,
+            filled,
+            remaining,
+            avg_fill_price,
+            perm_id,
+            parent_id,
+            last_fill_price,
+            client_id,
+            why_held,
+            market_cap_price,
+        }))
+    }
+
+    fn decode_tick_price(&self, fields: &mut Fields) -> Result<Response, Error> {
+        fields.skip()?;
+        let request_id = fields.read_int()?;
+        let tick_type = read_tick_type(fields)?;
+        let price = fields.read_double()?;
+        let size = fields.read_decimal()?;
+        let mut mask = fields.read_int()?;
+        if self.server_version < MIN_SERVER_VER_PAST_LIMIT {
+            mask &= 1;
+        } else if self.server_version < MIN_SERVER_VER_PRE_OPEN_BID_ASK {
+            mask &= 3;
+        }
+        let tick = Tick::Price {
+            tick_type,
+            price,
+            size: tick_type.size_tick_type().map(|_| size),
+            attributes: TickAttributes::from_mask(mask),
+        };
+        Ok(Response::Tick { request_id, tick })
+    }
+
+    fn decode_portfolio_value(&self, fields: &mut Fields) -> Result<Response, Error> {
+        let version = fields.read_int()?;
+        let mut contract = Contract::default();
+        if version >= 6 {
+            contract.contract_id = fields.read_int()? as ContractId;
+        }
+        contract.symbol = fields.read_string()?;
+        contract.security_type = SecurityType::from(fields.read_string()?.as_str());
+        contract.last_trade_date_or_contract_month = fields.read_string()?;
+        contract.strike = fields.read_decimal()?;
+        contract.right = fields.read_string()?;
+        if version >= 7 {
+            contract.multiplier = fields.read_string()?;
+            contract.primary_exchange = fields.read_string()?;
+        }
+        contract.currency = fields.read_string()?;
+        if version >= 2 {
+            contract.local_symbol = fields.read_string()?;
+        }
+        if version >= 8 {
+            contract.trading_class = fields.read_string()?;
+        }
+        let mut item = PortfolioItem {
+            contract,
+            position: fields.read_decimal()?,
+            market_price: fields.read_double()?,
+            market_value: fields.read_double()?,
+            average_cost: 0.0,
+            unrealized_pnl: 0.0,
+            realized_pnl: 0.0,
+            account: String::new(),
+        };
+        if version >= 3 {
+            item.average_cost = fields.read_double()?;
+            item.unrealized_pnl = fields.read_double()?;
+            item.realized_pnl = fields.read_double()?;
+        }
+        if version >= 4 {
+            item.account = fields.read_string()?;
+        }
+        Ok(Response::PortfolioValue(Box::new(item)))
+    }
+
+    fn decode_tick_option_computation(&self, fields: &mut Fields) -> Result<Response, Error> {
+        // Servers that send the tick attribute no longer send a version
+        let version = if self.server_version < MIN_SERVER_VER_PRICE_BASED_VOLATILITY {
+            fields.read_int()?
+        } else {
+            i32::MAX
+        };
+        let request_id = fields.read_int()?;
+        let tick_type = read_tick_type(fields)?;
+        let tick_attribute = if self.server_version >= MIN_SERVER_VER_PRICE_BASED_VOLATILITY {
+            fields.read_int()?
+        } else {
+            0
+        };
+        // Values that weren't computed are sent as -1, or -2 for those that can be negative
+        let mut greeks = OptionGreeks {
+            tick_type,
+            tick_attribute,
+            implied_volatility: fields.read_double_max()?.filter(|v| *v >= 0.0),
+            delta: fields.read_double_max()?.filter(|v| *v != -2.0),
+            option_price: None,
+            pv_dividend: None,
+            gamma: None,
+            vega: None,
+            theta: None,
+            underlying_price: None,
+        };
+        if version >= 6
+            || tick_type == TickType::ModelOptionComputation
+            || tick_type == TickType::DelayedModelOptionComputation
+        {
+            greeks.option_price = fields.read_double_max()?.filter(|v| *v != -1.0);
+            greeks.pv_dividend = fields.read_double_max()?.filter(|v| *v != -1.0);
+        }
+        if version >= 6 {
+            greeks.gamma = fields.read_double_max()?.filter(|v| *v != -2.0);
+            greeks.vega = fields.read_double_max()?.filter(|v| *v != -2.0);
+            greeks.theta = fields.read_double_max()?.filter(|v| *v != -2.0);
+            greeks.underlying_price = fields.read_double_max()?.filter(|v| *v != -1.0);
+        }
+        Ok(Response::Tick {
+            request_id,
+            tick: Tick::OptionComputation(greeks),
+        })
+    }
+
+    fn decode_tick_by_tick(&self, fields: &mut Fields) -> Result<Response, Error> {
+        let request_id = fields.read_int()?;
+        let tick_type = fields.read_int()?;
+        let time = fields.read_long()?;
+        let tick = match tick_type {
+            1 | 2 => {
+                let price = fields.read_double()?;
+                let size = fields.read_decimal()?;
+                let mask = fields.read_int()?;
+                TickByTick::Trade {
+                    tick_type: if tick_type == 1 {
+                        TickByTickType::Last
+                    } else {
+                        TickByTickType::AllLast
+                    },
+                    time,
+                    price,
+                    size,
+                    attributes: TradeAttributes {
+                        past_limit: mask & 1 != 0,
+                        unreported: mask & 2 != 0,
+                    },
+                    exchange: fields.read_string()?,
+                    special_conditions: fields.read_string()?,
+                }
+            }
+            3 => {
+                let bid_price = fields.read_double()?;
+                let ask_price = fields.read_double()?;
+                let bid_size = fields.read_decimal()?;
+                let ask_size = fields.read_decimal()?;
+                let mask = fields.read_int()?;
+                TickByTick::BidAsk {
+                    time,
+                    bid_price,
+                    ask_price,
+                    bid_size,
+                    ask_size,
+                    attributes: BidAskAttributes {
+                        bid_past_low: mask & 1 != 0,
+                        ask_past_high: mask & 2 != 0,
+                    },
+                }
+            }
+            4 => TickByTick::MidPoint {
+                time,
+                mid_point: fields.read_double()?,
+            },
+            // 0 means no data
+            _ => return Ok(Response::Unhandled(InboundMessages::TickByTick)),
+        };
+        Ok(Response::TickByTick { request_id, tick })
+    }
+
+    fn decode_execution_data(&self, fields: &mut Fields) -> Result<Response, Error> {
+        if self.server_version < MIN_SERVER_VER_LAST_LIQUIDITY {
+            fields.skip()?;
+        }
+        let request_id = fields.read_int()?;
+        let order_id = fields.read_int()?;
+        let contract = Contract {
+            contract_id: fields.read_int()? as usize,
+            symbol: fields.read_string()?,
+            security_type: SecurityType::from(fields.read_string()?.as_str()),
+            last_trade_date_or_contract_month: fields.read_string()?,
+            strike: fields.read_decimal()?,
+            right: fields.read_string()?,
+            multiplier: fields.read_string()?,
+            exchange: fields.read_string()?,
+            currency: fields.read_string()?,
+            local_symbol: fields.read_string()?,
+            trading_class: fields.read_string()?,
+            ..Default::default()
+        };
+        let mut execution = Execution {
+            order_id,
+            exec_id: fields.read_string()?,
+            time: fields.read_string()?,
+            account_number: fields.read_string()?,
+            exchange: fields.read_string()?,
+            side: fields.read_string()?,
+            shares: fields.read_decimal()?,
+            price: fields.read_double()?,
+            perm_id: fields.read_long()?,
+            client_id: fields.read_int()?,
+            liquidation: fields.read_int()?,
+            cum_qty: fields.read_decimal()?,
+            avg_price: fields.read_double()?,
+            order_ref: fields.read_string()?,
+            ev_rule: fields.read_string()?,
+            ev_multiplier: fields.read_double_max()?,
+            ..Default::default()
+        };
+        if self.server_version >= MIN_SERVER_VER_MODELS_SUPPORT {
+            execution.model_code = fields.read_string()?;
+        }
+        if self.server_version >= MIN_SERVER_VER_LAST_LIQUIDITY {
+            execution.last_liquidity = fields.read_int()?;
+        }
+        Ok(Response::ExecutionData(Box::new(ExecutionDetails {
+            request_id,
+            contract,
+            execution,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(fields: &[&str]) -> InboundMessage {
+        let mut msg = InboundMessage::new();
+        for f in fields {
+            msg.add_field(IBField::IBString(f.to_string()));
+        }
+        msg
+    }
+
+    #[test]
+    fn decode_execution_and_commission_report() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "11",
+            "100000000",
+            "4",
+            // contract
+            "265598",
+            "AAPL",
+            "STK",
+            "",
+            "0",
+            "",
+            "",
+            "ISLAND",
+            "USD",
+            "AAPL",
+            "NMS",
+            // execution
+            "0000e0d5.5f8a1b2c.01.01",
+            "20201020  09:30:01",
+            "DU123",
+            "ISLAND",
+            "BOT",
+            "100",
+            "150.25",
+            "42",
+            "1",
+            "0",
+            "100",
+            "150.25",
+            "",
+            "",
+            "",
+            "",
+            "2",
+        ]);
+        let details = match decoder.decode(&msg).unwrap() {
+            Response::ExecutionData(details) => details,
+            other => panic!("Unexpected response {:?}", other),
+        };
+        assert_eq!(details.request_id, 100_000_000);
+        assert_eq!(details.contract.contract_id, 265598);
+        assert_eq!(details.execution.order_id, 4);
+        assert_eq!(details.execution.side, "BOT");
+        assert_eq!(details.execution.shares, Decimal::from(100));
+        assert_eq!(details.execution.perm_id, 42);
+        assert_eq!(details.execution.ev_multiplier, None);
+        assert_eq!(details.execution.last_liquidity, 2);
+
+        let msg = message(&[
+            "59",
+            "1",
+            "0000e0d5.5f8a1b2c.01.01",
+            "1.0",
+            "USD",
+            "1.7976931348623157E308",
+            "1.7976931348623157E308",
+            "0",
+        ]);
+        assert_eq!(
+            decoder.decode(&msg).unwrap(),
+            Response::CommissionReport(CommissionReport {
+                exec_id: "0000e0d5.5f8a1b2c.01.01".to_string(),
+                commission: 1.0,
+                currency: "USD".to_string(),
+                realized_pnl: None,
+                yield_amount: None,
+                yield_redemption_date: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn decode_tick_price_with_attributes() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&["1", "6", "100000001", "1", "150.25", "300", "5"]);
+        assert_eq!(
+            decoder.decode(&msg).unwrap(),
+            Response::Tick {
+                request_id: 100_000_001,
+                tick: Tick::Price {
+                    tick_type: TickType::Bid,
+                    price: 150.25,
+                    size: Some(Decimal::from(300)),
+                    attributes: TickAttributes {
+                        can_auto_execute: true,
+                        past_limit: false,
+                        pre_open: true,
+                    },
+                },
+            }
+        );
+
+        let msg = message(&["81", "100000001", "0.01", "9c0001", "3"]);
+        assert_eq!(
+            decoder.decode(&msg).unwrap(),
+            Response::Tick {
+                request_id: 100_000_001,
+                tick: Tick::ReqParams {
+                    min_tick: 0.01,
+                    bbo_exchange: "9c0001".to_string(),
+                    snapshot_permissions: 3,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn decode_market_depth_exchanges() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "80", "2", "ISLAND", "STK", "NASDAQ", "Deep2", "", "SMART", "STK", "", "Deep", "2",
+        ]);
+        let descriptions = match decoder.decode(&msg).unwrap() {
+            Response::MarketDepthExchanges(d) => d,
+            other => panic!("Unexpected response {:?}", other),
+        };
+        assert_eq!(descriptions.len(), 2);
+        assert_eq!(descriptions[0].service_data_type, "Deep2");
+        assert_eq!(descriptions[0].agg_group, None);
+        assert_eq!(descriptions[1].exchange, "SMART");
+        assert_eq!(descriptions[1].agg_group, Some(2));
+    }
+
+    #[test]
+    fn decode_tick_by_tick_trade() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "99",
+            "100000002",
+            "2",
+            "1603200601",
+            "150.25",
+            "100",
+            "2",
+            "ARCA",
+            "T",
+        ]);
+        assert_eq!(
+            decoder.decode(&msg).unwrap(),
+            Response::TickByTick {
+                request_id: 100_000_002,
+                tick: TickByTick::Trade {
+                    tick_type: TickByTickType::AllLast,
+                    time: 1_603_200_601,
+                    price: 150.25,
+                    size: Decimal::from(100),
+                    attributes: TradeAttributes {
+                        past_limit: false,
+                        unreported: true,
+                    },
+                    exchange: "ARCA".to_string(),
+                    special_conditions: "T".to_string(),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn decode_real_time_bar() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "50",
+            "3",
+            "100000003",
+            "1603200600",
+            "150.1",
+            "150.4",
+            "150",
+            "150.3",
+            "1200",
+            "150.22",
+            "17",
+        ]);
+        assert_eq!(
+            decoder.decode(&msg).unwrap(),
+            Response::RealTimeBar {
+                request_id: 100_000_003,
+                bar: Bar {
+                    time: "1603200600".to_string(),
+                    open: 150.1,
+                    high: 150.4,
+                    low: 150.0,
+                    close: 150.3,
+                    volume: Decimal::from(1200),
+                    wap: Decimal::new(15022, 2),
+                    count: 17,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn decode_historical_data() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "17",
+            "100000004",
+            "20201019 09:30:00",
+            "20201020 16:00:00",
+            "2",
+            "20201019",
+            "150",
+            "151",
+            "149",
+            "150.5",
+            "30000",
+            "150.2",
+            "400",
+            "20201020",
+            "150.5",
+            "152",
+            "150",
+            "151.5",
+            "25000",
+            "151.1",
+            "350",
+        ]);
+        let bars = match decoder.decode(&msg).unwrap() {
+            Response::HistoricalData { request_id, bars } => {
+                assert_eq!(request_id, 100_000_004);
+                bars
+            }
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[1].time, "20201020");
+        assert_eq!(bars[1].close, 151.5);
+        assert_eq!(bars[1].volume, Decimal::from(25000));
+        assert_eq!(bars[1].count, 350);
+
+        // updates send close before high and low, and wap before volume
+        let msg = message(&[
+            "90",
+            "100000004",
+            "12",
+            "20201020",
+            "150.5",
+            "151.6",
+            "152",
+            "150",
+            "151.2",
+            "26000",
+        ]);
+        match decoder.decode(&msg).unwrap() {
+            Response::HistoricalDataUpdate { bar, .. } => {
+                assert_eq!((bar.close, bar.high, bar.low), (151.6, 152.0, 150.0));
+                assert_eq!(bar.volume, Decimal::from(26000));
+                assert_eq!(bar.count, 12);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_historical_ticks_last() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "98",
+            "100000005",
+            "2",
+            "1603200600",
+            "0",
+            "150.1",
+            "100",
+            "ISLAND",
+            "",
+            "1603200601",
+            "1",
+            "150.2",
+            "5",
+            "FINRA",
+            "I",
+            "1",
+        ]);
+        match decoder.decode(&msg).unwrap() {
+            Response::HistoricalTicks {
+                ticks: HistoricalTicks::Last(ticks),
+                done,
+                ..
+            } => {
+                assert!(done);
+                assert_eq!(ticks.len(), 2);
+                assert_eq!(ticks[1].time, 1_603_200_601);
+                assert!(ticks[1].attributes.past_limit);
+                assert_eq!(ticks[1].exchange, "FINRA");
+                assert_eq!(ticks[1].special_conditions, "I");
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_histogram_data() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&["89", "100000006", "2", "150.25", "1200", "150.5", "300"]);
+        assert_eq!(
+            decoder.decode(&msg).unwrap(),
+            Response::HistogramData {
+                request_id: 100_000_006,
+                entries: vec![
+                    HistogramEntry {
+                        price: 150.25,
+                        size: Decimal::from(1200),
+                    },
+                    HistogramEntry {
+                        price: 150.5,
+                        size: Decimal::from(300),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn decode_option_computation_sentinels() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "21",
+            "100000007",
+            "13",
+            "1",
+            "0.31",
+            "0.55",
+            "7.25",
+            "-1",
+            "0.021",
+            "0.18",
+            "-2",
+            "120.5",
+        ]);
+        let greeks = match decoder.decode(&msg).unwrap() {
+            Response::Tick {
+                tick: Tick::OptionComputation(greeks),
+                ..
+            } => greeks,
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(greeks.tick_type, TickType::ModelOptionComputation);
+        assert_eq!(greeks.tick_attribute, 1);
+        assert_eq!(greeks.implied_volatility, Some(0.31));
+        assert_eq!(greeks.delta, Some(0.55));
+        assert_eq!(greeks.option_price, Some(7.25));
+        assert_eq!(greeks.pv_dividend, None);
+        assert_eq!(greeks.vega, Some(0.18));
+        assert_eq!(greeks.theta, None);
+        assert_eq!(greeks.underlying_price, Some(120.5));
+    }
+
+    #[test]
+    fn decode_portfolio_value() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "7", "8", "265598", "AAPL", "STK", "", "0", "", "", "NASDAQ", "USD", "AAPL", "NMS",
+            "100", "150.5", "15050", "140.25", "1025", "0", "DU123",
+        ]);
+        let item = match decoder.decode(&msg).unwrap() {
+            Response::PortfolioValue(item) => item,
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(item.contract.contract_id, 265_598);
+        assert_eq!(item.contract.primary_exchange, "NASDAQ");
+        assert_eq!(item.contract.trading_class, "NMS");
+        assert_eq!(item.position, Decimal::from(100));
+        assert_eq!(item.market_value, 15050.0);
+        assert_eq!(item.average_cost, 140.25);
+        assert_eq!(item.unrealized_pnl, 1025.0);
+        assert_eq!(item.account, "DU123");
+    }
+
+    #[test]
+    fn decode_position_multi() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "71",
+            "1",
+            "100000008",
+            "DU123",
+            "265598",
+            "AAPL",
+            "STK",
+            "",
+            "0",
+            "",
+            "",
+            "NASDAQ",
+            "USD",
+            "AAPL",
+            "NMS",
+            "12.5",
+            "140.25",
+            "GROWTH",
+        ]);
+        let (request_id, position) = match decoder.decode(&msg).unwrap() {
+            Response::PositionMulti {
+                request_id,
+                position,
+            } => (request_id, position),
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(request_id, 100_000_008);
+        assert_eq!(position.account, "DU123");
+        assert_eq!(position.contract.contract_id, 265_598);
+        assert_eq!(position.contract.trading_class, "NMS");
+        assert_eq!(position.position, Decimal::new(125, 1));
+        assert_eq!(position.average_cost, 140.25);
+        assert_eq!(position.model_code, "GROWTH");
+    }
+
+    #[test]
+    fn decode_account_update_multi_and_end() {
+        let decoder = Decoder::new(MAX_CLIENT_VER);
+        let msg = message(&[
+            "73",
+            "1",
+            "100000009",
+            "DU123",
+            "GROWTH",
+            "NetLiquidation",
+            "25000.75",
+            "USD",
+        ]);
+        assert_eq!(
+            decoder.decode(&msg).unwrap(),
+            Response::AccountUpdateMulti {
+                request_id: 100_000_009,
+                value: AccountValue {
+                    key: "NetLiquidation".to_string(),
+                    value: "25000.75".to_string(),
+                    currency: "USD".to_string(),
+                    account: "DU123".to_string(),
+                    model_code: "GROWTH".to_string(),
+                },
+            }
+        );
+        let msg = message(&["74", "1", "100000009"]);
+        assert_eq!(
+            decoder.decode(&msg).unwrap(),
+            Response::AccountUpdateMultiEnd(100_000_009)
+        );
     }
 }
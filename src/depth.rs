@@ -0,0 +1,273 @@
+//! Level 2 market depth: the row updates the server sends, and an order book maintained from them
+
+use crate::subscription::Subscription;
+use anyhow::*;
+use rust_decimal::prelude::*;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl DepthOperation {
+    pub(crate) fn from_id(id: i32) -> Result<DepthOperation, Error> {
+        match id {
+            0 => Ok(DepthOperation::Insert),
+            1 => Ok(DepthOperation::Update),
+            2 => Ok(DepthOperation::Delete),
+            other => Err(anyhow!("Unknown market depth operation: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthSide {
+    Ask,
+    Bid,
+}
+
+impl DepthSide {
+    pub(crate) fn from_id(id: i32) -> Result<DepthSide, Error> {
+        match id {
+            0 => Ok(DepthSide::Ask),
+            1 => Ok(DepthSide::Bid),
+            other => Err(anyhow!("Unknown market depth side: {}", other)),
+        }
+    }
+}
+
+/// A change to one row of the book. `market_maker` is the exchange or market maker quoting the
+/// row, and is empty for depth from a single exchange.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthUpdate {
+    pub position: usize,
+    pub market_maker: String,
+    pub operation: DepthOperation,
+    pub side: DepthSide,
+    pub price: f64,
+    pub size: Decimal,
+    pub is_smart_depth: bool,
+}
+
+/// What a depth subscription delivers
+#[derive(Debug, Clone, PartialEq)]
+pub enum DepthEvent {
+    Update(DepthUpdate),
+    /// The server discarded its book; everything received so far is stale
+    Reset,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthLevel {
+    pub price: f64,
+    pub size: Decimal,
+    pub market_maker: String,
+}
+
+/// Bid and ask ladders, best price first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderBook {
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+impl OrderBook {
+    /// Applies an update by row position. Positions past the end of the ladder, which the server
+    /// occasionally sends, are appended or ignored rather than treated as errors.
+    pub fn apply(&mut self, update: &DepthUpdate) {
+        let rows = match update.side {
+            DepthSide::Bid => &mut self.bids,
+            DepthSide::Ask => &mut self.asks,
+        };
+        let level = DepthLevel {
+            price: update.price,
+            size: update.size,
+            market_maker: update.market_maker.clone(),
+        };
+        let position = update.position;
+        match update.operation {
+            DepthOperation::Insert => rows.insert(position.min(rows.len()), level),
+            DepthOperation::Update if position < rows.len() => rows[position] = level,
+            DepthOperation::Update => rows.push(level),
+            DepthOperation::Delete if position < rows.len() => {
+                rows.remove(position);
+            }
+            DepthOperation::Delete => {}
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    pub fn best_bid(&self) -> Option<&DepthLevel> {
+        self.bids.first()
+    }
+
+    pub fn best_ask(&self) -> Option<&DepthLevel> {
+        self.asks.first()
+    }
+
+    pub fn spread(&self) -> Option<f64> {
+        Some(self.best_ask()?.price - self.best_bid()?.price)
+    }
+
+    pub fn mid(&self) -> Option<f64> {
+        Some((self.best_ask()?.price + self.best_bid()?.price) / 2.0)
+    }
+
+    /// (bid size - ask size) / (bid size + ask size) over the top `levels` rows of each side,
+    /// from -1 when only asks are quoted to 1 when only bids are
+    pub fn imbalance(&self, levels: usize) -> Option<f64> {
+        let bid: Decimal = self.bids.iter().take(levels).map(|l| l.size).sum();
+        let ask: Decimal = self.asks.iter().take(levels).map(|l| l.size).sum();
+        let total = bid + ask;
+        if total.is_zero() {
+            return None;
+        }
+        ((bid - ask) / total).to_f64()
+    }
+
+    /// The running total of size at each price level of one side, best price first
+    pub fn cumulative_depth(&self, side: DepthSide) -> Vec<(f64, Decimal)> {
+        let rows = match side {
+            DepthSide::Bid => &self.bids,
+            DepthSide::Ask => &self.asks,
+        };
+        let mut total = Decimal::zero();
+        rows.iter()
+            .map(|l| {
+                total += l.size;
+                (l.price, total)
+            })
+            .collect()
+    }
+}
+
+/// An exchange that offers market depth, from `req_mkt_depth_exchanges`. `service_data_type` is
+/// `Deep` for depth from a single exchange or `Deep2` for a consolidated book.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DepthMktDataDescription {
+    pub exchange: String,
+    pub security_type: String,
+    pub listing_exchange: String,
+    pub service_data_type: String,
+    pub agg_group: Option<i32>,
+}
+
+/// A depth subscription with the book it maintains. Updates are applied on the caller's thread
+/// when it polls, so `book()` is always a consistent view. Dropping it cancels the subscription.
+pub struct MarketDepth {
+    subscription: Subscription<DepthEvent>,
+    book: OrderBook,
+}
+
+impl MarketDepth {
+    pub(crate) fn new(subscription: Subscription<DepthEvent>) -> MarketDepth {
+        MarketDepth {
+            subscription,
+            book: OrderBook::default(),
+        }
+    }
+
+    pub fn request_id(&self) -> i32 {
+        self.subscription.request_id()
+    }
+
+    /// The book as of the last `poll` or `wait`
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    /// Applies every update that has arrived. Returns how many were applied.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        let mut applied = 0;
+        while let Some(event) = self.subscription.try_next() {
+            self.apply(event?);
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Waits up to `timeout` for at least one update, then applies everything that has arrived.
+    /// Returns how many updates were applied.
+    pub fn wait(&mut self, timeout: Duration) -> Result<usize, Error> {
+        match self.subscription.next_timeout(timeout) {
+            Some(event) => {
+                self.apply(event?);
+                Ok(1 + self.poll()?)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn apply(&mut self, event: DepthEvent) {
+        match event {
+            DepthEvent::Update(update) => self.book.apply(&update),
+            DepthEvent::Reset => self.book.clear(),
+        }
+    }
+
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        self.subscription.cancel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(
+        operation: DepthOperation,
+        side: DepthSide,
+        position: usize,
+        price: f64,
+        size: i64,
+    ) -> DepthUpdate {
+        DepthUpdate {
+            position,
+            market_maker: "ISLAND".to_string(),
+            operation,
+            side,
+            price,
+            size: Decimal::from(size),
+            is_smart_depth: true,
+        }
+    }
+
+    #[test]
+    fn maintain_book_from_updates() {
+        use DepthOperation::*;
+        use DepthSide::*;
+
+        let mut book = OrderBook::default();
+        book.apply(&update(Insert, Bid, 0, 100.0, 300));
+        book.apply(&update(Insert, Bid, 1, 99.9, 100));
+        book.apply(&update(Insert, Ask, 0, 100.2, 100));
+        book.apply(&update(Insert, Bid, 0, 100.1, 200));
+        book.apply(&update(Update, Bid, 2, 99.9, 500));
+        book.apply(&update(Delete, Ask, 3, 0.0, 0));
+
+        let prices: Vec<f64> = book.bids.iter().map(|l| l.price).collect();
+        assert_eq!(prices, vec![100.1, 100.0, 99.9]);
+        assert_eq!(book.bids[2].size, Decimal::from(500));
+        assert!((book.spread().unwrap() - 0.1).abs() < 1e-9);
+        // (200 - 100) / (200 + 100) over the top level
+        assert!((book.imbalance(1).unwrap() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            book.cumulative_depth(Bid),
+            vec![
+                (100.1, Decimal::from(200)),
+                (100.0, Decimal::from(500)),
+                (99.9, Decimal::from(1000))
+            ]
+        );
+
+        book.apply(&update(Delete, Bid, 0, 100.1, 200));
+        assert_eq!(book.best_bid().unwrap().price, 100.0);
+    }
+}
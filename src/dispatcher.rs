@@ -0,0 +1,386 @@
+//! Routes decoded responses to whoever is waiting for them: the order tracker, or the channel a
+//! request registered for its request id.
+
+use crate::decoder::Response;
+use crate::execution::CommissionReport;
+use crate::order::OrderId;
+use crate::order_tracker::OrderTracker;
+use crate::quote::QuoteCache;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::*;
+use std::collections::{HashMap, VecDeque};
+
+/// Identifies where a response should go. Most responses carry the id of the request they answer;
+/// the rest go to whichever single request of that kind is outstanding.
+/// How many commission reports are kept before the oldest are dropped. Executions are normally
+/// requested soon after they happen, so this only bounds long running sessions.
+const MAX_COMMISSION_REPORTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteKey {
+    Request(i32),
+    NextValidId,
+    OpenOrders,
+    CompletedOrders,
+    MarketDepthExchanges,
+    CurrentTime,
+    /// The server allows one account update subscription at a time
+    AccountUpdates,
+    Positions,
+}
+
+pub struct Dispatcher {
+    routes: HashMap<RouteKey, Sender<Response>>,
+    /// Which registration each shared route currently belongs to, so a subscription replaced by
+    /// a newer one doesn't remove the newer one's route
+    owners: HashMap<RouteKey, u64>,
+    next_owner: u64,
+    orders: OrderTracker,
+    quotes: QuoteCache,
+    /// Commission reports carry only the exec id of the execution they belong to, so the most
+    /// recent ones are kept for whichever request asks about that execution
+    commission_reports: HashMap<String, CommissionReport>,
+    /// Exec ids of the kept commission reports, oldest first
+    commission_report_order: VecDeque<String>,
+    next_valid_id: Option<OrderId>,
+    managed_accounts: Vec<String>,
+}
+
+impl Dispatcher {
+    pub fn new(client_id: i32) -> Dispatcher {
+        Dispatcher {
+            routes: HashMap::new(),
+            owners: HashMap::new(),
+            next_owner: 0,
+            orders: OrderTracker::new(client_id),
+            quotes: QuoteCache::new(),
+            commission_reports: HashMap::new(),
+            commission_report_order: VecDeque::new(),
+            next_valid_id: None,
+            managed_accounts: vec![],
+        }
+    }
+
+    /// Registers a route, replacing any previous one with the same key
+    pub fn register(&mut self, key: RouteKey) -> Receiver<Response> {
+        let (tx, rx) = unbounded();
+        self.routes.insert(key, tx);
+        self.owners.remove(&key);
+        rx
+    }
+
+    /// Registers a route like `register`, along with a token that `unregister_owned` checks to
+    /// tell whether the route has since been taken over
+    pub fn register_owned(&mut self, key: RouteKey) -> (u64, Receiver<Response>) {
+        let rx = self.register(key);
+        self.next_owner += 1;
+        self.owners.insert(key, self.next_owner);
+        (self.next_owner, rx)
+    }
+
+    /// Removes a route registered with `register_owned` if it hasn't been replaced since.
+    /// Returns whether it was still ours.
+    pub fn unregister_owned(&mut self, key: RouteKey, owner: u64) -> bool {
+        if self.owners.get(&key) != Some(&owner) {
+            return false;
+        }
+        self.unregister(key);
+        true
+    }
+
+    /// Registers several routes that all feed the same channel, unless any of them is already
+    /// taken, in which case nothing is registered and `None` is returned
+    pub fn register_vacant(&mut self, keys: &[RouteKey]) -> Option<Receiver<Response>> {
+        if keys.iter().any(|key| self.routes.contains_key(key)) {
+            return None;
+        }
+        let (tx, rx) = unbounded();
+        for key in keys {
+            self.routes.insert(*key, tx.clone());
+        }
+        Some(rx)
+    }
+
+    pub fn unregister(&mut self, key: RouteKey) {
+        self.routes.remove(&key);
+        self.owners.remove(&key);
+    }
+
+    pub fn orders(&self) -> &OrderTracker {
+        &self.orders
+    }
+
+    pub fn orders_mut(&mut self) -> &mut OrderTracker {
+        &mut self.orders
+    }
+
+    pub fn quotes_mut(&mut self) -> &mut QuoteCache {
+        &mut self.quotes
+    }
+
+    pub fn next_valid_id(&self) -> Option<OrderId> {
+        self.next_valid_id
+    }
+
+    /// Hands out the next order id and advances the counter
+    pub fn take_order_id(&mut self) -> Option<OrderId> {
+        let id = self.next_valid_id?;
+        self.next_valid_id = Some(id + 1);
+        Some(id)
+    }
+
+    pub fn managed_accounts(&self) -> &[String] {
+        &self.managed_accounts
+    }
+
+    pub fn commission_report(&self, exec_id: &str) -> Option<CommissionReport> {
+        self.commission_reports.get(exec_id).cloned()
+    }
+
+    pub fn dispatch(&mut self, response: Response) {
+        match &response {
+            Response::NextValidId(id) => {
+                self.next_valid_id = Some(*id);
+                self.send(RouteKey::NextValidId, response);
+            }
+            Response::ManagedAccounts(accounts) => {
+                self.managed_accounts = accounts.clone();
+            }
+            Response::CurrentTime(_) => {
+                self.send(RouteKey::CurrentTime, response);
+            }
+            Response::Error(error) => {
+                let for_order = self.orders.handle(&response);
+                let for_quote = self.quotes.handle(&response);
+                let for_request = self.send(RouteKey::Request(error.id), response.clone());
+                if !for_order && !for_quote && !for_request {
+                    if error.is_warning() {
+                        info!("{}", error);
+                    } else {
+                        error!("{}", error);
+                    }
+                }
+            }
+            Response::OrderStatus(_) | Response::OrderBound(_) => {
+                self.orders.handle(&response);
+            }
+            Response::OpenOrder(_) => {
+                self.orders.handle(&response);
+                self.send(RouteKey::OpenOrders, response);
+            }
+            Response::OpenOrderEnd => {
+                self.send(RouteKey::OpenOrders, response);
+            }
+            Response::ExecutionData(details) => {
+                let key = RouteKey::Request(details.request_id);
+                self.send(key, response);
+            }
+            Response::ExecutionDataEnd(request_id) => {
+                self.send(RouteKey::Request(*request_id), response);
+            }
+            Response::Tick { request_id, .. } => {
+                if !self.quotes.handle(&response) {
+                    self.send(RouteKey::Request(*request_id), response);
+                }
+            }
+            Response::TickByTick { request_id, .. }
+            | Response::RealTimeBar { request_id, .. }
+            | Response::HistoricalData { request_id, .. }
+            | Response::HistoricalDataUpdate { request_id, .. }
+            | Response::HeadTimestamp { request_id, .. }
+            | Response::HistogramData { request_id, .. }
+            | Response::SecurityDefinitionOptionParameter { request_id, .. }
+            | Response::SecurityDefinitionOptionParameterEnd(request_id)
+            | Response::HistoricalTicks { request_id, .. }
+            | Response::AccountSummary { request_id, .. }
+            | Response::AccountSummaryEnd(request_id)
+            | Response::PositionMulti { request_id, .. }
+            | Response::PositionMultiEnd(request_id)
+            | Response::AccountUpdateMulti { request_id, .. }
+            | Response::AccountUpdateMultiEnd(request_id)
+            | Response::MarketDepth { request_id, .. }
+            | Response::MarketDepthReset(request_id) => {
+                self.send(RouteKey::Request(*request_id), response);
+            }
+            Response::AccountValue(_)
+            | Response::PortfolioValue(_)
+            | Response::AccountUpdateTime(_)
+            | Response::AccountDownloadEnd(_) => {
+                self.send(RouteKey::AccountUpdates, response);
+            }
+            Response::Position(_) | Response::PositionEnd => {
+                self.send(RouteKey::Positions, response);
+            }
+            Response::MarketDepthExchanges(_) => {
+                self.send(RouteKey::MarketDepthExchanges, response);
+            }
+            Response::CompletedOrder(_) | Response::CompletedOrdersEnd => {
+                self.send(RouteKey::CompletedOrders, response);
+            }
+            Response::CommissionReport(report) => {
+                self.keep_commission_report(report.clone());
+            }
+            Response::Unhandled(kind) => {
+                debug!("Ignoring unhandled message: {:?}", kind);
+            }
+        }
+    }
+
+    fn keep_commission_report(&mut self, report: CommissionReport) {
+        let exec_id = report.exec_id.clone();
+        if self
+            .commission_reports
+            .insert(exec_id.clone(), report)
+            .is_none()
+        {
+            self.commission_report_order.push_back(exec_id);
+        }
+        while self.commission_report_order.len() > MAX_COMMISSION_REPORTS {
+            if let Some(oldest) = self.commission_report_order.pop_front() {
+                self.commission_reports.remove(&oldest);
+            }
+        }
+    }
+
+    /// Sends a response down a route. Returns whether anyone was listening.
+    fn send(&mut self, key: RouteKey, response: Response) -> bool {
+        match self.routes.get(&key) {
+            Some(tx) => {
+                if tx.send(response).is_err() {
+                    self.unregister(key);
+                    return false;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::Contract;
+    use crate::decoder::TwsError;
+    use crate::execution::{Execution, ExecutionDetails};
+    use crate::order::{OpenOrder, Order, OrderState};
+
+    #[test]
+    fn open_orders_reach_route_and_tracker() {
+        let mut dispatcher = Dispatcher::new(1);
+        let rx = dispatcher.register(RouteKey::OpenOrders);
+        let open_order = OpenOrder {
+            order_id: 5,
+            contract: Contract::stock("AAPL", "SMART", "USD"),
+            order: Order {
+                client_id: 1,
+                perm_id: 42,
+                ..Default::default()
+            },
+            order_state: OrderState {
+                status: "Submitted".to_string(),
+                ..Default::default()
+            },
+        };
+        dispatcher.dispatch(Response::OpenOrder(Box::new(open_order.clone())));
+        dispatcher.dispatch(Response::OpenOrderEnd);
+
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Response::OpenOrder(Box::new(open_order))
+        );
+        assert_eq!(rx.try_recv().unwrap(), Response::OpenOrderEnd);
+        assert_eq!(dispatcher.orders().get(5).unwrap().perm_id, 42);
+    }
+
+    #[test]
+    fn commission_reports_outlive_overlapping_execution_requests() {
+        let mut dispatcher = Dispatcher::new(1);
+        let first = dispatcher.register(RouteKey::Request(1));
+        let second = dispatcher.register(RouteKey::Request(2));
+        let execution = |request_id, exec_id: &str| {
+            Response::ExecutionData(Box::new(ExecutionDetails {
+                request_id,
+                contract: Contract::stock("AAPL", "SMART", "USD"),
+                execution: Execution {
+                    exec_id: exec_id.to_string(),
+                    ..Default::default()
+                },
+            }))
+        };
+        let report = |exec_id: &str| {
+            Response::CommissionReport(CommissionReport {
+                exec_id: exec_id.to_string(),
+                commission: 1.0,
+                ..Default::default()
+            })
+        };
+        dispatcher.dispatch(execution(1, "0001"));
+        dispatcher.dispatch(report("0001"));
+        dispatcher.dispatch(execution(2, "0001"));
+        dispatcher.dispatch(report("0001"));
+        dispatcher.dispatch(execution(2, "0002"));
+        dispatcher.dispatch(report("0002"));
+        dispatcher.dispatch(Response::ExecutionDataEnd(1));
+        // The first request finishing doesn't take the second one's reports with it
+        dispatcher.unregister(RouteKey::Request(1));
+        dispatcher.dispatch(Response::ExecutionDataEnd(2));
+
+        assert_eq!(first.try_iter().count(), 2);
+        assert_eq!(second.try_iter().count(), 3);
+        for exec_id in ["0001", "0002"] {
+            assert_eq!(
+                dispatcher.commission_report(exec_id).unwrap().exec_id,
+                exec_id
+            );
+        }
+    }
+
+    #[test]
+    fn vacant_registration_leaves_taken_routes_alone() {
+        let mut dispatcher = Dispatcher::new(1);
+        let existing = dispatcher.register(RouteKey::Request(-1));
+        assert!(dispatcher
+            .register_vacant(&[RouteKey::Request(-1), RouteKey::CurrentTime])
+            .is_none());
+        assert!(!dispatcher.routes.contains_key(&RouteKey::CurrentTime));
+
+        dispatcher.dispatch(Response::Error(TwsError {
+            id: -1,
+            code: 1100,
+            message: "Connectivity lost".to_string(),
+        }));
+        assert_eq!(existing.try_iter().count(), 1);
+
+        dispatcher.unregister(RouteKey::Request(-1));
+        let responses = dispatcher
+            .register_vacant(&[RouteKey::Request(-1), RouteKey::CurrentTime])
+            .unwrap();
+        dispatcher.dispatch(Response::CurrentTime(1));
+        assert_eq!(responses.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn oldest_commission_reports_are_dropped() {
+        let mut dispatcher = Dispatcher::new(1);
+        let report = |exec_id: String| {
+            Response::CommissionReport(CommissionReport {
+                exec_id,
+                ..Default::default()
+            })
+        };
+        for i in 0..=MAX_COMMISSION_REPORTS {
+            dispatcher.dispatch(report(format!("{:05}", i)));
+        }
+        // A repeated report replaces the kept one rather than taking another slot
+        dispatcher.dispatch(report(format!("{:05}", MAX_COMMISSION_REPORTS)));
+
+        assert_eq!(dispatcher.commission_reports.len(), MAX_COMMISSION_REPORTS);
+        assert_eq!(
+            dispatcher.commission_report_order.len(),
+            MAX_COMMISSION_REPORTS
+        );
+        assert!(dispatcher.commission_report("00000").is_none());
+        assert!(dispatcher.commission_report("00001").is_some());
+    }
+}
@@ -0,0 +1,76 @@
+//! Executions (fills) and the commission reports that follow them
+
+use crate::contract::Contract;
+use crate::order::{OrderId, PermId};
+use rust_decimal::Decimal;
+
+/// Narrows down which executions `req_executions` returns. Empty fields and a client id of 0
+/// match everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecutionFilter {
+    pub client_id: i32,
+    pub account_code: String,
+    /// Only executions after this time, as `yyyymmdd hh:mm:ss`
+    pub time: String,
+    pub symbol: String,
+    pub security_type: String,
+    pub exchange: String,
+    /// `BUY` or `SELL`
+    pub side: String,
+}
+
+/// A single fill. `side` is `BOT` or `SLD`.
+///
+/// The MiFID II decision maker and execution trader are not part of executions at the server
+/// versions we negotiate; they are on the order, which can be looked up by `perm_id`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Execution {
+    pub exec_id: String,
+    pub order_id: OrderId,
+    pub client_id: i32,
+    pub perm_id: PermId,
+    pub time: String,
+    pub account_number: String,
+    pub exchange: String,
+    pub side: String,
+    pub shares: Decimal,
+    pub price: f64,
+    pub liquidation: i32,
+    pub cum_qty: Decimal,
+    pub avg_price: f64,
+    pub order_ref: String,
+    pub ev_rule: String,
+    pub ev_multiplier: Option<f64>,
+    pub model_code: String,
+    /// 1 if the fill added liquidity, 2 if it removed liquidity, 3 if it was routed out
+    pub last_liquidity: i32,
+}
+
+/// An execution as sent by the server, along with the request it answers. Executions sent as
+/// orders fill carry a request id of -1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionDetails {
+    pub request_id: i32,
+    pub contract: Contract,
+    pub execution: Execution,
+}
+
+/// Commission and realized P&L for one execution
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommissionReport {
+    pub exec_id: String,
+    pub commission: f64,
+    pub currency: String,
+    pub realized_pnl: Option<f64>,
+    pub yield_amount: Option<f64>,
+    /// `yyyymmdd`, or 0 if the instrument has no redemption date
+    pub yield_redemption_date: i32,
+}
+
+/// An execution joined with its commission report, if the server sent one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionReport {
+    pub contract: Contract,
+    pub execution: Execution,
+    pub commission_report: Option<CommissionReport>,
+}
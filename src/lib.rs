@@ -1,10 +1,22 @@
+pub mod account;
+pub mod backfill;
+pub mod bars;
 pub mod client;
+pub mod contract;
+pub mod decoder;
+pub mod depth;
+pub mod dispatcher;
+pub mod execution;
+pub mod market_data;
 pub mod message;
+pub mod options;
+pub mod order;
+pub mod order_decoder;
+pub mod order_encoder;
+pub mod order_tracker;
+pub mod quote;
+pub mod reader;
+pub mod server_versions;
 pub mod socket;
+pub mod subscription;
 pub mod traits;
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    fn create_connection() {}
-}
@@ -0,0 +1,633 @@
+//! Top-of-book market data from `req_mkt_data`: tick types, tick attributes and the ticks
+//! themselves, along with tick-by-tick and historical ticks
+
+use anyhow::*;
+use rust_decimal::Decimal;
+use std::convert::TryFrom;
+
+/// What a tick reports. The discriminants are the ids the server uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TickType {
+    BidSize = 0,
+    Bid = 1,
+    Ask = 2,
+    AskSize = 3,
+    Last = 4,
+    LastSize = 5,
+    High = 6,
+    Low = 7,
+    Volume = 8,
+    Close = 9,
+    BidOptionComputation = 10,
+    AskOptionComputation = 11,
+    LastOptionComputation = 12,
+    ModelOptionComputation = 13,
+    Open = 14,
+    Low13Week = 15,
+    High13Week = 16,
+    Low26Week = 17,
+    High26Week = 18,
+    Low52Week = 19,
+    High52Week = 20,
+    AvgVolume = 21,
+    OpenInterest = 22,
+    OptionHistoricalVol = 23,
+    OptionImpliedVol = 24,
+    OptionBidExch = 25,
+    OptionAskExch = 26,
+    OptionCallOpenInterest = 27,
+    OptionPutOpenInterest = 28,
+    OptionCallVolume = 29,
+    OptionPutVolume = 30,
+    IndexFuturePremium = 31,
+    BidExch = 32,
+    AskExch = 33,
+    AuctionVolume = 34,
+    AuctionPrice = 35,
+    AuctionImbalance = 36,
+    MarkPrice = 37,
+    BidEfpComputation = 38,
+    AskEfpComputation = 39,
+    LastEfpComputation = 40,
+    OpenEfpComputation = 41,
+    HighEfpComputation = 42,
+    LowEfpComputation = 43,
+    CloseEfpComputation = 44,
+    LastTimestamp = 45,
+    Shortable = 46,
+    FundamentalRatios = 47,
+    RtVolume = 48,
+    Halted = 49,
+    BidYield = 50,
+    AskYield = 51,
+    LastYield = 52,
+    CustOptionComputation = 53,
+    TradeCount = 54,
+    TradeRate = 55,
+    VolumeRate = 56,
+    LastRthTrade = 57,
+    RtHistoricalVol = 58,
+    IbDividends = 59,
+    BondFactorMultiplier = 60,
+    RegulatoryImbalance = 61,
+    NewsTick = 62,
+    ShortTermVolume3Min = 63,
+    ShortTermVolume5Min = 64,
+    ShortTermVolume10Min = 65,
+    DelayedBid = 66,
+    DelayedAsk = 67,
+    DelayedLast = 68,
+    DelayedBidSize = 69,
+    DelayedAskSize = 70,
+    DelayedLastSize = 71,
+    DelayedHigh = 72,
+    DelayedLow = 73,
+    DelayedVolume = 74,
+    DelayedClose = 75,
+    DelayedOpen = 76,
+    RtTrdVolume = 77,
+    CreditmanMarkPrice = 78,
+    CreditmanSlowMarkPrice = 79,
+    DelayedBidOptionComputation = 80,
+    DelayedAskOptionComputation = 81,
+    DelayedLastOptionComputation = 82,
+    DelayedModelOptionComputation = 83,
+    LastExch = 84,
+    LastRegTime = 85,
+    FuturesOpenInterest = 86,
+    AvgOptVolume = 87,
+    DelayedLastTimestamp = 88,
+    ShortableShares = 89,
+    DelayedHalted = 90,
+    Reuters2MutualFunds = 91,
+    EtfNavClose = 92,
+    EtfNavPriorClose = 93,
+    EtfNavBid = 94,
+    EtfNavAsk = 95,
+    EtfNavLast = 96,
+    EtfFrozenNavLast = 97,
+    EtfNavHigh = 98,
+    EtfNavLow = 99,
+    SocialMarketAnalytics = 100,
+    EstimatedIpoMidpoint = 101,
+    FinalIpoLast = 102,
+}
+
+impl TickType {
+    pub fn id(self) -> i32 {
+        self as i32
+    }
+
+    /// Price ticks for the bid, ask and last carry the matching size as well
+    pub fn size_tick_type(self) -> Option<TickType> {
+        match self {
+            TickType::Bid => Some(TickType::BidSize),
+            TickType::Ask => Some(TickType::AskSize),
+            TickType::Last => Some(TickType::LastSize),
+            TickType::DelayedBid => Some(TickType::DelayedBidSize),
+            TickType::DelayedAsk => Some(TickType::DelayedAskSize),
+            TickType::DelayedLast => Some(TickType::DelayedLastSize),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<i32> for TickType {
+    type Error = Error;
+
+    fn try_from(id: i32) -> Result<Self, Self::Error> {
+        match id {
+            0 => Ok(TickType::BidSize),
+            1 => Ok(TickType::Bid),
+            2 => Ok(TickType::Ask),
+            3 => Ok(TickType::AskSize),
+            4 => Ok(TickType::Last),
+            5 => Ok(TickType::LastSize),
+            6 => Ok(TickType::High),
+            7 => Ok(TickType::Low),
+            8 => Ok(TickType::Volume),
+            9 => Ok(TickType::Close),
+            10 => Ok(TickType::BidOptionComputation),
+            11 => Ok(TickType::AskOptionComputation),
+            12 => Ok(TickType::LastOptionComputation),
+            13 => Ok(TickType::ModelOptionComputation),
+            14 => Ok(TickType::Open),
+            15 => Ok(TickType::Low13Week),
+            16 => Ok(TickType::High13Week),
+            17 => Ok(TickType::Low26Week),
+            18 => Ok(TickType::High26Week),
+            19 => Ok(TickType::Low52Week),
+            20 => Ok(TickType::High52Week),
+            21 => Ok(TickType::AvgVolume),
+            22 => Ok(TickType::OpenInterest),
+            23 => Ok(TickType::OptionHistoricalVol),
+            24 => Ok(TickType::OptionImpliedVol),
+            25 => Ok(TickType::OptionBidExch),
+            26 => Ok(TickType::OptionAskExch),
+            27 => Ok(TickType::OptionCallOpenInterest),
+            28 => Ok(TickType::OptionPutOpenInterest),
+            29 => Ok(TickType::OptionCallVolume),
+            30 => Ok(TickType::OptionPutVolume),
+            31 => Ok(TickType::IndexFuturePremium),
+            32 => Ok(TickType::BidExch),
+            33 => Ok(TickType::AskExch),
+            34 => Ok(TickType::AuctionVolume),
+            35 => Ok(TickType::AuctionPrice),
+            36 => Ok(TickType::AuctionImbalance),
+            37 => Ok(TickType::MarkPrice),
+            38 => Ok(TickType::BidEfpComputation),
+            39 => Ok(TickType::AskEfpComputation),
+            40 => Ok(TickType::LastEfpComputation),
+            41 => Ok(TickType::OpenEfpComputation),
+            42 => Ok(TickType::HighEfpComputation),
+            43 => Ok(TickType::LowEfpComputation),
+            44 => Ok(TickType::CloseEfpComputation),
+            45 => Ok(TickType::LastTimestamp),
+            46 => Ok(TickType::Shortable),
+            47 => Ok(TickType::FundamentalRatios),
+            48 => Ok(TickType::RtVolume),
+            49 => Ok(TickType::Halted),
+            50 => Ok(TickType::BidYield),
+            51 => Ok(TickType::AskYield),
+            52 => Ok(TickType::LastYield),
+            53 => Ok(TickType::CustOptionComputation),
+            54 => Ok(TickType::TradeCount),
+            55 => Ok(TickType::TradeRate),
+            56 => Ok(TickType::VolumeRate),
+            57 => Ok(TickType::LastRthTrade),
+            58 => Ok(TickType::RtHistoricalVol),
+            59 => Ok(TickType::IbDividends),
+            60 => Ok(TickType::BondFactorMultiplier),
+            61 => Ok(TickType::RegulatoryImbalance),
+            62 => Ok(TickType::NewsTick),
+            63 => Ok(TickType::ShortTermVolume3Min),
+            64 => Ok(TickType::ShortTermVolume5Min),
+            65 => Ok(TickType::ShortTermVolume10Min),
+            66 => Ok(TickType::DelayedBid),
+            67 => Ok(TickType::DelayedAsk),
+            68 => Ok(TickType::DelayedLast),
+            69 => Ok(TickType::DelayedBidSize),
+            70 => Ok(TickType::DelayedAskSize),
+            71 => Ok(TickType::DelayedLastSize),
+            72 => Ok(TickType::DelayedHigh),
+            73 => Ok(TickType::DelayedLow),
+            74 => Ok(TickType::DelayedVolume),
+            75 => Ok(TickType::DelayedClose),
+            76 => Ok(TickType::DelayedOpen),
+            77 => Ok(TickType::RtTrdVolume),
+            78 => Ok(TickType::CreditmanMarkPrice),
+            79 => Ok(TickType::CreditmanSlowMarkPrice),
+            80 => Ok(TickType::DelayedBidOptionComputation),
+            81 => Ok(TickType::DelayedAskOptionComputation),
+            82 => Ok(TickType::DelayedLastOptionComputation),
+            83 => Ok(TickType::DelayedModelOptionComputation),
+            84 => Ok(TickType::LastExch),
+            85 => Ok(TickType::LastRegTime),
+            86 => Ok(TickType::FuturesOpenInterest),
+            87 => Ok(TickType::AvgOptVolume),
+            88 => Ok(TickType::DelayedLastTimestamp),
+            89 => Ok(TickType::ShortableShares),
+            90 => Ok(TickType::DelayedHalted),
+            91 => Ok(TickType::Reuters2MutualFunds),
+            92 => Ok(TickType::EtfNavClose),
+            93 => Ok(TickType::EtfNavPriorClose),
+            94 => Ok(TickType::EtfNavBid),
+            95 => Ok(TickType::EtfNavAsk),
+            96 => Ok(TickType::EtfNavLast),
+            97 => Ok(TickType::EtfFrozenNavLast),
+            98 => Ok(TickType::EtfNavHigh),
+            99 => Ok(TickType::EtfNavLow),
+            100 => Ok(TickType::SocialMarketAnalytics),
+            101 => Ok(TickType::EstimatedIpoMidpoint),
+            102 => Ok(TickType::FinalIpoLast),
+            other => Err(anyhow!("Unknown tick type: {}", other)),
+        }
+    }
+}
+
+/// Which kind of data a subscription delivers. Frozen data is the last value from the close,
+/// for when the market is shut; delayed data needs no market data subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MarketDataType {
+    Live = 1,
+    Frozen = 2,
+    Delayed = 3,
+    DelayedFrozen = 4,
+}
+
+impl MarketDataType {
+    pub fn id(self) -> i32 {
+        self as i32
+    }
+
+    pub fn is_delayed(self) -> bool {
+        matches!(
+            self,
+            MarketDataType::Delayed | MarketDataType::DelayedFrozen
+        )
+    }
+}
+
+impl TryFrom<i32> for MarketDataType {
+    type Error = Error;
+
+    fn try_from(id: i32) -> Result<Self, Self::Error> {
+        match id {
+            1 => Ok(MarketDataType::Live),
+            2 => Ok(MarketDataType::Frozen),
+            3 => Ok(MarketDataType::Delayed),
+            4 => Ok(MarketDataType::DelayedFrozen),
+            other => Err(anyhow!("Unknown market data type: {}", other)),
+        }
+    }
+}
+
+/// Flags sent with price ticks
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TickAttributes {
+    /// The bid or ask can be executed against automatically
+    pub can_auto_execute: bool,
+    /// The bid is below, or the ask above, the day's limit price
+    pub past_limit: bool,
+    /// The quote is from the pre-open session
+    pub pre_open: bool,
+}
+
+impl TickAttributes {
+    pub(crate) fn from_mask(mask: i32) -> TickAttributes {
+        TickAttributes {
+            can_auto_execute: mask & 1 != 0,
+            past_limit: mask & 2 != 0,
+            pre_open: mask & 4 != 0,
+        }
+    }
+}
+
+/// A single update to a market data subscription
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tick {
+    /// `size` is set for the bid, ask and last, and is also sent as its own size tick
+    Price {
+        tick_type: TickType,
+        price: f64,
+        size: Option<Decimal>,
+        attributes: TickAttributes,
+    },
+    Size {
+        tick_type: TickType,
+        size: Decimal,
+    },
+    String {
+        tick_type: TickType,
+        value: String,
+    },
+    Generic {
+        tick_type: TickType,
+        value: f64,
+    },
+    Efp {
+        tick_type: TickType,
+        basis_points: f64,
+        formatted_basis_points: String,
+        implied_futures_price: f64,
+        hold_days: i32,
+        future_last_trade_date: String,
+        dividend_impact: f64,
+        dividends_to_last_trade_date: f64,
+    },
+    /// Option model values, sent for option contracts
+    OptionComputation(OptionGreeks),
+    /// Sent once at the start of a subscription. `bbo_exchange` identifies the set of exchanges
+    /// making up the best bid and offer.
+    ReqParams {
+        min_tick: f64,
+        bbo_exchange: String,
+        snapshot_permissions: i32,
+    },
+    /// The kind of data the subscription delivers from here on. Sent before the first tick and
+    /// whenever it changes, such as when live data freezes at the close.
+    MarketDataType(MarketDataType),
+    /// All the data for a snapshot request has been sent
+    SnapshotEnd,
+}
+
+/// Implied volatility, greeks and model values for an option, computed from the bid, ask, last
+/// price or the server's model depending on `tick_type`. Values the server did not compute are
+/// `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionGreeks {
+    pub tick_type: TickType,
+    /// 0 when volatility is return based, 1 when it is price based
+    pub tick_attribute: i32,
+    pub implied_volatility: Option<f64>,
+    pub delta: Option<f64>,
+    pub option_price: Option<f64>,
+    /// The present value of dividends expected before expiry
+    pub pv_dividend: Option<f64>,
+    pub gamma: Option<f64>,
+    pub vega: Option<f64>,
+    pub theta: Option<f64>,
+    pub underlying_price: Option<f64>,
+}
+
+/// The kinds of tick-by-tick data. `AllLast` includes trades that `Last` filters out, such as
+/// combos, derivatives and average price trades.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TickByTickType {
+    Last,
+    AllLast,
+    BidAsk,
+    MidPoint,
+}
+
+impl TickByTickType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TickByTickType::Last => "Last",
+            TickByTickType::AllLast => "AllLast",
+            TickByTickType::BidAsk => "BidAsk",
+            TickByTickType::MidPoint => "MidPoint",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TradeAttributes {
+    pub past_limit: bool,
+    pub unreported: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BidAskAttributes {
+    pub bid_past_low: bool,
+    pub ask_past_high: bool,
+}
+
+/// A single tick-by-tick event. `time` is in seconds since the epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TickByTick {
+    /// `tick_type` is `Last` or `AllLast`
+    Trade {
+        tick_type: TickByTickType,
+        time: i64,
+        price: f64,
+        size: Decimal,
+        attributes: TradeAttributes,
+        exchange: String,
+        special_conditions: String,
+    },
+    BidAsk {
+        time: i64,
+        bid_price: f64,
+        ask_price: f64,
+        bid_size: Decimal,
+        ask_size: Decimal,
+        attributes: BidAskAttributes,
+    },
+    MidPoint {
+        time: i64,
+        mid_point: f64,
+    },
+}
+
+/// The kinds of historical ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HistoricalTickType {
+    Trades,
+    BidAsk,
+    Midpoint,
+}
+
+impl HistoricalTickType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoricalTickType::Trades => "TRADES",
+            HistoricalTickType::BidAsk => "BID_ASK",
+            HistoricalTickType::Midpoint => "MIDPOINT",
+        }
+    }
+}
+
+/// A historical midpoint. `time` is in seconds since the epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalTick {
+    pub time: i64,
+    pub price: f64,
+    pub size: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalTickBidAsk {
+    pub time: i64,
+    pub attributes: BidAskAttributes,
+    pub bid_price: f64,
+    pub ask_price: f64,
+    pub bid_size: Decimal,
+    pub ask_size: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalTickLast {
+    pub time: i64,
+    pub attributes: TradeAttributes,
+    pub price: f64,
+    pub size: Decimal,
+    pub exchange: String,
+    pub special_conditions: String,
+}
+
+/// Historical ticks of one kind, oldest first
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoricalTicks {
+    Midpoint(Vec<HistoricalTick>),
+    BidAsk(Vec<HistoricalTickBidAsk>),
+    Last(Vec<HistoricalTickLast>),
+}
+
+impl HistoricalTicks {
+    pub fn empty(tick_type: HistoricalTickType) -> HistoricalTicks {
+        match tick_type {
+            HistoricalTickType::Trades => HistoricalTicks::Last(vec![]),
+            HistoricalTickType::BidAsk => HistoricalTicks::BidAsk(vec![]),
+            HistoricalTickType::Midpoint => HistoricalTicks::Midpoint(vec![]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            HistoricalTicks::Midpoint(t) => t.len(),
+            HistoricalTicks::BidAsk(t) => t.len(),
+            HistoricalTicks::Last(t) => t.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds ticks of the same kind, such as the next part of a multi-message response
+    pub(crate) fn extend(&mut self, other: HistoricalTicks) -> Result<(), Error> {
+        match (self, other) {
+            (HistoricalTicks::Midpoint(a), HistoricalTicks::Midpoint(b)) => a.extend(b),
+            (HistoricalTicks::BidAsk(a), HistoricalTicks::BidAsk(b)) => a.extend(b),
+            (HistoricalTicks::Last(a), HistoricalTicks::Last(b)) => a.extend(b),
+            _ => return Err(anyhow!("Mismatched historical tick types")),
+        }
+        Ok(())
+    }
+
+    /// Adds a page of up to `page_size` ticks requested from `cursor` onwards, keeping those up
+    /// to `end`. A page starts at the second the previous one ended in, so ticks already held
+    /// from that second are replaced. Returns where the next page should start, or `None` once
+    /// `end` is reached or the page wasn't full. Fails if a full page doesn't get past the second
+    /// it started in, since the rest of that second can't be asked for.
+    pub(crate) fn append_page(
+        &mut self,
+        page: HistoricalTicks,
+        cursor: i64,
+        end: i64,
+        page_size: usize,
+    ) -> Result<Option<i64>, Error> {
+        match (self, page) {
+            (HistoricalTicks::Midpoint(a), HistoricalTicks::Midpoint(b)) => {
+                append_page(a, b, cursor, end, page_size, |t| t.time)
+            }
+            (HistoricalTicks::BidAsk(a), HistoricalTicks::BidAsk(b)) => {
+                append_page(a, b, cursor, end, page_size, |t| t.time)
+            }
+            (HistoricalTicks::Last(a), HistoricalTicks::Last(b)) => {
+                append_page(a, b, cursor, end, page_size, |t| t.time)
+            }
+            _ => Err(anyhow!("Mismatched historical tick types")),
+        }
+    }
+}
+
+fn append_page<T>(
+    ticks: &mut Vec<T>,
+    page: Vec<T>,
+    cursor: i64,
+    end: i64,
+    page_size: usize,
+    time: fn(&T) -> i64,
+) -> Result<Option<i64>, Error> {
+    let full = page.len() >= page_size;
+    let (first, last) = match (page.iter().map(time).min(), page.iter().map(time).max()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(None),
+    };
+    // Requests only go down to the second, so the next page would start at this one again
+    if full && last <= cursor && last < end {
+        return Err(anyhow!(
+            "More than {} ticks fall in the second at {}; the rest of it can't be requested",
+            page_size,
+            cursor
+        ));
+    }
+    ticks.retain(|t| time(t) < first);
+    ticks.extend(page.into_iter().filter(|t| time(t) <= end));
+    if last >= end || !full {
+        return Ok(None);
+    }
+    Ok(Some(last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midpoint(time: i64, price: f64) -> HistoricalTick {
+        HistoricalTick {
+            time,
+            price,
+            size: Decimal::from(0),
+        }
+    }
+
+    #[test]
+    fn pages_replace_overlapping_second() {
+        let mut ticks = HistoricalTicks::Midpoint(vec![]);
+        let page = HistoricalTicks::Midpoint(vec![midpoint(10, 1.0), midpoint(11, 1.1)]);
+        assert_eq!(ticks.append_page(page, 10, 20, 2).unwrap(), Some(11));
+        let page = HistoricalTicks::Midpoint(vec![
+            midpoint(11, 1.1),
+            midpoint(11, 1.2),
+            midpoint(21, 1.3),
+        ]);
+        assert_eq!(ticks.append_page(page, 11, 20, 3).unwrap(), None);
+        assert_eq!(
+            ticks,
+            HistoricalTicks::Midpoint(vec![
+                midpoint(10, 1.0),
+                midpoint(11, 1.1),
+                midpoint(11, 1.2)
+            ])
+        );
+    }
+
+    #[test]
+    fn full_page_within_one_second_is_an_error() {
+        let mut ticks = HistoricalTicks::Midpoint(vec![]);
+        let page = HistoricalTicks::Midpoint(vec![midpoint(10, 1.0), midpoint(10, 1.1)]);
+        assert!(ticks.append_page(page, 10, 20, 2).is_err());
+        assert!(ticks.is_empty());
+
+        // A page that isn't full holds the whole second
+        let page = HistoricalTicks::Midpoint(vec![midpoint(10, 1.0), midpoint(10, 1.1)]);
+        assert_eq!(ticks.append_page(page, 10, 20, 3).unwrap(), None);
+        assert_eq!(ticks.len(), 2);
+    }
+
+    #[test]
+    fn tick_type_ids_round_trip() {
+        for id in 0..=102 {
+            assert_eq!(TickType::try_from(id).unwrap().id(), id);
+        }
+        assert!(TickType::try_from(103).is_err());
+        assert_eq!(
+            TickType::DelayedLast.size_tick_type(),
+            Some(TickType::DelayedLastSize)
+        );
+        assert_eq!(TickType::High.size_tick_type(), None);
+    }
+}
@@ -1,16 +1,107 @@
 //! Contains enums for message types and field types
 
 use anyhow::*;
-use std::convert::Into;
+use rust_decimal::prelude::*;
+use std::convert::{Into, TryFrom};
 
 use crate::traits::FromBytes;
 
-/// Enumerates the three possible data types for message fields
-#[derive(Debug, PartialEq)]
+/// Fields in a message are terminated by a NUL byte
+pub static DELIMITER: u8 = 0;
+
+/// Enumerates the possible data types for message fields. Everything is sent over the wire as text,
+/// so these only determine how a value is formatted.
+#[derive(Debug, PartialEq, Clone)]
 pub enum IBField {
-    IBInteger(u32),
+    IBInteger(i64),
     IBString(String),
-    IBFloat(f32),
+    IBFloat(f64),
+    IBBool(bool),
+    /// An unset value, which the server expects as an empty field
+    IBEmpty,
+}
+
+impl IBField {
+    /// Formats the field the way the API server expects it, without the trailing delimiter
+    pub fn to_text(&self) -> String {
+        match self {
+            IBField::IBInteger(v) => v.to_string(),
+            IBField::IBString(v) => v.clone(),
+            IBField::IBFloat(v) => v.to_string(),
+            IBField::IBBool(v) => if *v { "1" } else { "0" }.to_string(),
+            IBField::IBEmpty => String::new(),
+        }
+    }
+}
+
+impl From<i32> for IBField {
+    fn from(v: i32) -> Self {
+        IBField::IBInteger(v as i64)
+    }
+}
+
+impl From<i64> for IBField {
+    fn from(v: i64) -> Self {
+        IBField::IBInteger(v)
+    }
+}
+
+impl From<u32> for IBField {
+    fn from(v: u32) -> Self {
+        IBField::IBInteger(v as i64)
+    }
+}
+
+impl From<usize> for IBField {
+    fn from(v: usize) -> Self {
+        IBField::IBInteger(v as i64)
+    }
+}
+
+impl From<f64> for IBField {
+    fn from(v: f64) -> Self {
+        IBField::IBFloat(v)
+    }
+}
+
+impl From<bool> for IBField {
+    fn from(v: bool) -> Self {
+        IBField::IBBool(v)
+    }
+}
+
+impl From<&str> for IBField {
+    fn from(v: &str) -> Self {
+        IBField::IBString(v.to_string())
+    }
+}
+
+impl From<String> for IBField {
+    fn from(v: String) -> Self {
+        IBField::IBString(v)
+    }
+}
+
+impl From<&String> for IBField {
+    fn from(v: &String) -> Self {
+        IBField::IBString(v.clone())
+    }
+}
+
+impl From<Decimal> for IBField {
+    fn from(v: Decimal) -> Self {
+        IBField::IBString(v.normalize().to_string())
+    }
+}
+
+/// Unset optional values are sent as empty fields
+impl<T: Into<IBField>> From<Option<T>> for IBField {
+    fn from(v: Option<T>) -> Self {
+        match v {
+            Some(v) => v.into(),
+            None => IBField::IBEmpty,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -28,12 +119,20 @@ impl Message {
         Message::Outbound(OutboundMessage::new())
     }
 
-    pub fn add_field(&mut self, field: IBField) {
+    /// Creates an outbound message whose first field is the message type
+    pub fn outbound(kind: OutboundMessages) -> Message {
+        let mut msg = Message::new_outbound();
+        let id: u32 = kind.into();
+        msg.add_field(id);
+        msg
+    }
+
+    pub fn add_field<F: Into<IBField>>(&mut self, field: F) {
         match self {
             Message::Inbound(msg) => {
-                msg.add_field(field);
+                msg.add_field(field.into());
             }
-            Message::Outbound(msg) => msg.add_field(field),
+            Message::Outbound(msg) => msg.add_field(field.into()),
         }
     }
 
@@ -44,7 +143,35 @@ impl Message {
         }
     }
 }
+
+/// Encodes fields as NUL-terminated text, prefixed with the big-endian length of the payload
+fn encode_fields(fields: &[IBField]) -> Vec<u8> {
+    let mut payload = vec![];
+    for field in fields {
+        payload.extend_from_slice(field.to_text().as_bytes());
+        payload.push(DELIMITER);
+    }
+    let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(payload);
+    bytes
+}
+
+/// Splits a length-prefixed payload into its text fields
+fn decode_fields(b: &[u8]) -> Result<Vec<IBField>, Error> {
+    let text = String::from_utf8(b[4..].into())?;
+    let mut fields: Vec<IBField> = text
+        .split(DELIMITER as char)
+        .map(|f| IBField::IBString(f.to_string()))
+        .collect();
+    // Every field is terminated, so the split leaves an empty remainder at the end
+    if text.ends_with(DELIMITER as char) {
+        fields.pop();
+    }
+    Ok(fields)
+}
+
 /// These are the possible inbound messages we can receive from the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum InboundMessages {
     TickPrice,
     TickSize,
@@ -127,10 +254,11 @@ pub enum InboundMessages {
 }
 
 /// These are the possible outbound message types we can send to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum OutboundMessages {
     ReqMarketData,
     CancelMarketData,
-    PlacerOrder,
+    PlaceOrder,
     CancelOrder,
     ReqOpenOrders,
     ReqAccountData,
@@ -159,6 +287,7 @@ pub enum OutboundMessages {
     ReqFundamentalData,
     CancelFundamentalData,
     ReqCalcImpliedVolat,
+    ReqCalcOptionPrice,
     CancelCalcImpliedVolat,
     CancelCalcOptionPrice,
     ReqGlobalCancel,
@@ -206,16 +335,179 @@ pub enum OutboundMessages {
 }
 
 /// Allows for converting an OutboundMessage to a u32 to be serialized for transmission
-impl Into<u32> for OutboundMessages {
-    fn into(self) -> u32 {
-        match self {
+impl From<OutboundMessages> for u32 {
+    fn from(msg: OutboundMessages) -> u32 {
+        match msg {
+            OutboundMessages::ReqMarketData => 1,
+            OutboundMessages::CancelMarketData => 2,
+            OutboundMessages::PlaceOrder => 3,
+            OutboundMessages::CancelOrder => 4,
+            OutboundMessages::ReqOpenOrders => 5,
+            OutboundMessages::ReqAccountData => 6,
+            OutboundMessages::ReqExecutions => 7,
+            OutboundMessages::ReqIds => 8,
+            OutboundMessages::ReqContractData => 9,
+            OutboundMessages::ReqMarketDepth => 10,
+            OutboundMessages::CancelMarketDepth => 11,
+            OutboundMessages::ReqNewsBulletins => 12,
+            OutboundMessages::CancelNewsBulletins => 13,
+            OutboundMessages::SetServerLogLevel => 14,
+            OutboundMessages::ReqAutoOpenOrders => 15,
+            OutboundMessages::ReqAllOpenOrders => 16,
+            OutboundMessages::ReqManagedAccounts => 17,
+            OutboundMessages::ReqFa => 18,
+            OutboundMessages::ReplaceFa => 19,
+            OutboundMessages::ReqHistoricalData => 20,
+            OutboundMessages::ExerciseOptions => 21,
+            OutboundMessages::ReqScannerSubscription => 22,
+            OutboundMessages::CancelScannerSubscription => 23,
+            OutboundMessages::ReqScannerParameters => 24,
+            OutboundMessages::CancelHistoricalData => 25,
+            OutboundMessages::ReqCurrentTime => 49,
+            OutboundMessages::ReqRealTimeBars => 50,
+            OutboundMessages::CancelRealTimeBars => 51,
+            OutboundMessages::ReqFundamentalData => 52,
+            OutboundMessages::CancelFundamentalData => 53,
+            OutboundMessages::ReqCalcImpliedVolat => 54,
+            OutboundMessages::ReqCalcOptionPrice => 55,
+            OutboundMessages::CancelCalcImpliedVolat => 56,
+            OutboundMessages::CancelCalcOptionPrice => 57,
+            OutboundMessages::ReqGlobalCancel => 58,
+            OutboundMessages::ReqMarketDataType => 59,
+            OutboundMessages::ReqPositions => 61,
+            OutboundMessages::ReqAccountSummary => 62,
+            OutboundMessages::CancelAccountSummary => 63,
+            OutboundMessages::CancelPositions => 64,
+            OutboundMessages::VerifyRequest => 65,
+            OutboundMessages::VerifyMessage => 66,
+            OutboundMessages::QueryDisplayGroups => 67,
+            OutboundMessages::SubscribeToGroupEvents => 68,
+            OutboundMessages::UpdateDisplayGroup => 69,
+            OutboundMessages::UnsubscribeFromGroupEvents => 70,
             OutboundMessages::StartApi => 71,
-            _ => 0,
+            OutboundMessages::VerifyAndAuthRequest => 72,
+            OutboundMessages::VerifyAndAuthMessage => 73,
+            OutboundMessages::ReqPositionsMulti => 74,
+            OutboundMessages::CancelPositionsMulti => 75,
+            OutboundMessages::ReqAccountUpdatesMulti => 76,
+            OutboundMessages::CancelAccountUpdatesMulti => 77,
+            OutboundMessages::ReqSecDefOptParams => 78,
+            OutboundMessages::ReqSoftDollarTiers => 79,
+            OutboundMessages::ReqFamilyCodes => 80,
+            OutboundMessages::ReqMatchingSymbols => 81,
+            OutboundMessages::ReqMarketDepthExchanges => 82,
+            OutboundMessages::ReqSmartComponents => 83,
+            OutboundMessages::ReqNewsArticle => 84,
+            OutboundMessages::ReqNewsProviders => 85,
+            OutboundMessages::ReqHistoricalNews => 86,
+            OutboundMessages::ReqHeadTimestamp => 87,
+            OutboundMessages::ReqHistogramData => 88,
+            OutboundMessages::CancelHistogramData => 89,
+            OutboundMessages::CancelHeadTimestamp => 90,
+            OutboundMessages::ReqMarketRule => 91,
+            OutboundMessages::ReqPnl => 92,
+            OutboundMessages::CancelPnl => 93,
+            OutboundMessages::ReqPnlSingle => 94,
+            OutboundMessages::CancelPnlSingle => 95,
+            OutboundMessages::ReqHistoricalTicks => 96,
+            OutboundMessages::ReqTickByTickData => 97,
+            OutboundMessages::CancelTickByTickData => 98,
+            OutboundMessages::ReqCompletedOrders => 99,
+            OutboundMessages::Invalid => 0,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Maps the message id at the start of every inbound message to its type
+impl TryFrom<u32> for InboundMessages {
+    type Error = Error;
+
+    fn try_from(id: u32) -> Result<Self, Self::Error> {
+        match id {
+            1 => Ok(InboundMessages::TickPrice),
+            2 => Ok(InboundMessages::TickSize),
+            3 => Ok(InboundMessages::OrderStatus),
+            4 => Ok(InboundMessages::ErrMsg),
+            5 => Ok(InboundMessages::OpenOrder),
+            6 => Ok(InboundMessages::AccountValue),
+            7 => Ok(InboundMessages::PortfolioValue),
+            8 => Ok(InboundMessages::AccountUpdateTime),
+            9 => Ok(InboundMessages::NextValidID),
+            10 => Ok(InboundMessages::ContractData),
+            11 => Ok(InboundMessages::ExecutionData),
+            12 => Ok(InboundMessages::MarketDepth),
+            13 => Ok(InboundMessages::MarketDepthL2),
+            14 => Ok(InboundMessages::NewsBulletins),
+            15 => Ok(InboundMessages::ManagedAccounts),
+            16 => Ok(InboundMessages::ReceiveFa),
+            17 => Ok(InboundMessages::HistoricalData),
+            18 => Ok(InboundMessages::BondContractData),
+            19 => Ok(InboundMessages::ScannerParameters),
+            20 => Ok(InboundMessages::ScannerData),
+            21 => Ok(InboundMessages::TickOptionComputation),
+            45 => Ok(InboundMessages::TickGeneric),
+            46 => Ok(InboundMessages::TickString),
+            47 => Ok(InboundMessages::TickEfp),
+            49 => Ok(InboundMessages::CurrentTime),
+            50 => Ok(InboundMessages::RealTimeBars),
+            51 => Ok(InboundMessages::FundamentalData),
+            52 => Ok(InboundMessages::ContractDataEnd),
+            53 => Ok(InboundMessages::OpenOrderEnd),
+            54 => Ok(InboundMessages::AccountDownloadEnd),
+            55 => Ok(InboundMessages::ExecutionDataEnd),
+            56 => Ok(InboundMessages::DeltaNeutralValidation),
+            57 => Ok(InboundMessages::TickSnapshotEnd),
+            58 => Ok(InboundMessages::MarketDataType),
+            59 => Ok(InboundMessages::CommissionReport),
+            61 => Ok(InboundMessages::PositionData),
+            62 => Ok(InboundMessages::PositionEnd),
+            63 => Ok(InboundMessages::AccountSummary),
+            64 => Ok(InboundMessages::AccountSummaryEnd),
+            65 => Ok(InboundMessages::VerifyMessageApi),
+            66 => Ok(InboundMessages::VerifyCompleted),
+            67 => Ok(InboundMessages::DisplayGroupList),
+            68 => Ok(InboundMessages::DisplayGroupUpdated),
+            69 => Ok(InboundMessages::VerifyAndAuthMessageApi),
+            70 => Ok(InboundMessages::VerifyAndAuthCompleted),
+            71 => Ok(InboundMessages::PositionMulti),
+            72 => Ok(InboundMessages::PositionMultiEnd),
+            73 => Ok(InboundMessages::AccountUpdateMulti),
+            74 => Ok(InboundMessages::AccountUpdateMultiEnd),
+            75 => Ok(InboundMessages::SecurityDefinitionOptionParameter),
+            76 => Ok(InboundMessages::SecurityDefinitionOptionParameterEnd),
+            77 => Ok(InboundMessages::SoftDollarTiers),
+            78 => Ok(InboundMessages::FamilyCodes),
+            79 => Ok(InboundMessages::SymbolSamples),
+            80 => Ok(InboundMessages::MarketDepthExchanges),
+            81 => Ok(InboundMessages::TickReqParams),
+            82 => Ok(InboundMessages::SmartComponents),
+            83 => Ok(InboundMessages::NewsArticle),
+            84 => Ok(InboundMessages::TickNews),
+            85 => Ok(InboundMessages::NewsProviders),
+            86 => Ok(InboundMessages::HistoricalNews),
+            87 => Ok(InboundMessages::HistoricalNewsEnd),
+            88 => Ok(InboundMessages::HeadTimestamp),
+            89 => Ok(InboundMessages::HistogramData),
+            90 => Ok(InboundMessages::HistoricalDataUpdate),
+            91 => Ok(InboundMessages::RerouteMarketDataReq),
+            92 => Ok(InboundMessages::RerouteMarketDepthReq),
+            93 => Ok(InboundMessages::MarketRule),
+            94 => Ok(InboundMessages::ProfitAndLoss),
+            95 => Ok(InboundMessages::ProfitAndLossSingle),
+            96 => Ok(InboundMessages::HistoricalTicks),
+            97 => Ok(InboundMessages::HistoricalTicksBidAsk),
+            98 => Ok(InboundMessages::HistoricalTicksLast),
+            99 => Ok(InboundMessages::TickByTick),
+            100 => Ok(InboundMessages::OrderBound),
+            101 => Ok(InboundMessages::CompletedOrder),
+            102 => Ok(InboundMessages::CompletedOrdersEnd),
+            103 => Ok(InboundMessages::ReplaceFaEnd),
+            _ => Err(anyhow!("Unknown inbound message id: {}", id)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Default)]
 pub struct OutboundMessage {
     fields: Vec<IBField>,
 }
@@ -229,26 +521,16 @@ impl OutboundMessage {
         self.fields.push(v);
     }
 
+    pub fn fields(&self) -> &[IBField] {
+        &self.fields
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = vec![];
-        for field in &self.fields {
-            match field {
-                IBField::IBFloat(v) => {
-                    bytes = [bytes, v.to_be_bytes().to_vec()].concat();
-                }
-                IBField::IBInteger(v) => {
-                    bytes = [bytes, v.to_be_bytes().to_vec()].concat();
-                }
-                IBField::IBString(v) => {
-                    bytes = [bytes, v.as_bytes().to_vec()].concat();
-                }
-            }
-        }
-        bytes
+        encode_fields(&self.fields)
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Default)]
 pub struct InboundMessage {
     fields: Vec<IBField>,
     raw: Option<String>,
@@ -266,31 +548,31 @@ impl InboundMessage {
         self.fields.push(v);
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = vec![];
-        for field in &self.fields {
-            match field {
-                IBField::IBFloat(v) => {
-                    bytes = [bytes, v.to_be_bytes().to_vec()].concat();
-                }
-                IBField::IBInteger(v) => {
-                    bytes = [bytes, v.to_be_bytes().to_vec()].concat();
-                }
-                IBField::IBString(v) => {
-                    bytes = [bytes, v.as_bytes().to_vec()].concat();
-                }
+    pub fn fields(&self) -> &[IBField] {
+        &self.fields
+    }
+
+    /// The type of the message, taken from its first field
+    pub fn message_type(&self) -> Result<InboundMessages, Error> {
+        match self.fields.first() {
+            Some(field) => {
+                let id: u32 = field.to_text().parse()?;
+                InboundMessages::try_from(id)
             }
+            None => Err(anyhow!("Empty message")),
         }
-        bytes
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_fields(&self.fields)
     }
 
     pub fn from_bytes(b: &[u8]) -> Result<InboundMessage, Error> {
         match b.len() > 4 {
             true => {
-                let size = &b[0..4];
                 let msg = InboundMessage {
                     raw: Some(String::from_utf8(b[4..].into())?),
-                    fields: vec![],
+                    fields: decode_fields(b)?,
                 };
 
                 Ok(msg)
@@ -302,19 +584,7 @@ impl InboundMessage {
 
 impl FromBytes for InboundMessage {
     fn from_bytes(b: &[u8]) -> Result<Message> {
-        match b.len() > 4 {
-            true => {
-                let size = &b[0..4];
-                let text = String::from_utf8(b[4..].into())?;
-
-                let msg = InboundMessage {
-                    fields: vec![],
-                    raw: Some(text),
-                };
-                Ok(Message::Inbound(msg))
-            }
-            false => Err(anyhow!("Not enough bytes in message")),
-        }
+        Ok(Message::Inbound(InboundMessage::from_bytes(b)?))
     }
 }
 
@@ -329,5 +599,29 @@ mod tests {
         let test_bytes = test_string.as_bytes();
         let test_bytes = [&_length, test_bytes].concat();
         let test_message = InboundMessage::from_bytes(&test_bytes).unwrap();
+        assert_eq!(
+            test_message.fields(),
+            &[IBField::IBString("TEST".to_string())]
+        );
+    }
+
+    #[test]
+    fn encode_outbound_message() {
+        let mut msg = Message::outbound(OutboundMessages::StartApi);
+        msg.add_field(2);
+        msg.add_field(1.5);
+        msg.add_field(true);
+        msg.add_field(None::<i32>);
+        msg.add_field("abc");
+        let payload = b"71\x002\x001.5\x001\x00\x00abc\x00";
+        let expected = [&(payload.len() as u32).to_be_bytes()[..], &payload[..]].concat();
+        assert_eq!(msg.to_bytes(), expected);
+    }
+
+    #[test]
+    fn inbound_message_type() {
+        let mut msg = InboundMessage::new();
+        msg.add_field(IBField::IBString("9".to_string()));
+        assert_eq!(msg.message_type().unwrap(), InboundMessages::NextValidID);
     }
 }
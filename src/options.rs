@@ -0,0 +1,218 @@
+//! Option chains: the expirations and strikes the server lists for an underlying, and the option
+//! contracts built from them. Also the results of option calculations done with the server's
+//! model.
+
+use crate::contract::{Contract, ContractId, SecurityType, Strike};
+use crate::market_data::OptionGreeks;
+use crate::subscription::Subscription;
+use anyhow::*;
+use rust_decimal::prelude::*;
+use std::time::Duration;
+
+/// The options on one underlying that trade on one exchange under one trading class
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OptionParameters {
+    pub exchange: String,
+    pub underlying_contract_id: ContractId,
+    pub trading_class: String,
+    pub multiplier: String,
+    /// `yyyymmdd`
+    pub expirations: Vec<String>,
+    pub strikes: Vec<Strike>,
+}
+
+/// Strikes whose ratio to the underlying price falls within `min..=max`, e.g. 0.9 to 1.1 for
+/// strikes within 10% of the money
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Moneyness {
+    pub underlying_price: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Narrows down which options a chain includes. Empty fields match everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChainFilter {
+    /// Only options on this exchange; `SMART` if empty, which options on futures are not listed on
+    pub exchange: String,
+    /// The earliest expiration to include, as `yyyymmdd`
+    pub first_expiry: String,
+    /// The last expiration to include, as `yyyymmdd`
+    pub last_expiry: String,
+    pub moneyness: Option<Moneyness>,
+    /// `C`, `P` or both
+    pub rights: Vec<String>,
+}
+
+impl ChainFilter {
+    pub(crate) fn exchange(&self) -> &str {
+        if self.exchange.is_empty() {
+            "SMART"
+        } else {
+            &self.exchange
+        }
+    }
+}
+
+impl OptionParameters {
+    /// Builds a contract for every expiration, strike and right that passes the filter, ordered
+    /// by expiration, then strike, then right
+    pub fn contracts(&self, underlying: &Contract, filter: &ChainFilter) -> Vec<Contract> {
+        let security_type = match underlying.security_type {
+            SecurityType::Future => SecurityType::FutureOption,
+            _ => SecurityType::Option,
+        };
+        let rights: Vec<&str> = if filter.rights.is_empty() {
+            vec!["C", "P"]
+        } else {
+            filter.rights.iter().map(|r| r.as_str()).collect()
+        };
+
+        let mut expirations: Vec<&String> = self
+            .expirations
+            .iter()
+            .filter(|e| filter.first_expiry.is_empty() || **e >= filter.first_expiry)
+            .filter(|e| filter.last_expiry.is_empty() || **e <= filter.last_expiry)
+            .collect();
+        expirations.sort();
+        let mut strikes: Vec<Strike> = self
+            .strikes
+            .iter()
+            .copied()
+            .filter(|s| match (&filter.moneyness, s.to_f64()) {
+                (Some(m), Some(strike)) => {
+                    let ratio = strike / m.underlying_price;
+                    ratio >= m.min && ratio <= m.max
+                }
+                _ => true,
+            })
+            .collect();
+        strikes.sort();
+
+        let mut contracts = vec![];
+        for expiry in expirations {
+            for strike in &strikes {
+                for right in &rights {
+                    contracts.push(Contract {
+                        symbol: underlying.symbol.clone(),
+                        security_type: security_type.clone(),
+                        last_trade_date_or_contract_month: expiry.clone(),
+                        strike: *strike,
+                        right: right.to_string(),
+                        multiplier: self.multiplier.clone(),
+                        exchange: self.exchange.clone(),
+                        currency: underlying.currency.clone(),
+                        trading_class: self.trading_class.clone(),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+        contracts
+    }
+}
+
+/// What to do with an option position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExerciseAction {
+    Exercise,
+    /// Let the option expire without exercising it, even if it is in the money
+    Lapse,
+}
+
+impl ExerciseAction {
+    pub fn id(self) -> i32 {
+        match self {
+            ExerciseAction::Exercise => 1,
+            ExerciseAction::Lapse => 2,
+        }
+    }
+}
+
+/// An implied volatility or option price calculation whose result arrives some time after the
+/// request. Dropping it before the result arrives cancels the calculation.
+pub struct OptionCalculation {
+    subscription: Subscription<OptionGreeks>,
+}
+
+impl OptionCalculation {
+    pub(crate) fn new(subscription: Subscription<OptionGreeks>) -> OptionCalculation {
+        OptionCalculation { subscription }
+    }
+
+    pub fn request_id(&self) -> i32 {
+        self.subscription.request_id()
+    }
+
+    /// Waits up to `timeout` for the result
+    pub fn wait(&mut self, timeout: Duration) -> Result<OptionGreeks, Error> {
+        match self.subscription.next_timeout(timeout) {
+            Some(result) => result,
+            None => Err(anyhow!(
+                "Timed out waiting for option calculation {}",
+                self.request_id()
+            )),
+        }
+    }
+
+    /// Returns the result if it has already arrived
+    pub fn try_result(&mut self) -> Option<Result<OptionGreeks, Error>> {
+        self.subscription.try_next()
+    }
+
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        self.subscription.cancel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_chain_by_expiry_moneyness_and_right() {
+        let parameters = OptionParameters {
+            exchange: "SMART".to_string(),
+            underlying_contract_id: 265_598,
+            trading_class: "AAPL".to_string(),
+            multiplier: "100".to_string(),
+            expirations: vec![
+                "20201218".to_string(),
+                "20201120".to_string(),
+                "20210115".to_string(),
+            ],
+            strikes: [130, 115, 120, 125, 100]
+                .iter()
+                .map(|s| Strike::from(*s))
+                .collect(),
+        };
+        let filter = ChainFilter {
+            first_expiry: "20201101".to_string(),
+            last_expiry: "20201231".to_string(),
+            moneyness: Some(Moneyness {
+                underlying_price: 120.0,
+                min: 0.95,
+                max: 1.05,
+            }),
+            rights: vec!["C".to_string()],
+            ..Default::default()
+        };
+        let underlying = Contract::stock("AAPL", "SMART", "USD");
+        let chain: Vec<(String, Strike)> = parameters
+            .contracts(&underlying, &filter)
+            .into_iter()
+            .map(|c| (c.last_trade_date_or_contract_month, c.strike))
+            .collect();
+        assert_eq!(
+            chain,
+            vec![
+                ("20201120".to_string(), Strike::from(115)),
+                ("20201120".to_string(), Strike::from(120)),
+                ("20201120".to_string(), Strike::from(125)),
+                ("20201218".to_string(), Strike::from(115)),
+                ("20201218".to_string(), Strike::from(120)),
+                ("20201218".to_string(), Strike::from(125)),
+            ]
+        );
+    }
+}
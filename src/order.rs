@@ -0,0 +1,552 @@
+//! Contains data structures for orders, their state on the server and the conditions attached to them
+
+use crate::contract::{Contract, ContractId, Exchange};
+use rust_decimal::prelude::*;
+use std::fmt;
+
+/// These are some convenience type wrappers
+pub type OrderId = i32;
+pub type PermId = i64;
+
+/// A tag/value pair, used for algo parameters and other free-form order options
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagValue {
+    pub tag: String,
+    pub value: String,
+}
+
+/// Joins tag/value pairs into the `tag=value;` form used for option lists on the wire
+pub(crate) fn encode_tag_values(tag_values: &[TagValue]) -> String {
+    tag_values
+        .iter()
+        .map(|t| format!("{}={};", t.tag, t.value))
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SoftDollarTier {
+    pub name: String,
+    pub value: String,
+    pub display_name: String,
+}
+
+/// The lifecycle states the server reports for an order
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OrderStatus {
+    /// Transmitted, but not yet acknowledged by the order destination
+    PendingSubmit,
+    /// A cancel was requested, but not yet confirmed by the order destination
+    PendingCancel,
+    /// Simulated order accepted, but not yet elected (e.g. a stop that hasn't triggered)
+    PreSubmitted,
+    /// Accepted by the order destination and working
+    Submitted,
+    /// Not yet transmitted by TWS; only used for API orders
+    ApiPending,
+    /// Cancelled before being transmitted by TWS
+    ApiCancelled,
+    /// Cancelled by the destination or the system
+    Cancelled,
+    Filled,
+    /// Received but not working, e.g. rejected or outside the trading window
+    Inactive,
+    /// Anything the server sends that we don't have a variant for
+    Unknown(String),
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            OrderStatus::PendingSubmit => "PendingSubmit",
+            OrderStatus::PendingCancel => "PendingCancel",
+            OrderStatus::PreSubmitted => "PreSubmitted",
+            OrderStatus::Submitted => "Submitted",
+            OrderStatus::ApiPending => "ApiPending",
+            OrderStatus::ApiCancelled => "ApiCancelled",
+            OrderStatus::Cancelled => "Cancelled",
+            OrderStatus::Filled => "Filled",
+            OrderStatus::Inactive => "Inactive",
+            OrderStatus::Unknown(s) => s,
+        }
+    }
+
+    /// Whether the order can no longer change; cancelled, filled or inactive
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            OrderStatus::ApiCancelled
+                | OrderStatus::Cancelled
+                | OrderStatus::Filled
+                | OrderStatus::Inactive
+        )
+    }
+
+    /// Whether the order is in a state where it may still be cancelled
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            OrderStatus::PendingSubmit
+                | OrderStatus::PreSubmitted
+                | OrderStatus::Submitted
+                | OrderStatus::ApiPending
+        )
+    }
+}
+
+impl From<&str> for OrderStatus {
+    fn from(s: &str) -> Self {
+        match s {
+            "PendingSubmit" => OrderStatus::PendingSubmit,
+            "PendingCancel" => OrderStatus::PendingCancel,
+            "PreSubmitted" => OrderStatus::PreSubmitted,
+            "Submitted" => OrderStatus::Submitted,
+            "ApiPending" => OrderStatus::ApiPending,
+            "ApiCancelled" => OrderStatus::ApiCancelled,
+            "Cancelled" => OrderStatus::Cancelled,
+            "Filled" => OrderStatus::Filled,
+            "Inactive" => OrderStatus::Inactive,
+            other => OrderStatus::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Conditions that activate or cancel an order. `conjunction_and` joins a condition to the next one
+/// with AND rather than OR.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderCondition {
+    Price {
+        conjunction_and: bool,
+        is_more: bool,
+        price: f64,
+        contract_id: ContractId,
+        exchange: Exchange,
+        trigger_method: i32,
+    },
+    Time {
+        conjunction_and: bool,
+        is_more: bool,
+        time: String,
+    },
+    Margin {
+        conjunction_and: bool,
+        is_more: bool,
+        percent: i32,
+    },
+    Execution {
+        conjunction_and: bool,
+        security_type: String,
+        exchange: Exchange,
+        symbol: String,
+    },
+    Volume {
+        conjunction_and: bool,
+        is_more: bool,
+        volume: i32,
+        contract_id: ContractId,
+        exchange: Exchange,
+    },
+    PercentChange {
+        conjunction_and: bool,
+        is_more: bool,
+        change_percent: f64,
+        contract_id: ContractId,
+        exchange: Exchange,
+    },
+}
+
+impl OrderCondition {
+    /// The id the server uses for each kind of condition
+    pub fn condition_type(&self) -> i32 {
+        match self {
+            OrderCondition::Price { .. } => 1,
+            OrderCondition::Time { .. } => 3,
+            OrderCondition::Margin { .. } => 4,
+            OrderCondition::Execution { .. } => 5,
+            OrderCondition::Volume { .. } => 6,
+            OrderCondition::PercentChange { .. } => 7,
+        }
+    }
+}
+
+/// An order as sent to, or reported by, the server. Unset optional values are sent as empty fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Order {
+    // identifiers
+    pub order_id: OrderId,
+    pub client_id: i32,
+    pub perm_id: PermId,
+
+    // main order fields
+    pub action: String,
+    pub total_quantity: Decimal,
+    pub order_type: String,
+    pub lmt_price: Option<f64>,
+    pub aux_price: Option<f64>,
+
+    // extended order fields
+    pub tif: String,
+    pub active_start_time: String,
+    pub active_stop_time: String,
+    pub oca_group: String,
+    pub oca_type: i32,
+    pub order_ref: String,
+    pub transmit: bool,
+    pub parent_id: OrderId,
+    pub block_order: bool,
+    pub sweep_to_fill: bool,
+    pub display_size: i32,
+    pub trigger_method: i32,
+    pub outside_rth: bool,
+    pub hidden: bool,
+    pub good_after_time: String,
+    pub good_till_date: String,
+    pub rule_80a: String,
+    pub all_or_none: bool,
+    pub min_qty: Option<i32>,
+    pub percent_offset: Option<f64>,
+    pub override_percentage_constraints: bool,
+    pub trail_stop_price: Option<f64>,
+    pub trailing_percent: Option<f64>,
+
+    // financial advisors only
+    pub fa_group: String,
+    pub fa_profile: String,
+    pub fa_method: String,
+    pub fa_percentage: String,
+
+    // institutional (ie non-cleared) only
+    pub designated_location: String,
+    pub open_close: String,
+    pub origin: i32,
+    pub short_sale_slot: i32,
+    pub exempt_code: i32,
+
+    // SMART routing only
+    pub discretionary_amt: f64,
+    pub e_trade_only: bool,
+    pub firm_quote_only: bool,
+    pub nbbo_price_cap: Option<f64>,
+    pub opt_out_smart_routing: bool,
+
+    // BOX exchange orders only
+    pub auction_strategy: i32,
+    pub starting_price: Option<f64>,
+    pub stock_ref_price: Option<f64>,
+    pub delta: Option<f64>,
+
+    // pegged to stock and VOL orders only
+    pub stock_range_lower: Option<f64>,
+    pub stock_range_upper: Option<f64>,
+
+    pub randomize_price: bool,
+    pub randomize_size: bool,
+
+    // VOLATILITY orders only
+    pub volatility: Option<f64>,
+    pub volatility_type: Option<i32>,
+    pub delta_neutral_order_type: String,
+    pub delta_neutral_aux_price: Option<f64>,
+    pub delta_neutral_contract_id: i32,
+    pub delta_neutral_settling_firm: String,
+    pub delta_neutral_clearing_account: String,
+    pub delta_neutral_clearing_intent: String,
+    pub delta_neutral_open_close: String,
+    pub delta_neutral_short_sale: bool,
+    pub delta_neutral_short_sale_slot: i32,
+    pub delta_neutral_designated_location: String,
+    pub continuous_update: bool,
+    pub reference_price_type: Option<i32>,
+
+    // EFP orders only
+    pub basis_points: Option<f64>,
+    pub basis_points_type: Option<i32>,
+
+    // SCALE orders only
+    pub scale_init_level_size: Option<i32>,
+    pub scale_subs_level_size: Option<i32>,
+    pub scale_price_increment: Option<f64>,
+    pub scale_price_adjust_value: Option<f64>,
+    pub scale_price_adjust_interval: Option<i32>,
+    pub scale_profit_offset: Option<f64>,
+    pub scale_auto_reset: bool,
+    pub scale_init_position: Option<i32>,
+    pub scale_init_fill_qty: Option<i32>,
+    pub scale_random_percent: bool,
+    pub scale_table: String,
+
+    // HEDGE orders
+    pub hedge_type: String,
+    pub hedge_param: String,
+
+    // Clearing info
+    pub account: String,
+    pub settling_firm: String,
+    pub clearing_account: String,
+    pub clearing_intent: String,
+
+    // ALGO orders only
+    pub algo_strategy: String,
+    pub algo_params: Vec<TagValue>,
+    pub smart_combo_routing_params: Vec<TagValue>,
+    pub algo_id: String,
+
+    pub what_if: bool,
+    pub not_held: bool,
+    pub solicited: bool,
+    pub model_code: String,
+
+    // order combo legs
+    pub order_combo_leg_prices: Vec<Option<f64>>,
+    pub order_misc_options: Vec<TagValue>,
+
+    // VER PEG2BENCH fields
+    pub reference_contract_id: i32,
+    pub pegged_change_amount: f64,
+    pub is_pegged_change_amount_decrease: bool,
+    pub reference_change_amount: f64,
+    pub reference_exchange_id: String,
+    pub adjusted_order_type: String,
+    pub trigger_price: Option<f64>,
+    pub adjusted_stop_price: Option<f64>,
+    pub adjusted_stop_limit_price: Option<f64>,
+    pub adjusted_trailing_amount: Option<f64>,
+    pub adjustable_trailing_unit: i32,
+    pub lmt_price_offset: Option<f64>,
+
+    pub conditions: Vec<OrderCondition>,
+    pub conditions_cancel_order: bool,
+    pub conditions_ignore_rth: bool,
+
+    pub ext_operator: String,
+    pub soft_dollar_tier: SoftDollarTier,
+    pub cash_qty: Option<f64>,
+
+    pub mifid2_decision_maker: String,
+    pub mifid2_decision_algo: String,
+    pub mifid2_execution_trader: String,
+    pub mifid2_execution_algo: String,
+
+    pub dont_use_auto_price_for_hedge: bool,
+    pub is_oms_container: bool,
+    pub discretionary_up_to_limit_price: bool,
+
+    pub auto_cancel_date: String,
+    pub filled_quantity: Option<Decimal>,
+    pub ref_futures_contract_id: i32,
+    pub auto_cancel_parent: bool,
+    pub shareholder: String,
+    pub imbalance_only: bool,
+    pub route_marketable_to_bbo: bool,
+    pub parent_perm_id: PermId,
+
+    pub use_price_mgmt_algo: Option<bool>,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order {
+            order_id: 0,
+            client_id: 0,
+            perm_id: 0,
+            action: String::new(),
+            total_quantity: Decimal::zero(),
+            order_type: String::new(),
+            lmt_price: None,
+            aux_price: None,
+            tif: String::new(),
+            active_start_time: String::new(),
+            active_stop_time: String::new(),
+            oca_group: String::new(),
+            oca_type: 0,
+            order_ref: String::new(),
+            transmit: true,
+            parent_id: 0,
+            block_order: false,
+            sweep_to_fill: false,
+            display_size: 0,
+            trigger_method: 0,
+            outside_rth: false,
+            hidden: false,
+            good_after_time: String::new(),
+            good_till_date: String::new(),
+            rule_80a: String::new(),
+            all_or_none: false,
+            min_qty: None,
+            percent_offset: None,
+            override_percentage_constraints: false,
+            trail_stop_price: None,
+            trailing_percent: None,
+            fa_group: String::new(),
+            fa_profile: String::new(),
+            fa_method: String::new(),
+            fa_percentage: String::new(),
+            designated_location: String::new(),
+            open_close: String::new(),
+            origin: 0,
+            short_sale_slot: 0,
+            exempt_code: -1,
+            discretionary_amt: 0.0,
+            e_trade_only: false,
+            firm_quote_only: false,
+            nbbo_price_cap: None,
+            opt_out_smart_routing: false,
+            auction_strategy: 0,
+            starting_price: None,
+            stock_ref_price: None,
+            delta: None,
+            stock_range_lower: None,
+            stock_range_upper: None,
+            randomize_price: false,
+            randomize_size: false,
+            volatility: None,
+            volatility_type: None,
+            delta_neutral_order_type: String::new(),
+            delta_neutral_aux_price: None,
+            delta_neutral_contract_id: 0,
+            delta_neutral_settling_firm: String::new(),
+            delta_neutral_clearing_account: String::new(),
+            delta_neutral_clearing_intent: String::new(),
+            delta_neutral_open_close: String::new(),
+            delta_neutral_short_sale: false,
+            delta_neutral_short_sale_slot: 0,
+            delta_neutral_designated_location: String::new(),
+            continuous_update: false,
+            reference_price_type: None,
+            basis_points: None,
+            basis_points_type: None,
+            scale_init_level_size: None,
+            scale_subs_level_size: None,
+            scale_price_increment: None,
+            scale_price_adjust_value: None,
+            scale_price_adjust_interval: None,
+            scale_profit_offset: None,
+            scale_auto_reset: false,
+            scale_init_position: None,
+            scale_init_fill_qty: None,
+            scale_random_percent: false,
+            scale_table: String::new(),
+            hedge_type: String::new(),
+            hedge_param: String::new(),
+            account: String::new(),
+            settling_firm: String::new(),
+            clearing_account: String::new(),
+            clearing_intent: String::new(),
+            algo_strategy: String::new(),
+            algo_params: vec![],
+            smart_combo_routing_params: vec![],
+            algo_id: String::new(),
+            what_if: false,
+            not_held: false,
+            solicited: false,
+            model_code: String::new(),
+            order_combo_leg_prices: vec![],
+            order_misc_options: vec![],
+            reference_contract_id: 0,
+            pegged_change_amount: 0.0,
+            is_pegged_change_amount_decrease: false,
+            reference_change_amount: 0.0,
+            reference_exchange_id: String::new(),
+            adjusted_order_type: String::new(),
+            trigger_price: None,
+            adjusted_stop_price: None,
+            adjusted_stop_limit_price: None,
+            adjusted_trailing_amount: None,
+            adjustable_trailing_unit: 0,
+            lmt_price_offset: None,
+            conditions: vec![],
+            conditions_cancel_order: false,
+            conditions_ignore_rth: false,
+            ext_operator: String::new(),
+            soft_dollar_tier: SoftDollarTier::default(),
+            cash_qty: None,
+            mifid2_decision_maker: String::new(),
+            mifid2_decision_algo: String::new(),
+            mifid2_execution_trader: String::new(),
+            mifid2_execution_algo: String::new(),
+            dont_use_auto_price_for_hedge: false,
+            is_oms_container: false,
+            discretionary_up_to_limit_price: false,
+            auto_cancel_date: String::new(),
+            filled_quantity: None,
+            ref_futures_contract_id: 0,
+            auto_cancel_parent: false,
+            shareholder: String::new(),
+            imbalance_only: false,
+            route_marketable_to_bbo: false,
+            parent_perm_id: 0,
+            use_price_mgmt_algo: None,
+        }
+    }
+}
+
+/// Margin and commission details the server attaches to an open order. The margin fields are
+/// only filled in for what-if orders.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderState {
+    pub status: String,
+    pub init_margin_before: String,
+    pub maint_margin_before: String,
+    pub equity_with_loan_before: String,
+    pub init_margin_change: String,
+    pub maint_margin_change: String,
+    pub equity_with_loan_change: String,
+    pub init_margin_after: String,
+    pub maint_margin_after: String,
+    pub equity_with_loan_after: String,
+    pub commission: Option<f64>,
+    pub min_commission: Option<f64>,
+    pub max_commission: Option<f64>,
+    pub commission_currency: String,
+    pub warning_text: String,
+    pub completed_time: String,
+    pub completed_status: String,
+}
+
+/// An order as reported by an `OpenOrder` message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpenOrder {
+    pub order_id: OrderId,
+    pub contract: Contract,
+    pub order: Order,
+    pub order_state: OrderState,
+}
+
+/// A filled or cancelled order from `req_completed_orders`. Completed orders have no order id;
+/// use the perm id on `order` instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletedOrder {
+    pub contract: Contract,
+    pub order: Order,
+    pub order_state: OrderState,
+}
+
+/// The contents of an `OrderStatus` message
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderStatusUpdate {
+    pub order_id: OrderId,
+    pub status: OrderStatus,
+    pub filled: Decimal,
+    pub remaining: Decimal,
+    pub avg_fill_price: f64,
+    pub perm_id: PermId,
+    pub parent_id: OrderId,
+    pub last_fill_price: f64,
+    pub client_id: i32,
+    pub why_held: String,
+    pub market_cap_price: f64,
+}
+
+/// The contents of an `OrderBound` message, sent when an order placed outside the API is bound
+/// to an API order id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderBound {
+    pub perm_id: PermId,
+    pub api_client_id: i32,
+    pub api_order_id: OrderId,
+}
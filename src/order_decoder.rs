@@ -0,0 +1,820 @@
+//! Decodes the order-carrying messages (`OpenOrder`, `CompletedOrder`). These share most of their
+//! fields, so the pieces are split out the same way the Python API's `OrderDecoder` does it.
+//!
+//! Servers we negotiate with (v100+) always send `OpenOrder` at message version 34 or later, so
+//! the checks against older message versions are left out.
+
+use crate::contract::{ComboLeg, Contract, DeltaNeutralContract, SecurityType};
+use crate::decoder::Fields;
+use crate::order::{CompletedOrder, OpenOrder, Order, OrderCondition, OrderState, TagValue};
+use crate::server_versions::*;
+use anyhow::*;
+
+struct OrderDecoder<'a, 'b> {
+    fields: &'a mut Fields<'b>,
+    server_version: usize,
+    contract: Contract,
+    order: Order,
+    order_state: OrderState,
+}
+
+/// Decodes an `OpenOrder` message, with the message id already read
+pub fn decode_open_order(fields: &mut Fields, server_version: usize) -> Result<OpenOrder, Error> {
+    if server_version < MIN_SERVER_VER_ORDER_CONTAINER {
+        fields.skip()?;
+    }
+    let mut d = OrderDecoder::new(fields, server_version);
+    d.order.order_id = d.fields.read_int()?;
+    d.decode_contract_fields()?;
+    d.decode_main_fields()?;
+    d.order.client_id = d.fields.read_int()?;
+    d.order.perm_id = d.fields.read_long()?;
+    d.order.outside_rth = d.fields.read_bool()?;
+    d.order.hidden = d.fields.read_bool()?;
+    d.order.discretionary_amt = d.fields.read_double()?;
+    d.order.good_after_time = d.fields.read_string()?;
+    // deprecated sharesAllocation field
+    d.fields.skip()?;
+    d.decode_fa_params()?;
+    d.decode_model_code()?;
+    d.order.good_till_date = d.fields.read_string()?;
+    d.order.rule_80a = d.fields.read_string()?;
+    d.order.percent_offset = d.fields.read_double_max()?;
+    d.order.settling_firm = d.fields.read_string()?;
+    d.decode_short_sale_params()?;
+    d.order.auction_strategy = d.fields.read_int()?;
+    d.decode_box_order_params()?;
+    d.decode_peg_to_stk_or_vol_order_params()?;
+    d.order.display_size = d.fields.read_int()?;
+    d.order.block_order = d.fields.read_bool()?;
+    d.order.sweep_to_fill = d.fields.read_bool()?;
+    d.order.all_or_none = d.fields.read_bool()?;
+    d.order.min_qty = d.fields.read_int_max()?;
+    d.order.oca_type = d.fields.read_int()?;
+    d.order.e_trade_only = d.fields.read_bool()?;
+    d.order.firm_quote_only = d.fields.read_bool()?;
+    d.order.nbbo_price_cap = d.fields.read_double_max()?;
+    d.order.parent_id = d.fields.read_int()?;
+    d.order.trigger_method = d.fields.read_int()?;
+    d.decode_vol_order_params(true)?;
+    d.decode_trail_params()?;
+    d.order.basis_points = d.fields.read_double_max()?;
+    d.order.basis_points_type = d.fields.read_int_max()?;
+    d.decode_combo_legs()?;
+    d.decode_smart_combo_routing_params()?;
+    d.decode_scale_order_params()?;
+    d.decode_hedge_params()?;
+    d.order.opt_out_smart_routing = d.fields.read_bool()?;
+    d.order.clearing_account = d.fields.read_string()?;
+    d.order.clearing_intent = d.fields.read_string()?;
+    d.order.not_held = d.fields.read_bool()?;
+    d.decode_delta_neutral()?;
+    d.decode_algo_params()?;
+    d.order.solicited = d.fields.read_bool()?;
+    d.decode_what_if_info_and_commission()?;
+    d.order.randomize_size = d.fields.read_bool()?;
+    d.order.randomize_price = d.fields.read_bool()?;
+    d.decode_peg_to_bench_params()?;
+    d.decode_conditions()?;
+    d.decode_adjusted_order_params()?;
+    d.decode_soft_dollar_tier()?;
+    d.decode_cash_qty()?;
+    if d.server_version >= MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE {
+        d.order.dont_use_auto_price_for_hedge = d.fields.read_bool()?;
+    }
+    if d.server_version >= MIN_SERVER_VER_ORDER_CONTAINER {
+        d.order.is_oms_container = d.fields.read_bool()?;
+    }
+    if d.server_version >= MIN_SERVER_VER_D_PEG_ORDERS {
+        d.order.discretionary_up_to_limit_price = d.fields.read_bool()?;
+    }
+    if d.server_version >= MIN_SERVER_VER_PRICE_MGMT_ALGO {
+        d.order.use_price_mgmt_algo = Some(d.fields.read_bool()?);
+    }
+    Ok(d.finish())
+}
+
+/// Decodes a `CompletedOrder` message, with the message id already read. It carries no order id
+/// or message version, and leaves out several of the fields `OpenOrder` has.
+pub fn decode_completed_order(
+    fields: &mut Fields,
+    server_version: usize,
+) -> Result<CompletedOrder, Error> {
+    let mut d = OrderDecoder::new(fields, server_version);
+    d.decode_contract_fields()?;
+    d.decode_main_fields()?;
+    d.order.perm_id = d.fields.read_long()?;
+    d.order.outside_rth = d.fields.read_bool()?;
+    d.order.hidden = d.fields.read_bool()?;
+    d.order.discretionary_amt = d.fields.read_double()?;
+    d.order.good_after_time = d.fields.read_string()?;
+    d.decode_fa_params()?;
+    d.decode_model_code()?;
+    d.order.good_till_date = d.fields.read_string()?;
+    d.order.rule_80a = d.fields.read_string()?;
+    d.order.percent_offset = d.fields.read_double_max()?;
+    d.order.settling_firm = d.fields.read_string()?;
+    d.decode_short_sale_params()?;
+    d.decode_box_order_params()?;
+    d.decode_peg_to_stk_or_vol_order_params()?;
+    d.order.display_size = d.fields.read_int()?;
+    d.order.sweep_to_fill = d.fields.read_bool()?;
+    d.order.all_or_none = d.fields.read_bool()?;
+    d.order.min_qty = d.fields.read_int_max()?;
+    d.order.oca_type = d.fields.read_int()?;
+    d.order.trigger_method = d.fields.read_int()?;
+    d.decode_vol_order_params(false)?;
+    d.decode_trail_params()?;
+    d.decode_combo_legs()?;
+    d.decode_smart_combo_routing_params()?;
+    d.decode_scale_order_params()?;
+    d.decode_hedge_params()?;
+    d.order.clearing_account = d.fields.read_string()?;
+    d.order.clearing_intent = d.fields.read_string()?;
+    d.order.not_held = d.fields.read_bool()?;
+    d.decode_delta_neutral()?;
+    d.decode_algo_params()?;
+    d.order.solicited = d.fields.read_bool()?;
+    d.order_state.status = d.fields.read_string()?;
+    d.order.randomize_size = d.fields.read_bool()?;
+    d.order.randomize_price = d.fields.read_bool()?;
+    d.decode_peg_to_bench_params()?;
+    d.decode_conditions()?;
+    d.decode_stop_price_and_lmt_price_offset()?;
+    d.decode_cash_qty()?;
+    if d.server_version >= MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE {
+        d.order.dont_use_auto_price_for_hedge = d.fields.read_bool()?;
+    }
+    if d.server_version >= MIN_SERVER_VER_ORDER_CONTAINER {
+        d.order.is_oms_container = d.fields.read_bool()?;
+    }
+    d.order.auto_cancel_date = d.fields.read_string()?;
+    d.order.filled_quantity = d.fields.read_decimal_max()?;
+    d.order.ref_futures_contract_id = d.fields.read_int()?;
+    d.order.auto_cancel_parent = d.fields.read_bool()?;
+    d.order.shareholder = d.fields.read_string()?;
+    d.order.imbalance_only = d.fields.read_bool()?;
+    d.order.route_marketable_to_bbo = d.fields.read_bool()?;
+    d.order.parent_perm_id = d.fields.read_long()?;
+    d.order_state.completed_time = d.fields.read_string()?;
+    d.order_state.completed_status = d.fields.read_string()?;
+    Ok(CompletedOrder {
+        contract: d.contract,
+        order: d.order,
+        order_state: d.order_state,
+    })
+}
+
+impl<'a, 'b> OrderDecoder<'a, 'b> {
+    fn new(fields: &'a mut Fields<'b>, server_version: usize) -> OrderDecoder<'a, 'b> {
+        OrderDecoder {
+            fields,
+            server_version,
+            contract: Contract::default(),
+            order: Order::default(),
+            order_state: OrderState::default(),
+        }
+    }
+
+    fn finish(self) -> OpenOrder {
+        OpenOrder {
+            order_id: self.order.order_id,
+            contract: self.contract,
+            order: self.order,
+            order_state: self.order_state,
+        }
+    }
+
+    fn decode_contract_fields(&mut self) -> Result<(), Error> {
+        let f = &mut self.fields;
+        self.contract.contract_id = f.read_int()? as usize;
+        self.contract.symbol = f.read_string()?;
+        self.contract.security_type = SecurityType::from(f.read_string()?.as_str());
+        self.contract.last_trade_date_or_contract_month = f.read_string()?;
+        self.contract.strike = f.read_decimal()?;
+        self.contract.right = f.read_string()?;
+        self.contract.multiplier = f.read_string()?;
+        self.contract.exchange = f.read_string()?;
+        self.contract.currency = f.read_string()?;
+        self.contract.local_symbol = f.read_string()?;
+        self.contract.trading_class = f.read_string()?;
+        Ok(())
+    }
+
+    /// Everything from the action up to and including the order ref
+    fn decode_main_fields(&mut self) -> Result<(), Error> {
+        let f = &mut self.fields;
+        self.order.action = f.read_string()?;
+        self.order.total_quantity = f.read_decimal()?;
+        self.order.order_type = f.read_string()?;
+        self.order.lmt_price = f.read_double_max()?;
+        self.order.aux_price = f.read_double_max()?;
+        self.order.tif = f.read_string()?;
+        self.order.oca_group = f.read_string()?;
+        self.order.account = f.read_string()?;
+        self.order.open_close = f.read_string()?;
+        self.order.origin = f.read_int()?;
+        self.order.order_ref = f.read_string()?;
+        Ok(())
+    }
+
+    fn decode_fa_params(&mut self) -> Result<(), Error> {
+        self.order.fa_group = self.fields.read_string()?;
+        self.order.fa_method = self.fields.read_string()?;
+        self.order.fa_percentage = self.fields.read_string()?;
+        self.order.fa_profile = self.fields.read_string()?;
+        Ok(())
+    }
+
+    fn decode_model_code(&mut self) -> Result<(), Error> {
+        if self.server_version >= MIN_SERVER_VER_MODELS_SUPPORT {
+            self.order.model_code = self.fields.read_string()?;
+        }
+        Ok(())
+    }
+
+    fn decode_short_sale_params(&mut self) -> Result<(), Error> {
+        self.order.short_sale_slot = self.fields.read_int()?;
+        self.order.designated_location = self.fields.read_string()?;
+        self.order.exempt_code = self.fields.read_int()?;
+        Ok(())
+    }
+
+    fn decode_box_order_params(&mut self) -> Result<(), Error> {
+        self.order.starting_price = self.fields.read_double_max()?;
+        self.order.stock_ref_price = self.fields.read_double_max()?;
+        self.order.delta = self.fields.read_double_max()?;
+        Ok(())
+    }
+
+    fn decode_peg_to_stk_or_vol_order_params(&mut self) -> Result<(), Error> {
+        self.order.stock_range_lower = self.fields.read_double_max()?;
+        self.order.stock_range_upper = self.fields.read_double_max()?;
+        Ok(())
+    }
+
+    fn decode_vol_order_params(&mut self, read_open_order_attribs: bool) -> Result<(), Error> {
+        let f = &mut self.fields;
+        self.order.volatility = f.read_double_max()?;
+        self.order.volatility_type = Some(f.read_int()?);
+        self.order.delta_neutral_order_type = f.read_string()?;
+        self.order.delta_neutral_aux_price = f.read_double_max()?;
+        if !self.order.delta_neutral_order_type.is_empty() {
+            self.order.delta_neutral_contract_id = f.read_int()?;
+            if read_open_order_attribs {
+                self.order.delta_neutral_settling_firm = f.read_string()?;
+                self.order.delta_neutral_clearing_account = f.read_string()?;
+                self.order.delta_neutral_clearing_intent = f.read_string()?;
+                self.order.delta_neutral_open_close = f.read_string()?;
+            }
+            self.order.delta_neutral_short_sale = f.read_bool()?;
+            self.order.delta_neutral_short_sale_slot = f.read_int()?;
+            self.order.delta_neutral_designated_location = f.read_string()?;
+        }
+        self.order.continuous_update = f.read_bool()?;
+        self.order.reference_price_type = Some(f.read_int()?);
+        Ok(())
+    }
+
+    fn decode_trail_params(&mut self) -> Result<(), Error> {
+        self.order.trail_stop_price = self.fields.read_double_max()?;
+        self.order.trailing_percent = self.fields.read_double_max()?;
+        Ok(())
+    }
+
+    fn decode_combo_legs(&mut self) -> Result<(), Error> {
+        let f = &mut self.fields;
+        let description = f.read_string()?;
+        if !description.is_empty() {
+            self.contract.combo_legs_description = Some(description);
+        }
+        let count = f.read_int()?;
+        for _ in 0..count {
+            self.contract.combo_legs.push(ComboLeg {
+                contract_id: f.read_int()? as usize,
+                ratio: f.read_int()?,
+                action: f.read_string()?,
+                exchange: f.read_string()?,
+                open_close: f.read_int()?,
+                short_sale_slot: f.read_int()?,
+                designated_location: f.read_string()?,
+                exempt_code: f.read_int()?,
+            });
+        }
+        let count = f.read_int()?;
+        for _ in 0..count {
+            self.order.order_combo_leg_prices.push(f.read_double_max()?);
+        }
+        Ok(())
+    }
+
+    fn decode_smart_combo_routing_params(&mut self) -> Result<(), Error> {
+        let count = self.fields.read_int()?;
+        for _ in 0..count {
+            self.order.smart_combo_routing_params.push(TagValue {
+                tag: self.fields.read_string()?,
+                value: self.fields.read_string()?,
+            });
+        }
+        Ok(())
+    }
+
+    fn decode_scale_order_params(&mut self) -> Result<(), Error> {
+        let f = &mut self.fields;
+        self.order.scale_init_level_size = f.read_int_max()?;
+        self.order.scale_subs_level_size = f.read_int_max()?;
+        self.order.scale_price_increment = f.read_double_max()?;
+        if let Some(increment) = self.order.scale_price_increment {
+            if increment > 0.0 {
+                self.order.scale_price_adjust_value = f.read_double_max()?;
+                self.order.scale_price_adjust_interval = f.read_int_max()?;
+                self.order.scale_profit_offset = f.read_double_max()?;
+                self.order.scale_auto_reset = f.read_bool()?;
+                self.order.scale_init_position = f.read_int_max()?;
+                self.order.scale_init_fill_qty = f.read_int_max()?;
+                self.order.scale_random_percent = f.read_bool()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_hedge_params(&mut self) -> Result<(), Error> {
+        self.order.hedge_type = self.fields.read_string()?;
+        if !self.order.hedge_type.is_empty() {
+            self.order.hedge_param = self.fields.read_string()?;
+        }
+        Ok(())
+    }
+
+    fn decode_delta_neutral(&mut self) -> Result<(), Error> {
+        if self.fields.read_bool()? {
+            self.contract.delta_neutral_contract = Some(DeltaNeutralContract {
+                contract_id: self.fields.read_int()? as usize,
+                delta: self.fields.read_double()?,
+                price: self.fields.read_double()?,
+            });
+        }
+        Ok(())
+    }
+
+    fn decode_algo_params(&mut self) -> Result<(), Error> {
+        self.order.algo_strategy = self.fields.read_string()?;
+        if !self.order.algo_strategy.is_empty() {
+            let count = self.fields.read_int()?;
+            for _ in 0..count {
+                self.order.algo_params.push(TagValue {
+                    tag: self.fields.read_string()?,
+                    value: self.fields.read_string()?,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_what_if_info_and_commission(&mut self) -> Result<(), Error> {
+        let f = &mut self.fields;
+        let state = &mut self.order_state;
+        self.order.what_if = f.read_bool()?;
+        state.status = f.read_string()?;
+        if self.server_version >= MIN_SERVER_VER_WHAT_IF_EXT_FIELDS {
+            state.init_margin_before = f.read_string()?;
+            state.maint_margin_before = f.read_string()?;
+            state.equity_with_loan_before = f.read_string()?;
+            state.init_margin_change = f.read_string()?;
+            state.maint_margin_change = f.read_string()?;
+            state.equity_with_loan_change = f.read_string()?;
+        }
+        state.init_margin_after = f.read_string()?;
+        state.maint_margin_after = f.read_string()?;
+        state.equity_with_loan_after = f.read_string()?;
+        state.commission = f.read_double_max()?;
+        state.min_commission = f.read_double_max()?;
+        state.max_commission = f.read_double_max()?;
+        state.commission_currency = f.read_string()?;
+        state.warning_text = f.read_string()?;
+        Ok(())
+    }
+
+    fn decode_peg_to_bench_params(&mut self) -> Result<(), Error> {
+        if self.server_version >= MIN_SERVER_VER_PEGGED_TO_BENCHMARK
+            && self.order.order_type == "PEG BENCH"
+        {
+            let f = &mut self.fields;
+            self.order.reference_contract_id = f.read_int()?;
+            self.order.is_pegged_change_amount_decrease = f.read_bool()?;
+            self.order.pegged_change_amount = f.read_double()?;
+            self.order.reference_change_amount = f.read_double()?;
+            self.order.reference_exchange_id = f.read_string()?;
+        }
+        Ok(())
+    }
+
+    fn decode_conditions(&mut self) -> Result<(), Error> {
+        if self.server_version < MIN_SERVER_VER_PEGGED_TO_BENCHMARK {
+            return Ok(());
+        }
+        let count = self.fields.read_int()?;
+        if count > 0 {
+            for _ in 0..count {
+                let condition = decode_condition(self.fields)?;
+                self.order.conditions.push(condition);
+            }
+            self.order.conditions_ignore_rth = self.fields.read_bool()?;
+            self.order.conditions_cancel_order = self.fields.read_bool()?;
+        }
+        Ok(())
+    }
+
+    fn decode_stop_price_and_lmt_price_offset(&mut self) -> Result<(), Error> {
+        self.order.trail_stop_price = self.fields.read_double_max()?;
+        self.order.lmt_price_offset = self.fields.read_double_max()?;
+        Ok(())
+    }
+
+    fn decode_adjusted_order_params(&mut self) -> Result<(), Error> {
+        if self.server_version >= MIN_SERVER_VER_PEGGED_TO_BENCHMARK {
+            self.order.adjusted_order_type = self.fields.read_string()?;
+            self.order.trigger_price = self.fields.read_double_max()?;
+            self.decode_stop_price_and_lmt_price_offset()?;
+            self.order.adjusted_stop_price = self.fields.read_double_max()?;
+            self.order.adjusted_stop_limit_price = self.fields.read_double_max()?;
+            self.order.adjusted_trailing_amount = self.fields.read_double_max()?;
+            self.order.adjustable_trailing_unit = self.fields.read_int()?;
+        }
+        Ok(())
+    }
+
+    fn decode_soft_dollar_tier(&mut self) -> Result<(), Error> {
+        if self.server_version >= MIN_SERVER_VER_SOFT_DOLLAR_TIER {
+            self.order.soft_dollar_tier.name = self.fields.read_string()?;
+            self.order.soft_dollar_tier.value = self.fields.read_string()?;
+            self.order.soft_dollar_tier.display_name = self.fields.read_string()?;
+        }
+        Ok(())
+    }
+
+    fn decode_cash_qty(&mut self) -> Result<(), Error> {
+        if self.server_version >= MIN_SERVER_VER_CASH_QTY {
+            self.order.cash_qty = self.fields.read_double_max()?;
+        }
+        Ok(())
+    }
+}
+
+/// Conditions are sent as their type id followed by the fields for that type
+fn decode_condition(f: &mut Fields) -> Result<OrderCondition, Error> {
+    let condition_type = f.read_int()?;
+    let conjunction_and = f.read_string()? == "a";
+    let condition = match condition_type {
+        1 => {
+            let is_more = f.read_bool()?;
+            let price = f.read_double()?;
+            OrderCondition::Price {
+                conjunction_and,
+                is_more,
+                price,
+                contract_id: f.read_int()? as usize,
+                exchange: f.read_string()?,
+                trigger_method: f.read_int()?,
+            }
+        }
+        3 => OrderCondition::Time {
+            conjunction_and,
+            is_more: f.read_bool()?,
+            time: f.read_string()?,
+        },
+        4 => OrderCondition::Margin {
+            conjunction_and,
+            is_more: f.read_bool()?,
+            percent: f.read_int()?,
+        },
+        5 => OrderCondition::Execution {
+            conjunction_and,
+            security_type: f.read_string()?,
+            exchange: f.read_string()?,
+            symbol: f.read_string()?,
+        },
+        6 => {
+            let is_more = f.read_bool()?;
+            let volume = f.read_int()?;
+            OrderCondition::Volume {
+                conjunction_and,
+                is_more,
+                volume,
+                contract_id: f.read_int()? as usize,
+                exchange: f.read_string()?,
+            }
+        }
+        7 => {
+            let is_more = f.read_bool()?;
+            let change_percent = f.read_double()?;
+            OrderCondition::PercentChange {
+                conjunction_and,
+                is_more,
+                change_percent,
+                contract_id: f.read_int()? as usize,
+                exchange: f.read_string()?,
+            }
+        }
+        other => return Err(anyhow!("Unknown order condition type: {}", other)),
+    };
+    Ok(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{IBField, InboundMessage};
+
+    fn message(fields: &[&str]) -> InboundMessage {
+        let mut msg = InboundMessage::new();
+        for f in fields {
+            msg.add_field(IBField::IBString(f.to_string()));
+        }
+        msg
+    }
+
+    #[test]
+    fn decode_open_order_at_max_server_version() {
+        let msg = message(&[
+            // order id and contract
+            "1",
+            "265598",
+            "AAPL",
+            "STK",
+            "",
+            "0",
+            "?",
+            "",
+            "SMART",
+            "USD",
+            "AAPL",
+            "NMS",
+            // action through order ref
+            "BUY",
+            "100",
+            "LMT",
+            "150.5",
+            "",
+            "DAY",
+            "",
+            "DU123",
+            "",
+            "0",
+            "",
+            // client id, perm id, outside rth, hidden, discretionary amount, good after time, shares allocation
+            "1",
+            "123456",
+            "0",
+            "0",
+            "0",
+            "",
+            "",
+            // fa params, model code, good till date, rule 80a, percent offset, settling firm
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            // short sale params, auction strategy, box params, peg to stock params
+            "0",
+            "",
+            "-1",
+            "0",
+            "",
+            "",
+            "",
+            "",
+            "",
+            // display size through trigger method
+            "0",
+            "0",
+            "0",
+            "0",
+            "",
+            "3",
+            "0",
+            "0",
+            "",
+            "0",
+            "0",
+            // vol order params, trail params, basis points
+            "",
+            "0",
+            "",
+            "",
+            "0",
+            "0",
+            "",
+            "",
+            "",
+            "",
+            // combo legs, smart combo routing params, scale params, hedge type
+            "",
+            "0",
+            "0",
+            "0",
+            "",
+            "",
+            "",
+            "",
+            // opt out smart routing, clearing params, not held, delta neutral, algo, solicited
+            "0",
+            "",
+            "",
+            "0",
+            "0",
+            "",
+            "0",
+            // what if, status and margin/commission fields
+            "0",
+            "Submitted",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "USD",
+            "",
+            // randomize flags
+            "0",
+            "0",
+            // one price condition, then ignore rth and cancel order
+            "1",
+            "1",
+            "a",
+            "1",
+            "100.5",
+            "265598",
+            "SMART",
+            "0",
+            "0",
+            "0",
+            // adjusted order params
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "0",
+            // soft dollar tier, cash qty, auto price for hedge, oms container, dpeg, price mgmt algo
+            "",
+            "",
+            "",
+            "",
+            "0",
+            "0",
+            "0",
+            "0",
+        ]);
+        let mut fields = Fields::new(&msg);
+        let open_order = decode_open_order(&mut fields, MAX_CLIENT_VER).unwrap();
+        assert_eq!(fields.remaining(), 0);
+        assert_eq!(open_order.order_id, 1);
+        assert_eq!(open_order.contract.symbol, "AAPL");
+        assert_eq!(open_order.contract.security_type, SecurityType::Stock);
+        assert_eq!(open_order.order.lmt_price, Some(150.5));
+        assert_eq!(open_order.order.aux_price, None);
+        assert_eq!(open_order.order.perm_id, 123456);
+        assert_eq!(open_order.order_state.status, "Submitted");
+        assert_eq!(open_order.order.conditions.len(), 1);
+        assert_eq!(open_order.order.use_price_mgmt_algo, Some(false));
+    }
+
+    #[test]
+    fn decode_completed_order_at_max_server_version() {
+        let msg = message(&[
+            // contract
+            "265598",
+            "AAPL",
+            "STK",
+            "",
+            "0",
+            "",
+            "",
+            "SMART",
+            "USD",
+            "AAPL",
+            "NMS",
+            // action through order ref
+            "BUY",
+            "100",
+            "LMT",
+            "150.5",
+            "",
+            "DAY",
+            "",
+            "DU123",
+            "",
+            "0",
+            "",
+            // perm id, outside rth, hidden, discretionary amount, good after time
+            "42",
+            "0",
+            "0",
+            "0",
+            "",
+            // fa params, model code
+            "",
+            "",
+            "",
+            "",
+            "",
+            // good till date, rule 80A, percent offset, settling firm
+            "",
+            "",
+            "",
+            "",
+            // short sale, box and peg to stock params
+            "0",
+            "",
+            "-1",
+            "",
+            "",
+            "",
+            "",
+            "",
+            // display size, sweep to fill, all or none, min qty, oca type, trigger method
+            "0",
+            "0",
+            "0",
+            "",
+            "3",
+            "0",
+            // volatility params without the open order attributes
+            "",
+            "0",
+            "",
+            "",
+            "0",
+            "0",
+            // trail params, combo legs, smart combo routing, scale params
+            "",
+            "",
+            "",
+            "0",
+            "0",
+            "0",
+            "",
+            "",
+            "",
+            // hedge type, clearing, not held, delta neutral, algo, solicited
+            "",
+            "",
+            "",
+            "0",
+            "0",
+            "",
+            "0",
+            // status, randomize flags, conditions, stop price and limit offset, cash qty
+            "Filled",
+            "0",
+            "0",
+            "0",
+            "",
+            "",
+            "",
+            // hedge auto price, oms container
+            "0",
+            "0",
+            // auto cancel date through parent perm id
+            "",
+            "100",
+            "0",
+            "0",
+            "",
+            "0",
+            "0",
+            "0",
+            "20201020 09:30:05 America/New_York",
+            "Filled Size: 100",
+        ]);
+        let mut fields = Fields::new(&msg);
+        let completed = decode_completed_order(&mut fields, MAX_CLIENT_VER).unwrap();
+        assert_eq!(fields.remaining(), 0);
+        assert_eq!(completed.contract.symbol, "AAPL");
+        assert_eq!(completed.order.perm_id, 42);
+        assert_eq!(completed.order.lmt_price, Some(150.5));
+        assert_eq!(completed.order.oca_type, 3);
+        assert_eq!(completed.order.filled_quantity, Some(100.into()));
+        assert_eq!(completed.order_state.status, "Filled");
+        assert_eq!(
+            completed.order_state.completed_time,
+            "20201020 09:30:05 America/New_York"
+        );
+        assert_eq!(completed.order_state.completed_status, "Filled Size: 100");
+    }
+}
@@ -0,0 +1,351 @@
+//! Encodes the `PlaceOrder` message. The field order follows the Python API's `placeOrder`, with
+//! the branches for servers older than v100 left out.
+
+use crate::contract::{add_contract_fields, Contract, SecurityType};
+use crate::message::{Message, OutboundMessages};
+use crate::order::{encode_tag_values, Order, OrderCondition, OrderId};
+use crate::server_versions::*;
+
+pub fn encode_place_order(
+    server_version: usize,
+    order_id: OrderId,
+    contract: &Contract,
+    order: &Order,
+) -> Message {
+    let mut msg = Message::outbound(OutboundMessages::PlaceOrder);
+    if server_version < MIN_SERVER_VER_ORDER_CONTAINER {
+        msg.add_field(45);
+    }
+    msg.add_field(order_id);
+
+    add_contract_fields(&mut msg, contract, true);
+    msg.add_field(&contract.trading_class);
+    msg.add_field(contract.security_id_type.map(|t| t.as_str().to_string()));
+    msg.add_field(&contract.security_id);
+
+    // main order fields
+    msg.add_field(&order.action);
+    msg.add_field(order.total_quantity);
+    msg.add_field(&order.order_type);
+    msg.add_field(order.lmt_price);
+    msg.add_field(order.aux_price);
+
+    // extended order fields
+    msg.add_field(&order.tif);
+    msg.add_field(&order.oca_group);
+    msg.add_field(&order.account);
+    msg.add_field(&order.open_close);
+    msg.add_field(order.origin);
+    msg.add_field(&order.order_ref);
+    msg.add_field(order.transmit);
+    msg.add_field(order.parent_id);
+    msg.add_field(order.block_order);
+    msg.add_field(order.sweep_to_fill);
+    msg.add_field(order.display_size);
+    msg.add_field(order.trigger_method);
+    msg.add_field(order.outside_rth);
+    msg.add_field(order.hidden);
+
+    if contract.security_type == SecurityType::Combo {
+        msg.add_field(contract.combo_legs.len());
+        for leg in &contract.combo_legs {
+            msg.add_field(leg.contract_id);
+            msg.add_field(leg.ratio);
+            msg.add_field(&leg.action);
+            msg.add_field(&leg.exchange);
+            msg.add_field(leg.open_close);
+            msg.add_field(leg.short_sale_slot);
+            msg.add_field(&leg.designated_location);
+            msg.add_field(leg.exempt_code);
+        }
+        msg.add_field(order.order_combo_leg_prices.len());
+        for price in &order.order_combo_leg_prices {
+            msg.add_field(*price);
+        }
+        msg.add_field(order.smart_combo_routing_params.len());
+        for param in &order.smart_combo_routing_params {
+            msg.add_field(&param.tag);
+            msg.add_field(&param.value);
+        }
+    }
+
+    // deprecated sharesAllocation field
+    msg.add_field("");
+    msg.add_field(order.discretionary_amt);
+    msg.add_field(&order.good_after_time);
+    msg.add_field(&order.good_till_date);
+    msg.add_field(&order.fa_group);
+    msg.add_field(&order.fa_method);
+    msg.add_field(&order.fa_percentage);
+    msg.add_field(&order.fa_profile);
+    if server_version >= MIN_SERVER_VER_MODELS_SUPPORT {
+        msg.add_field(&order.model_code);
+    }
+
+    // institutional short sale slot data
+    msg.add_field(order.short_sale_slot);
+    msg.add_field(&order.designated_location);
+    msg.add_field(order.exempt_code);
+
+    msg.add_field(order.oca_type);
+    msg.add_field(&order.rule_80a);
+    msg.add_field(&order.settling_firm);
+    msg.add_field(order.all_or_none);
+    msg.add_field(order.min_qty);
+    msg.add_field(order.percent_offset);
+    msg.add_field(order.e_trade_only);
+    msg.add_field(order.firm_quote_only);
+    msg.add_field(order.nbbo_price_cap);
+    msg.add_field(order.auction_strategy);
+    msg.add_field(order.starting_price);
+    msg.add_field(order.stock_ref_price);
+    msg.add_field(order.delta);
+    msg.add_field(order.stock_range_lower);
+    msg.add_field(order.stock_range_upper);
+    msg.add_field(order.override_percentage_constraints);
+
+    // volatility orders
+    msg.add_field(order.volatility);
+    msg.add_field(order.volatility_type);
+    msg.add_field(&order.delta_neutral_order_type);
+    msg.add_field(order.delta_neutral_aux_price);
+    if !order.delta_neutral_order_type.is_empty() {
+        msg.add_field(order.delta_neutral_contract_id);
+        msg.add_field(&order.delta_neutral_settling_firm);
+        msg.add_field(&order.delta_neutral_clearing_account);
+        msg.add_field(&order.delta_neutral_clearing_intent);
+        msg.add_field(&order.delta_neutral_open_close);
+        msg.add_field(order.delta_neutral_short_sale);
+        msg.add_field(order.delta_neutral_short_sale_slot);
+        msg.add_field(&order.delta_neutral_designated_location);
+    }
+    msg.add_field(order.continuous_update);
+    msg.add_field(order.reference_price_type);
+    msg.add_field(order.trail_stop_price);
+    msg.add_field(order.trailing_percent);
+
+    // scale orders
+    msg.add_field(order.scale_init_level_size);
+    msg.add_field(order.scale_subs_level_size);
+    msg.add_field(order.scale_price_increment);
+    if let Some(increment) = order.scale_price_increment {
+        if increment > 0.0 {
+            msg.add_field(order.scale_price_adjust_value);
+            msg.add_field(order.scale_price_adjust_interval);
+            msg.add_field(order.scale_profit_offset);
+            msg.add_field(order.scale_auto_reset);
+            msg.add_field(order.scale_init_position);
+            msg.add_field(order.scale_init_fill_qty);
+            msg.add_field(order.scale_random_percent);
+        }
+    }
+    msg.add_field(&order.scale_table);
+    msg.add_field(&order.active_start_time);
+    msg.add_field(&order.active_stop_time);
+
+    // hedge orders
+    msg.add_field(&order.hedge_type);
+    if !order.hedge_type.is_empty() {
+        msg.add_field(&order.hedge_param);
+    }
+
+    msg.add_field(order.opt_out_smart_routing);
+    msg.add_field(&order.clearing_account);
+    msg.add_field(&order.clearing_intent);
+    msg.add_field(order.not_held);
+
+    match &contract.delta_neutral_contract {
+        Some(dn) => {
+            msg.add_field(true);
+            msg.add_field(dn.contract_id);
+            msg.add_field(dn.delta);
+            msg.add_field(dn.price);
+        }
+        None => msg.add_field(false),
+    }
+
+    msg.add_field(&order.algo_strategy);
+    if !order.algo_strategy.is_empty() {
+        msg.add_field(order.algo_params.len());
+        for param in &order.algo_params {
+            msg.add_field(&param.tag);
+            msg.add_field(&param.value);
+        }
+    }
+    msg.add_field(&order.algo_id);
+    msg.add_field(order.what_if);
+
+    msg.add_field(encode_tag_values(&order.order_misc_options));
+    msg.add_field(order.solicited);
+    msg.add_field(order.randomize_size);
+    msg.add_field(order.randomize_price);
+
+    if server_version >= MIN_SERVER_VER_PEGGED_TO_BENCHMARK {
+        if order.order_type == "PEG BENCH" {
+            msg.add_field(order.reference_contract_id);
+            msg.add_field(order.is_pegged_change_amount_decrease);
+            msg.add_field(order.pegged_change_amount);
+            msg.add_field(order.reference_change_amount);
+            msg.add_field(&order.reference_exchange_id);
+        }
+        msg.add_field(order.conditions.len());
+        if !order.conditions.is_empty() {
+            for condition in &order.conditions {
+                add_condition_fields(&mut msg, condition);
+            }
+            msg.add_field(order.conditions_ignore_rth);
+            msg.add_field(order.conditions_cancel_order);
+        }
+        msg.add_field(&order.adjusted_order_type);
+        msg.add_field(order.trigger_price);
+        msg.add_field(order.lmt_price_offset);
+        msg.add_field(order.adjusted_stop_price);
+        msg.add_field(order.adjusted_stop_limit_price);
+        msg.add_field(order.adjusted_trailing_amount);
+        msg.add_field(order.adjustable_trailing_unit);
+    }
+    if server_version >= MIN_SERVER_VER_EXT_OPERATOR {
+        msg.add_field(&order.ext_operator);
+    }
+    if server_version >= MIN_SERVER_VER_SOFT_DOLLAR_TIER {
+        msg.add_field(&order.soft_dollar_tier.name);
+        msg.add_field(&order.soft_dollar_tier.value);
+    }
+    if server_version >= MIN_SERVER_VER_CASH_QTY {
+        msg.add_field(order.cash_qty);
+    }
+    if server_version >= MIN_SERVER_VER_DECISION_MAKER {
+        msg.add_field(&order.mifid2_decision_maker);
+        msg.add_field(&order.mifid2_decision_algo);
+    }
+    if server_version >= MIN_SERVER_VER_MIFID_EXECUTION {
+        msg.add_field(&order.mifid2_execution_trader);
+        msg.add_field(&order.mifid2_execution_algo);
+    }
+    if server_version >= MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE {
+        msg.add_field(order.dont_use_auto_price_for_hedge);
+    }
+    if server_version >= MIN_SERVER_VER_ORDER_CONTAINER {
+        msg.add_field(order.is_oms_container);
+    }
+    if server_version >= MIN_SERVER_VER_D_PEG_ORDERS {
+        msg.add_field(order.discretionary_up_to_limit_price);
+    }
+    if server_version >= MIN_SERVER_VER_PRICE_MGMT_ALGO {
+        msg.add_field(order.use_price_mgmt_algo);
+    }
+    msg
+}
+
+/// Conditions are sent as their type id followed by the fields for that type
+fn add_condition_fields(msg: &mut Message, condition: &OrderCondition) {
+    msg.add_field(condition.condition_type());
+    let conjunction = |and: bool| if and { "a" } else { "o" };
+    match condition {
+        OrderCondition::Price {
+            conjunction_and,
+            is_more,
+            price,
+            contract_id,
+            exchange,
+            trigger_method,
+        } => {
+            msg.add_field(conjunction(*conjunction_and));
+            msg.add_field(*is_more);
+            msg.add_field(*price);
+            msg.add_field(*contract_id);
+            msg.add_field(exchange);
+            msg.add_field(*trigger_method);
+        }
+        OrderCondition::Time {
+            conjunction_and,
+            is_more,
+            time,
+        } => {
+            msg.add_field(conjunction(*conjunction_and));
+            msg.add_field(*is_more);
+            msg.add_field(time);
+        }
+        OrderCondition::Margin {
+            conjunction_and,
+            is_more,
+            percent,
+        } => {
+            msg.add_field(conjunction(*conjunction_and));
+            msg.add_field(*is_more);
+            msg.add_field(*percent);
+        }
+        OrderCondition::Execution {
+            conjunction_and,
+            security_type,
+            exchange,
+            symbol,
+        } => {
+            msg.add_field(conjunction(*conjunction_and));
+            msg.add_field(security_type);
+            msg.add_field(exchange);
+            msg.add_field(symbol);
+        }
+        OrderCondition::Volume {
+            conjunction_and,
+            is_more,
+            volume,
+            contract_id,
+            exchange,
+        } => {
+            msg.add_field(conjunction(*conjunction_and));
+            msg.add_field(*is_more);
+            msg.add_field(*volume);
+            msg.add_field(*contract_id);
+            msg.add_field(exchange);
+        }
+        OrderCondition::PercentChange {
+            conjunction_and,
+            is_more,
+            change_percent,
+            contract_id,
+            exchange,
+        } => {
+            msg.add_field(conjunction(*conjunction_and));
+            msg.add_field(*is_more);
+            msg.add_field(*change_percent);
+            msg.add_field(*contract_id);
+            msg.add_field(exchange);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::InboundMessage;
+    use rust_decimal::prelude::*;
+
+    #[test]
+    fn encode_limit_order_at_max_server_version() {
+        let contract = Contract::stock("AAPL", "SMART", "USD");
+        let order = Order {
+            action: "BUY".to_string(),
+            total_quantity: Decimal::from(100),
+            order_type: "LMT".to_string(),
+            lmt_price: Some(150.25),
+            tif: "DAY".to_string(),
+            ..Default::default()
+        };
+        let msg = encode_place_order(MAX_CLIENT_VER, 7, &contract, &order);
+        let fields: Vec<String> = InboundMessage::from_bytes(&msg.to_bytes())
+            .unwrap()
+            .fields()
+            .iter()
+            .map(|f| f.to_text())
+            .collect();
+        // No message version once the server supports order containers
+        assert_eq!(&fields[..4], &["3", "7", "0", "AAPL"]);
+        assert_eq!(fields[4], "STK");
+        assert_eq!(fields[9], "SMART");
+        assert_eq!(fields[11], "USD");
+        assert_eq!(&fields[16..22], &["BUY", "100", "LMT", "150.25", "", "DAY"]);
+        // use_price_mgmt_algo is unset
+        assert_eq!(fields.last().unwrap(), "");
+    }
+}
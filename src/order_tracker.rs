@@ -0,0 +1,409 @@
+//! Keeps the live state of every order we hear about, built from `OpenOrder`, `OrderStatus` and
+//! `OrderBound` messages. Orders are keyed by their perm id, which the server assigns and which is
+//! unique across clients; API order ids are only unique per client id.
+
+use crate::contract::Contract;
+use crate::decoder::{Response, TwsError};
+use crate::order::{
+    OpenOrder, Order, OrderBound, OrderId, OrderState, OrderStatus, OrderStatusUpdate, PermId,
+};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use rust_decimal::prelude::*;
+use std::collections::HashMap;
+
+/// The latest known state of an order. `contract`, `order` and `order_state` are filled in once an
+/// `OpenOrder` message has been seen for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedOrder {
+    pub order_id: OrderId,
+    pub client_id: i32,
+    pub perm_id: PermId,
+    pub parent_id: OrderId,
+    pub status: OrderStatus,
+    pub filled: Decimal,
+    pub remaining: Decimal,
+    pub avg_fill_price: f64,
+    pub last_fill_price: f64,
+    pub why_held: String,
+    pub market_cap_price: f64,
+    pub contract: Option<Contract>,
+    pub order: Option<Order>,
+    pub order_state: Option<OrderState>,
+    /// The last error the server reported for this order
+    pub last_error: Option<TwsError>,
+    /// How many `OpenOrder` messages have been applied, so an echo of new terms can be told
+    /// apart from one sent before them
+    pub revision: u32,
+}
+
+impl TrackedOrder {
+    fn new(order_id: OrderId, client_id: i32, perm_id: PermId) -> TrackedOrder {
+        TrackedOrder {
+            order_id,
+            client_id,
+            perm_id,
+            parent_id: 0,
+            status: OrderStatus::ApiPending,
+            filled: Decimal::zero(),
+            remaining: Decimal::zero(),
+            avg_fill_price: 0.0,
+            last_fill_price: 0.0,
+            why_held: String::new(),
+            market_cap_price: 0.0,
+            contract: None,
+            order: None,
+            order_state: None,
+            last_error: None,
+            revision: 0,
+        }
+    }
+}
+
+/// Changes to tracked orders, sent to every subscriber
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderEvent {
+    /// The first message we've seen for an order
+    New(TrackedOrder),
+    /// The order moved to a different status
+    StatusChanged {
+        previous: OrderStatus,
+        order: TrackedOrder,
+    },
+    /// Fill progress or order details changed without a change in status
+    Updated(TrackedOrder),
+    /// An order placed outside the API was bound to an API order id
+    Bound(OrderBound),
+    /// The server reported an error for one of our orders
+    Error { order_id: OrderId, error: TwsError },
+}
+
+impl OrderEvent {
+    /// The API order id the event relates to
+    pub fn order_id(&self) -> OrderId {
+        match self {
+            OrderEvent::New(o) | OrderEvent::Updated(o) => o.order_id,
+            OrderEvent::StatusChanged { order, .. } => order.order_id,
+            OrderEvent::Bound(b) => b.api_order_id,
+            OrderEvent::Error { order_id, .. } => *order_id,
+        }
+    }
+}
+
+pub struct OrderTracker {
+    client_id: i32,
+    orders: HashMap<PermId, TrackedOrder>,
+    /// Our own orders that the server hasn't assigned a perm id to yet
+    unassigned: HashMap<OrderId, TrackedOrder>,
+    perm_ids: HashMap<(i32, OrderId), PermId>,
+    subscribers: Vec<Sender<OrderEvent>>,
+}
+
+impl OrderTracker {
+    /// `client_id` is our own client id, used to resolve the order ids in error messages
+    pub fn new(client_id: i32) -> OrderTracker {
+        OrderTracker {
+            client_id,
+            orders: HashMap::new(),
+            unassigned: HashMap::new(),
+            perm_ids: HashMap::new(),
+            subscribers: vec![],
+        }
+    }
+
+    /// Returns a channel that receives every subsequent change to tracked orders
+    pub fn subscribe(&mut self) -> Receiver<OrderEvent> {
+        let (tx, rx) = unbounded();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Snapshot of every tracked order
+    pub fn orders(&self) -> Vec<TrackedOrder> {
+        self.orders
+            .values()
+            .chain(self.unassigned.values())
+            .cloned()
+            .collect()
+    }
+
+    /// Snapshot of one of our own orders
+    pub fn get(&self, order_id: OrderId) -> Option<TrackedOrder> {
+        match self.perm_id(self.client_id, order_id) {
+            Some(perm_id) => self.orders.get(&perm_id).cloned(),
+            None => self.unassigned.get(&order_id).cloned(),
+        }
+    }
+
+    pub fn get_by_perm_id(&self, perm_id: PermId) -> Option<TrackedOrder> {
+        self.orders.get(&perm_id).cloned()
+    }
+
+    /// The perm id the server assigned to the given client's API order id
+    pub fn perm_id(&self, client_id: i32, order_id: OrderId) -> Option<PermId> {
+        self.perm_ids.get(&(client_id, order_id)).copied()
+    }
+
+    /// Applies an order-related response. Returns false for anything the tracker doesn't handle,
+    /// including errors that don't belong to a known order.
+    pub fn handle(&mut self, response: &Response) -> bool {
+        match response {
+            Response::OrderStatus(status) => {
+                self.on_order_status(status);
+                true
+            }
+            Response::OpenOrder(open_order) => {
+                self.on_open_order(open_order);
+                true
+            }
+            Response::OrderBound(bound) => {
+                self.on_order_bound(bound);
+                true
+            }
+            Response::Error(error) => self.on_error(error),
+            _ => false,
+        }
+    }
+
+    fn on_order_status(&mut self, update: &OrderStatusUpdate) {
+        let (order, is_new) = self.entry(update.client_id, update.order_id, update.perm_id);
+        let previous = order.status.clone();
+        let before = order.clone();
+        order.status = update.status.clone();
+        order.filled = update.filled;
+        order.remaining = update.remaining;
+        order.avg_fill_price = update.avg_fill_price;
+        order.last_fill_price = update.last_fill_price;
+        order.parent_id = update.parent_id;
+        order.why_held = update.why_held.clone();
+        order.market_cap_price = update.market_cap_price;
+        let snapshot = order.clone();
+        self.publish_change(is_new, previous, before, snapshot);
+    }
+
+    fn on_open_order(&mut self, open_order: &OpenOrder) {
+        let o = &open_order.order;
+        let (order, is_new) = self.entry(o.client_id, open_order.order_id, o.perm_id);
+        let previous = order.status.clone();
+        let before = order.clone();
+        let status = OrderStatus::from(open_order.order_state.status.as_str());
+        if !open_order.order_state.status.is_empty() && (is_new || !order.status.is_done()) {
+            order.status = status;
+        }
+        order.parent_id = o.parent_id;
+        order.contract = Some(open_order.contract.clone());
+        order.order = Some(o.clone());
+        order.order_state = Some(open_order.order_state.clone());
+        order.revision += 1;
+        if is_new {
+            order.remaining = o.total_quantity;
+        }
+        let snapshot = order.clone();
+        self.publish_change(is_new, previous, before, snapshot);
+    }
+
+    fn on_order_bound(&mut self, bound: &OrderBound) {
+        if let Some(order) = self.orders.get_mut(&bound.perm_id) {
+            // The order no longer answers to the ids it had before it was bound
+            let old_key = (order.client_id, order.order_id);
+            if self.perm_ids.get(&old_key) == Some(&bound.perm_id) {
+                self.perm_ids.remove(&old_key);
+            }
+            order.order_id = bound.api_order_id;
+            order.client_id = bound.api_client_id;
+        }
+        self.perm_ids
+            .insert((bound.api_client_id, bound.api_order_id), bound.perm_id);
+        self.publish(OrderEvent::Bound(*bound));
+    }
+
+    fn on_error(&mut self, error: &TwsError) -> bool {
+        let order_id = error.id;
+        let order = match self.perm_id(self.client_id, order_id) {
+            Some(perm_id) => self.orders.get_mut(&perm_id),
+            None => self.unassigned.get_mut(&order_id),
+        };
+        match order {
+            Some(order) => {
+                order.last_error = Some(error.clone());
+                self.publish(OrderEvent::Error {
+                    order_id,
+                    error: error.clone(),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Starts tracking one of our own orders before the server has acknowledged it, so errors
+    /// for it can be matched up
+    pub fn track(&mut self, order_id: OrderId, contract: &Contract, order: &Order) {
+        if self.perm_id(self.client_id, order_id).is_some() {
+            return;
+        }
+        let mut tracked = TrackedOrder::new(order_id, self.client_id, 0);
+        tracked.remaining = order.total_quantity;
+        tracked.contract = Some(contract.clone());
+        tracked.order = Some(order.clone());
+        self.unassigned.insert(order_id, tracked);
+    }
+
+    /// Finds or creates the tracked order for the given ids. Orders without a perm id yet are kept
+    /// aside by order id and moved over once one arrives.
+    fn entry(
+        &mut self,
+        client_id: i32,
+        order_id: OrderId,
+        perm_id: PermId,
+    ) -> (&mut TrackedOrder, bool) {
+        if perm_id == 0 {
+            let is_new = !self.unassigned.contains_key(&order_id);
+            let order = self
+                .unassigned
+                .entry(order_id)
+                .or_insert_with(|| TrackedOrder::new(order_id, client_id, 0));
+            return (order, is_new);
+        }
+        if order_id != 0 {
+            self.perm_ids.insert((client_id, order_id), perm_id);
+        }
+        let mut is_new = false;
+        if !self.orders.contains_key(&perm_id) {
+            let order = match self.unassigned.remove(&order_id) {
+                Some(mut order) if client_id == self.client_id => {
+                    order.perm_id = perm_id;
+                    order
+                }
+                Some(order) => {
+                    self.unassigned.insert(order_id, order);
+                    is_new = true;
+                    TrackedOrder::new(order_id, client_id, perm_id)
+                }
+                None => {
+                    is_new = true;
+                    TrackedOrder::new(order_id, client_id, perm_id)
+                }
+            };
+            self.orders.insert(perm_id, order);
+        }
+        (self.orders.get_mut(&perm_id).unwrap(), is_new)
+    }
+
+    fn publish_change(
+        &mut self,
+        is_new: bool,
+        previous: OrderStatus,
+        before: TrackedOrder,
+        order: TrackedOrder,
+    ) {
+        if is_new {
+            self.publish(OrderEvent::New(order));
+        } else if previous != order.status {
+            self.publish(OrderEvent::StatusChanged { previous, order });
+        } else if before != order {
+            self.publish(OrderEvent::Updated(order));
+        }
+    }
+
+    fn publish(&mut self, event: OrderEvent) {
+        // Subscribers that have gone away are dropped
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(
+        order_id: OrderId,
+        perm_id: PermId,
+        status: OrderStatus,
+        filled: i64,
+        remaining: i64,
+    ) -> Response {
+        Response::OrderStatus(OrderStatusUpdate {
+            order_id,
+            status,
+            filled: Decimal::from(filled),
+            remaining: Decimal::from(remaining),
+            avg_fill_price: 0.0,
+            perm_id,
+            parent_id: 0,
+            last_fill_price: 0.0,
+            client_id: 1,
+            why_held: String::new(),
+            market_cap_price: 0.0,
+        })
+    }
+
+    #[test]
+    fn tracks_status_changes_and_fills() {
+        let mut tracker = OrderTracker::new(1);
+        let events = tracker.subscribe();
+        tracker.handle(&status(5, 100, OrderStatus::Submitted, 0, 10));
+        tracker.handle(&status(5, 100, OrderStatus::Submitted, 4, 6));
+        tracker.handle(&status(5, 100, OrderStatus::Filled, 10, 0));
+
+        assert!(matches!(events.try_recv().unwrap(), OrderEvent::New(_)));
+        assert!(matches!(events.try_recv().unwrap(), OrderEvent::Updated(_)));
+        match events.try_recv().unwrap() {
+            OrderEvent::StatusChanged { previous, order } => {
+                assert_eq!(previous, OrderStatus::Submitted);
+                assert_eq!(order.status, OrderStatus::Filled);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        let order = tracker.get(5).unwrap();
+        assert_eq!(order.filled, Decimal::from(10));
+        assert_eq!(tracker.perm_id(1, 5), Some(100));
+    }
+
+    #[test]
+    fn unassigned_orders_move_over_once_perm_id_arrives() {
+        let mut tracker = OrderTracker::new(1);
+        tracker.track(7, &Contract::default(), &Order::default());
+        assert!(tracker.handle(&Response::Error(TwsError {
+            id: 7,
+            code: 201,
+            message: "Order rejected".to_string(),
+        })));
+        tracker.handle(&status(7, 200, OrderStatus::PreSubmitted, 0, 1));
+        assert_eq!(tracker.orders().len(), 1);
+        assert_eq!(
+            tracker
+                .get_by_perm_id(200)
+                .unwrap()
+                .last_error
+                .unwrap()
+                .code,
+            201
+        );
+    }
+
+    #[test]
+    fn bound_orders_map_to_perm_ids() {
+        let mut tracker = OrderTracker::new(0);
+        tracker.handle(&Response::OrderBound(OrderBound {
+            perm_id: 300,
+            api_client_id: 0,
+            api_order_id: -2,
+        }));
+        assert_eq!(tracker.perm_id(0, -2), Some(300));
+    }
+
+    #[test]
+    fn binding_drops_the_previous_order_id() {
+        let mut tracker = OrderTracker::new(1);
+        tracker.handle(&status(4, 300, OrderStatus::Submitted, 0, 1));
+        assert_eq!(tracker.perm_id(1, 4), Some(300));
+        tracker.handle(&Response::OrderBound(OrderBound {
+            perm_id: 300,
+            api_client_id: 1,
+            api_order_id: -2,
+        }));
+        assert_eq!(tracker.perm_id(1, 4), None);
+        assert_eq!(tracker.perm_id(1, -2), Some(300));
+        assert_eq!(tracker.get(-2).unwrap().perm_id, 300);
+    }
+}
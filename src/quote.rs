@@ -0,0 +1,395 @@
+//! Merges market data ticks into one `Quote` per subscription, so consumers can read the latest
+//! prices and sizes without handling each tick themselves. The cache lives in the dispatcher and
+//! is updated as ticks are decoded; `LiveQuote` is the caller's handle on one entry.
+
+use crate::decoder::{Response, TwsError};
+use crate::dispatcher::Dispatcher;
+use crate::market_data::{MarketDataType, Tick, TickType};
+use crate::message::Message;
+use anyhow::*;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use log::*;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// A value and when it last changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timestamped<T> {
+    pub value: T,
+    pub time: SystemTime,
+}
+
+/// Trading halt state, from the halted generic tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Halted {
+    NotHalted,
+    General,
+    Volatility,
+    Unknown,
+}
+
+impl From<f64> for Halted {
+    fn from(v: f64) -> Self {
+        match v as i32 {
+            0 => Halted::NotHalted,
+            1 => Halted::General,
+            2 => Halted::Volatility,
+            _ => Halted::Unknown,
+        }
+    }
+}
+
+/// The latest top-of-book values for one instrument. Delayed ticks fill the same fields as live
+/// ones; `market_data_type` tells them apart, and the values are cleared when the subscription
+/// switches between live and delayed data so the two are never mixed. Fields are `None` until the
+/// server has sent a value, or when it reports one as unavailable.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Quote {
+    pub bid: Option<Timestamped<f64>>,
+    pub ask: Option<Timestamped<f64>>,
+    pub last: Option<Timestamped<f64>>,
+    pub bid_size: Option<Timestamped<Decimal>>,
+    pub ask_size: Option<Timestamped<Decimal>>,
+    pub last_size: Option<Timestamped<Decimal>>,
+    pub volume: Option<Timestamped<Decimal>>,
+    pub high: Option<Timestamped<f64>>,
+    pub low: Option<Timestamped<f64>>,
+    pub close: Option<Timestamped<f64>>,
+    pub open: Option<Timestamped<f64>>,
+    /// Needs generic tick 49 for live data
+    pub halted: Option<Timestamped<Halted>>,
+    /// Above 2.5 means shares are available to short, above 1.5 that they are hard to borrow.
+    /// Needs generic tick 236.
+    pub shortable: Option<Timestamped<f64>>,
+    /// Whether the values are live, frozen or delayed
+    pub market_data_type: Option<MarketDataType>,
+    /// The error the server stopped the subscription with, if any
+    pub error: Option<TwsError>,
+    /// When any field last changed
+    pub updated: Option<SystemTime>,
+}
+
+impl Quote {
+    /// Applies a tick. Returns whether any field changed.
+    pub fn apply(&mut self, tick: &Tick, time: SystemTime) -> bool {
+        let changed = match tick {
+            Tick::Price {
+                tick_type,
+                price,
+                size,
+                ..
+            } => {
+                // The server sends -1 when there is no price, e.g. outside trading hours
+                let price = if *price == -1.0 { None } else { Some(*price) };
+                let price_changed = match self.price_field(*tick_type) {
+                    Some(field) => set(field, price, time),
+                    None => false,
+                };
+                let size_changed = match (tick_type.size_tick_type(), size) {
+                    (Some(size_type), Some(size)) => match self.size_field(size_type) {
+                        Some(field) => set(field, Some(*size), time),
+                        None => false,
+                    },
+                    _ => false,
+                };
+                price_changed || size_changed
+            }
+            Tick::Size { tick_type, size } => match self.size_field(*tick_type) {
+                Some(field) => set(field, Some(*size), time),
+                None => false,
+            },
+            Tick::Generic { tick_type, value } => match tick_type {
+                TickType::Halted | TickType::DelayedHalted => {
+                    set(&mut self.halted, Some(Halted::from(*value)), time)
+                }
+                TickType::Shortable => set(&mut self.shortable, Some(*value), time),
+                _ => false,
+            },
+            Tick::MarketDataType(data_type) => {
+                if self.market_data_type == Some(*data_type) {
+                    return false;
+                }
+                if let Some(previous) = self.market_data_type {
+                    if previous.is_delayed() != data_type.is_delayed() {
+                        *self = Quote {
+                            error: self.error.take(),
+                            ..Quote::default()
+                        };
+                    }
+                }
+                self.market_data_type = Some(*data_type);
+                true
+            }
+            _ => false,
+        };
+        if changed {
+            self.updated = Some(time);
+        }
+        changed
+    }
+
+    fn price_field(&mut self, tick_type: TickType) -> Option<&mut Option<Timestamped<f64>>> {
+        match tick_type {
+            TickType::Bid | TickType::DelayedBid => Some(&mut self.bid),
+            TickType::Ask | TickType::DelayedAsk => Some(&mut self.ask),
+            TickType::Last | TickType::DelayedLast => Some(&mut self.last),
+            TickType::High | TickType::DelayedHigh => Some(&mut self.high),
+            TickType::Low | TickType::DelayedLow => Some(&mut self.low),
+            TickType::Close | TickType::DelayedClose => Some(&mut self.close),
+            TickType::Open | TickType::DelayedOpen => Some(&mut self.open),
+            _ => None,
+        }
+    }
+
+    fn size_field(&mut self, tick_type: TickType) -> Option<&mut Option<Timestamped<Decimal>>> {
+        match tick_type {
+            TickType::BidSize | TickType::DelayedBidSize => Some(&mut self.bid_size),
+            TickType::AskSize | TickType::DelayedAskSize => Some(&mut self.ask_size),
+            TickType::LastSize | TickType::DelayedLastSize => Some(&mut self.last_size),
+            TickType::Volume | TickType::DelayedVolume => Some(&mut self.volume),
+            _ => None,
+        }
+    }
+}
+
+/// Sets a field if the value differs, stamping it with `time`
+fn set<T: PartialEq>(
+    field: &mut Option<Timestamped<T>>,
+    value: Option<T>,
+    time: SystemTime,
+) -> bool {
+    let unchanged = match (field.as_ref(), value.as_ref()) {
+        (Some(current), Some(value)) => current.value == *value,
+        (None, None) => true,
+        _ => false,
+    };
+    if unchanged {
+        return false;
+    }
+    *field = value.map(|value| Timestamped { value, time });
+    true
+}
+
+struct QuoteSlot {
+    quote: Arc<RwLock<Quote>>,
+    /// Holds at most one pending notification, so a slow reader only ever sees the latest quote
+    changed: Sender<()>,
+}
+
+/// The quotes for every market data request made through `req_quote`, keyed by request id
+#[derive(Default)]
+pub struct QuoteCache {
+    quotes: HashMap<i32, QuoteSlot>,
+}
+
+impl QuoteCache {
+    pub fn new() -> QuoteCache {
+        QuoteCache::default()
+    }
+
+    /// Starts collecting ticks for a request id
+    pub fn insert(&mut self, request_id: i32) -> (Arc<RwLock<Quote>>, Receiver<()>) {
+        let quote = Arc::new(RwLock::new(Quote::default()));
+        let (tx, rx) = bounded(1);
+        self.quotes.insert(
+            request_id,
+            QuoteSlot {
+                quote: quote.clone(),
+                changed: tx,
+            },
+        );
+        (quote, rx)
+    }
+
+    pub fn remove(&mut self, request_id: i32) {
+        self.quotes.remove(&request_id);
+    }
+
+    /// Applies ticks and errors for cached requests. Returns false for anything else.
+    pub fn handle(&mut self, response: &Response) -> bool {
+        let (request_id, tick, error) = match response {
+            Response::Tick { request_id, tick } => (*request_id, Some(tick), None),
+            Response::Error(e) if !e.is_warning() => (e.id, None, Some(e)),
+            _ => return false,
+        };
+        let slot = match self.quotes.get(&request_id) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let changed = match slot.quote.write() {
+            Ok(mut quote) => match (tick, error) {
+                (Some(tick), _) => quote.apply(tick, SystemTime::now()),
+                (_, Some(error)) => {
+                    quote.error = Some(error.clone());
+                    true
+                }
+                _ => false,
+            },
+            Err(e) => {
+                error!("Error locking quote {}: {}", request_id, e);
+                false
+            }
+        };
+        if changed {
+            // A full channel already has a notification pending
+            let _ = slot.changed.try_send(());
+        }
+        true
+    }
+}
+
+/// A live quote kept up to date by a market data subscription. Dropping it cancels the
+/// subscription.
+pub struct LiveQuote {
+    request_id: i32,
+    quote: Arc<RwLock<Quote>>,
+    changed: Receiver<()>,
+    cancel: Option<Message>,
+    outbound: Sender<Message>,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+}
+
+impl LiveQuote {
+    pub(crate) fn new(
+        request_id: i32,
+        quote: Arc<RwLock<Quote>>,
+        changed: Receiver<()>,
+        cancel: Message,
+        outbound: Sender<Message>,
+        dispatcher: Arc<Mutex<Dispatcher>>,
+    ) -> LiveQuote {
+        LiveQuote {
+            request_id,
+            quote,
+            changed,
+            cancel: Some(cancel),
+            outbound,
+            dispatcher,
+        }
+    }
+
+    pub fn request_id(&self) -> i32 {
+        self.request_id
+    }
+
+    /// A copy of the quote as it is now
+    pub fn latest(&self) -> Quote {
+        match self.quote.read() {
+            Ok(quote) => quote.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    /// Waits until the quote changes, then returns it. Changes made since the last call count, so
+    /// none are missed, but several changes may be seen as one. Returns `None` on timeout.
+    pub fn wait_for_change(&self, timeout: Duration) -> Option<Quote> {
+        match self.changed.recv_timeout(timeout) {
+            Ok(_) => Some(self.latest()),
+            Err(_) => None,
+        }
+    }
+
+    /// Cancels the market data subscription. The quote keeps its last values.
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        let msg = match self.cancel.take() {
+            Some(msg) => msg,
+            None => return Ok(()),
+        };
+        match self.dispatcher.lock() {
+            Ok(mut d) => d.quotes_mut().remove(self.request_id),
+            Err(e) => error!("Error locking dispatcher: {}", e),
+        }
+        self.outbound
+            .send(msg)
+            .map_err(|e| anyhow!("Error queueing cancel message: {}", e))
+    }
+}
+
+impl Drop for LiveQuote {
+    fn drop(&mut self) {
+        if let Err(e) = self.cancel() {
+            debug!("Error cancelling quote {}: {}", self.request_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_data::TickAttributes;
+
+    #[test]
+    fn merge_ticks_into_quote() {
+        let mut cache = QuoteCache::new();
+        let (quote, changed) = cache.insert(1);
+        let tick = |tick| Response::Tick {
+            request_id: 1,
+            tick,
+        };
+
+        assert!(cache.handle(&tick(Tick::Price {
+            tick_type: TickType::Bid,
+            price: 150.25,
+            size: Some(Decimal::from(300)),
+            attributes: TickAttributes::default(),
+        })));
+        cache.handle(&tick(Tick::Size {
+            tick_type: TickType::Volume,
+            size: Decimal::from(12000),
+        }));
+        cache.handle(&tick(Tick::Generic {
+            tick_type: TickType::Halted,
+            value: 2.0,
+        }));
+        assert!(!cache.handle(&Response::Tick {
+            request_id: 2,
+            tick: Tick::SnapshotEnd,
+        }));
+
+        assert!(changed.try_recv().is_ok());
+        assert!(changed.try_recv().is_err());
+        let q = quote.read().unwrap();
+        assert_eq!(q.bid.as_ref().unwrap().value, 150.25);
+        assert_eq!(q.bid_size.as_ref().unwrap().value, Decimal::from(300));
+        assert_eq!(q.volume.as_ref().unwrap().value, Decimal::from(12000));
+        assert_eq!(q.halted.as_ref().unwrap().value, Halted::Volatility);
+        assert_eq!(q.ask, None);
+    }
+
+    #[test]
+    fn switching_to_delayed_data_clears_live_values() {
+        let mut quote = Quote::default();
+        let now = SystemTime::now();
+        quote.apply(&Tick::MarketDataType(MarketDataType::Live), now);
+        quote.apply(
+            &Tick::Size {
+                tick_type: TickType::BidSize,
+                size: Decimal::from(10),
+            },
+            now,
+        );
+        // Frozen is still live data
+        quote.apply(&Tick::MarketDataType(MarketDataType::Frozen), now);
+        assert!(quote.bid_size.is_some());
+
+        assert!(quote.apply(&Tick::MarketDataType(MarketDataType::Delayed), now));
+        assert_eq!(quote.bid_size, None);
+        assert_eq!(quote.market_data_type, Some(MarketDataType::Delayed));
+    }
+
+    #[test]
+    fn unchanged_tick_keeps_timestamp() {
+        let mut quote = Quote::default();
+        let first = SystemTime::UNIX_EPOCH;
+        let later = first + Duration::from_secs(1);
+        let tick = Tick::Size {
+            tick_type: TickType::AskSize,
+            size: Decimal::from(5),
+        };
+        assert!(quote.apply(&tick, first));
+        assert!(!quote.apply(&tick, later));
+        assert_eq!(quote.ask_size.unwrap().time, first);
+    }
+}
@@ -1,7 +1,42 @@
-pub struct Reader {}
+//! Reads length-prefixed messages off the socket and hands them to the inbound channel
+
+use crate::message::{InboundMessage, Message};
+use anyhow::*;
+use crossbeam_channel::Sender;
+use log::debug;
+use std::io::Read;
+use std::net::TcpStream;
+
+pub struct Reader {
+    stream: TcpStream,
+    tx: Sender<Message>,
+}
 
 impl Reader {
-    pub fn new() -> Reader {
-        Reader {}
+    pub fn new(stream: TcpStream, tx: Sender<Message>) -> Reader {
+        Reader { stream, tx }
+    }
+
+    /// Reads one complete message, including its 4 byte length prefix
+    pub fn read_frame(stream: &mut TcpStream) -> Result<Vec<u8>, Error> {
+        let mut size = [0; 4];
+        stream.read_exact(&mut size)?;
+        let len = u32::from_be_bytes(size) as usize;
+        let mut frame = vec![0; 4 + len];
+        frame[..4].copy_from_slice(&size);
+        stream.read_exact(&mut frame[4..])?;
+        Ok(frame)
+    }
+
+    /// Reads messages until the connection is closed or nobody is listening anymore
+    pub fn run(&mut self) -> Result<(), Error> {
+        loop {
+            let frame = Reader::read_frame(&mut self.stream)?;
+            debug!("Read {} bytes from server", frame.len());
+            let msg = InboundMessage::from_bytes(&frame)?;
+            if self.tx.send(Message::Inbound(msg)).is_err() {
+                return Ok(());
+            }
+        }
     }
 }
@@ -1,6 +1,6 @@
 //! These are the known server versions that determine features available;
 
-/// These are commented out here because they were in the Python API;
+// These are commented out here because they were in the Python API;
 
 // MIN_SERVER_VER_REAL_TIME_BARS      : usize =  34;
 // MIN_SERVER_VER_SCALE_ORDERS        : usize =  35;
@@ -9,101 +9,101 @@
 // MIN_SERVER_VER_WHAT_IF_ORDERS      : usize =  36;
 // MIN_SERVER_VER_CONTRACT_CONID      : usize =  37;
 
-/// Used server versions start here;
+// Used server versions start here;
 
-const MIN_SERVER_VER_PTA_ORDERS: usize = 39;
-const MIN_SERVER_VER_FUNDAMENTAL_DATA: usize = 40;
-const MIN_SERVER_VER_DELTA_NEUTRAL: usize = 40;
-const MIN_SERVER_VER_CONTRACT_DATA_CHAIN: usize = 40;
-const MIN_SERVER_VER_SCALE_ORDERS2: usize = 40;
-const MIN_SERVER_VER_ALGO_ORDERS: usize = 41;
-const MIN_SERVER_VER_EXECUTION_DATA_CHAIN: usize = 42;
-const MIN_SERVER_VER_NOT_HELD: usize = 44;
-const MIN_SERVER_VER_SEC_ID_TYPE: usize = 45;
-const MIN_SERVER_VER_PLACE_ORDER_CONID: usize = 46;
-const MIN_SERVER_VER_REQ_MKT_DATA_CONID: usize = 47;
-const MIN_SERVER_VER_REQ_CALC_IMPLIED_VOLAT: usize = 49;
-const MIN_SERVER_VER_REQ_CALC_OPTION_PRICE: usize = 50;
-const MIN_SERVER_VER_SSHORTX_OLD: usize = 51;
-const MIN_SERVER_VER_SSHORTX: usize = 52;
-const MIN_SERVER_VER_REQ_GLOBAL_CANCEL: usize = 53;
-const MIN_SERVER_VER_HEDGE_ORDERS: usize = 54;
-const MIN_SERVER_VER_REQ_MARKET_DATA_TYPE: usize = 55;
-const MIN_SERVER_VER_OPT_OUT_SMART_ROUTING: usize = 56;
-const MIN_SERVER_VER_SMART_COMBO_ROUTING_PARAMS: usize = 57;
-const MIN_SERVER_VER_DELTA_NEUTRAL_CONID: usize = 58;
-const MIN_SERVER_VER_SCALE_ORDERS3: usize = 60;
-const MIN_SERVER_VER_ORDER_COMBO_LEGS_PRICE: usize = 61;
-const MIN_SERVER_VER_TRAILING_PERCENT: usize = 62;
-const MIN_SERVER_VER_DELTA_NEUTRAL_OPEN_CLOSE: usize = 66;
-const MIN_SERVER_VER_POSITIONS: usize = 67;
-const MIN_SERVER_VER_ACCOUNT_SUMMARY: usize = 67;
-const MIN_SERVER_VER_TRADING_CLASS: usize = 68;
-const MIN_SERVER_VER_SCALE_TABLE: usize = 69;
-const MIN_SERVER_VER_LINKING: usize = 70;
-const MIN_SERVER_VER_ALGO_ID: usize = 71;
-const MIN_SERVER_VER_OPTIONAL_CAPABILITIES: usize = 72;
-const MIN_SERVER_VER_ORDER_SOLICITED: usize = 73;
-const MIN_SERVER_VER_LINKING_AUTH: usize = 74;
-const MIN_SERVER_VER_PRIMARYEXCH: usize = 75;
-const MIN_SERVER_VER_RANDOMIZE_SIZE_AND_PRICE: usize = 76;
-const MIN_SERVER_VER_FRACTIONAL_POSITIONS: usize = 101;
-const MIN_SERVER_VER_PEGGED_TO_BENCHMARK: usize = 102;
-const MIN_SERVER_VER_MODELS_SUPPORT: usize = 103;
-const MIN_SERVER_VER_SEC_DEF_OPT_PARAMS_REQ: usize = 104;
-const MIN_SERVER_VER_EXT_OPERATOR: usize = 105;
-const MIN_SERVER_VER_SOFT_DOLLAR_TIER: usize = 106;
-const MIN_SERVER_VER_REQ_FAMILY_CODES: usize = 107;
-const MIN_SERVER_VER_REQ_MATCHING_SYMBOLS: usize = 108;
-const MIN_SERVER_VER_PAST_LIMIT: usize = 109;
-const MIN_SERVER_VER_MD_SIZE_MULTIPLIER: usize = 110;
-const MIN_SERVER_VER_CASH_QTY: usize = 111;
-const MIN_SERVER_VER_REQ_MKT_DEPTH_EXCHANGES: usize = 112;
-const MIN_SERVER_VER_TICK_NEWS: usize = 113;
-const MIN_SERVER_VER_REQ_SMART_COMPONENTS: usize = 114;
-const MIN_SERVER_VER_REQ_NEWS_PROVIDERS: usize = 115;
-const MIN_SERVER_VER_REQ_NEWS_ARTICLE: usize = 116;
-const MIN_SERVER_VER_REQ_HISTORICAL_NEWS: usize = 117;
-const MIN_SERVER_VER_REQ_HEAD_TIMESTAMP: usize = 118;
-const MIN_SERVER_VER_REQ_HISTOGRAM: usize = 119;
-const MIN_SERVER_VER_SERVICE_DATA_TYPE: usize = 120;
-const MIN_SERVER_VER_AGG_GROUP: usize = 121;
-const MIN_SERVER_VER_UNDERLYING_INFO: usize = 122;
-const MIN_SERVER_VER_CANCEL_HEADTIMESTAMP: usize = 123;
-const MIN_SERVER_VER_SYNT_REALTIME_BARS: usize = 124;
-const MIN_SERVER_VER_CFD_REROUTE: usize = 125;
-const MIN_SERVER_VER_MARKET_RULES: usize = 126;
-const MIN_SERVER_VER_PNL: usize = 127;
-const MIN_SERVER_VER_NEWS_QUERY_ORIGINS: usize = 128;
-const MIN_SERVER_VER_UNREALIZED_PNL: usize = 129;
-const MIN_SERVER_VER_HISTORICAL_TICKS: usize = 130;
-const MIN_SERVER_VER_MARKET_CAP_PRICE: usize = 131;
-const MIN_SERVER_VER_PRE_OPEN_BID_ASK: usize = 132;
-const MIN_SERVER_VER_REAL_EXPIRATION_DATE: usize = 134;
-const MIN_SERVER_VER_REALIZED_PNL: usize = 135;
-const MIN_SERVER_VER_LAST_LIQUIDITY: usize = 136;
-const MIN_SERVER_VER_TICK_BY_TICK: usize = 137;
-const MIN_SERVER_VER_DECISION_MAKER: usize = 138;
-const MIN_SERVER_VER_MIFID_EXECUTION: usize = 139;
-const MIN_SERVER_VER_TICK_BY_TICK_IGNORE_SIZE: usize = 140;
-const MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE: usize = 141;
-const MIN_SERVER_VER_WHAT_IF_EXT_FIELDS: usize = 142;
-const MIN_SERVER_VER_SCANNER_GENERIC_OPTS: usize = 143;
-const MIN_SERVER_VER_API_BIND_ORDER: usize = 144;
-const MIN_SERVER_VER_ORDER_CONTAINER: usize = 145;
-const MIN_SERVER_VER_SMART_DEPTH: usize = 146;
-const MIN_SERVER_VER_REMOVE_NULL_ALL_CASTING: usize = 147;
-const MIN_SERVER_VER_D_PEG_ORDERS: usize = 148;
-const MIN_SERVER_VER_MKT_DEPTH_PRIM_EXCHANGE: usize = 149;
-const MIN_SERVER_VER_COMPLETED_ORDERS: usize = 150;
-const MIN_SERVER_VER_PRICE_MGMT_ALGO: usize = 151;
-const MIN_SERVER_VER_STOCK_TYPE: usize = 152;
-const MIN_SERVER_VER_ENCODE_MSG_ASCII7: usize = 153;
-const MIN_SERVER_VER_SEND_ALL_FAMILY_CODES: usize = 154;
-const MIN_SERVER_VER_NO_DEFAULT_OPEN_CLOSE: usize = 155;
-const MIN_SERVER_VER_PRICE_BASED_VOLATILITY: usize = 156;
-const MIN_SERVER_VER_REPLACE_FA_END: usize = 157;
+pub const MIN_SERVER_VER_PTA_ORDERS: usize = 39;
+pub const MIN_SERVER_VER_FUNDAMENTAL_DATA: usize = 40;
+pub const MIN_SERVER_VER_DELTA_NEUTRAL: usize = 40;
+pub const MIN_SERVER_VER_CONTRACT_DATA_CHAIN: usize = 40;
+pub const MIN_SERVER_VER_SCALE_ORDERS2: usize = 40;
+pub const MIN_SERVER_VER_ALGO_ORDERS: usize = 41;
+pub const MIN_SERVER_VER_EXECUTION_DATA_CHAIN: usize = 42;
+pub const MIN_SERVER_VER_NOT_HELD: usize = 44;
+pub const MIN_SERVER_VER_SEC_ID_TYPE: usize = 45;
+pub const MIN_SERVER_VER_PLACE_ORDER_CONID: usize = 46;
+pub const MIN_SERVER_VER_REQ_MKT_DATA_CONID: usize = 47;
+pub const MIN_SERVER_VER_REQ_CALC_IMPLIED_VOLAT: usize = 49;
+pub const MIN_SERVER_VER_REQ_CALC_OPTION_PRICE: usize = 50;
+pub const MIN_SERVER_VER_SSHORTX_OLD: usize = 51;
+pub const MIN_SERVER_VER_SSHORTX: usize = 52;
+pub const MIN_SERVER_VER_REQ_GLOBAL_CANCEL: usize = 53;
+pub const MIN_SERVER_VER_HEDGE_ORDERS: usize = 54;
+pub const MIN_SERVER_VER_REQ_MARKET_DATA_TYPE: usize = 55;
+pub const MIN_SERVER_VER_OPT_OUT_SMART_ROUTING: usize = 56;
+pub const MIN_SERVER_VER_SMART_COMBO_ROUTING_PARAMS: usize = 57;
+pub const MIN_SERVER_VER_DELTA_NEUTRAL_CONID: usize = 58;
+pub const MIN_SERVER_VER_SCALE_ORDERS3: usize = 60;
+pub const MIN_SERVER_VER_ORDER_COMBO_LEGS_PRICE: usize = 61;
+pub const MIN_SERVER_VER_TRAILING_PERCENT: usize = 62;
+pub const MIN_SERVER_VER_DELTA_NEUTRAL_OPEN_CLOSE: usize = 66;
+pub const MIN_SERVER_VER_POSITIONS: usize = 67;
+pub const MIN_SERVER_VER_ACCOUNT_SUMMARY: usize = 67;
+pub const MIN_SERVER_VER_TRADING_CLASS: usize = 68;
+pub const MIN_SERVER_VER_SCALE_TABLE: usize = 69;
+pub const MIN_SERVER_VER_LINKING: usize = 70;
+pub const MIN_SERVER_VER_ALGO_ID: usize = 71;
+pub const MIN_SERVER_VER_OPTIONAL_CAPABILITIES: usize = 72;
+pub const MIN_SERVER_VER_ORDER_SOLICITED: usize = 73;
+pub const MIN_SERVER_VER_LINKING_AUTH: usize = 74;
+pub const MIN_SERVER_VER_PRIMARYEXCH: usize = 75;
+pub const MIN_SERVER_VER_RANDOMIZE_SIZE_AND_PRICE: usize = 76;
+pub const MIN_SERVER_VER_FRACTIONAL_POSITIONS: usize = 101;
+pub const MIN_SERVER_VER_PEGGED_TO_BENCHMARK: usize = 102;
+pub const MIN_SERVER_VER_MODELS_SUPPORT: usize = 103;
+pub const MIN_SERVER_VER_SEC_DEF_OPT_PARAMS_REQ: usize = 104;
+pub const MIN_SERVER_VER_EXT_OPERATOR: usize = 105;
+pub const MIN_SERVER_VER_SOFT_DOLLAR_TIER: usize = 106;
+pub const MIN_SERVER_VER_REQ_FAMILY_CODES: usize = 107;
+pub const MIN_SERVER_VER_REQ_MATCHING_SYMBOLS: usize = 108;
+pub const MIN_SERVER_VER_PAST_LIMIT: usize = 109;
+pub const MIN_SERVER_VER_MD_SIZE_MULTIPLIER: usize = 110;
+pub const MIN_SERVER_VER_CASH_QTY: usize = 111;
+pub const MIN_SERVER_VER_REQ_MKT_DEPTH_EXCHANGES: usize = 112;
+pub const MIN_SERVER_VER_TICK_NEWS: usize = 113;
+pub const MIN_SERVER_VER_REQ_SMART_COMPONENTS: usize = 114;
+pub const MIN_SERVER_VER_REQ_NEWS_PROVIDERS: usize = 115;
+pub const MIN_SERVER_VER_REQ_NEWS_ARTICLE: usize = 116;
+pub const MIN_SERVER_VER_REQ_HISTORICAL_NEWS: usize = 117;
+pub const MIN_SERVER_VER_REQ_HEAD_TIMESTAMP: usize = 118;
+pub const MIN_SERVER_VER_REQ_HISTOGRAM: usize = 119;
+pub const MIN_SERVER_VER_SERVICE_DATA_TYPE: usize = 120;
+pub const MIN_SERVER_VER_AGG_GROUP: usize = 121;
+pub const MIN_SERVER_VER_UNDERLYING_INFO: usize = 122;
+pub const MIN_SERVER_VER_CANCEL_HEADTIMESTAMP: usize = 123;
+pub const MIN_SERVER_VER_SYNT_REALTIME_BARS: usize = 124;
+pub const MIN_SERVER_VER_CFD_REROUTE: usize = 125;
+pub const MIN_SERVER_VER_MARKET_RULES: usize = 126;
+pub const MIN_SERVER_VER_PNL: usize = 127;
+pub const MIN_SERVER_VER_NEWS_QUERY_ORIGINS: usize = 128;
+pub const MIN_SERVER_VER_UNREALIZED_PNL: usize = 129;
+pub const MIN_SERVER_VER_HISTORICAL_TICKS: usize = 130;
+pub const MIN_SERVER_VER_MARKET_CAP_PRICE: usize = 131;
+pub const MIN_SERVER_VER_PRE_OPEN_BID_ASK: usize = 132;
+pub const MIN_SERVER_VER_REAL_EXPIRATION_DATE: usize = 134;
+pub const MIN_SERVER_VER_REALIZED_PNL: usize = 135;
+pub const MIN_SERVER_VER_LAST_LIQUIDITY: usize = 136;
+pub const MIN_SERVER_VER_TICK_BY_TICK: usize = 137;
+pub const MIN_SERVER_VER_DECISION_MAKER: usize = 138;
+pub const MIN_SERVER_VER_MIFID_EXECUTION: usize = 139;
+pub const MIN_SERVER_VER_TICK_BY_TICK_IGNORE_SIZE: usize = 140;
+pub const MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE: usize = 141;
+pub const MIN_SERVER_VER_WHAT_IF_EXT_FIELDS: usize = 142;
+pub const MIN_SERVER_VER_SCANNER_GENERIC_OPTS: usize = 143;
+pub const MIN_SERVER_VER_API_BIND_ORDER: usize = 144;
+pub const MIN_SERVER_VER_ORDER_CONTAINER: usize = 145;
+pub const MIN_SERVER_VER_SMART_DEPTH: usize = 146;
+pub const MIN_SERVER_VER_REMOVE_NULL_ALL_CASTING: usize = 147;
+pub const MIN_SERVER_VER_D_PEG_ORDERS: usize = 148;
+pub const MIN_SERVER_VER_MKT_DEPTH_PRIM_EXCHANGE: usize = 149;
+pub const MIN_SERVER_VER_COMPLETED_ORDERS: usize = 150;
+pub const MIN_SERVER_VER_PRICE_MGMT_ALGO: usize = 151;
+pub const MIN_SERVER_VER_STOCK_TYPE: usize = 152;
+pub const MIN_SERVER_VER_ENCODE_MSG_ASCII7: usize = 153;
+pub const MIN_SERVER_VER_SEND_ALL_FAMILY_CODES: usize = 154;
+pub const MIN_SERVER_VER_NO_DEFAULT_OPEN_CLOSE: usize = 155;
+pub const MIN_SERVER_VER_PRICE_BASED_VOLATILITY: usize = 156;
+pub const MIN_SERVER_VER_REPLACE_FA_END: usize = 157;
 // 100+ messaging;
 // 100: usize =  enhanced handshake, msg length prefixes;
-const MIN_CLIENT_VER: usize = 100;
-const MAX_CLIENT_VER: usize = MIN_SERVER_VER_REPLACE_FA_END;
+pub const MIN_CLIENT_VER: usize = 100;
+pub const MAX_CLIENT_VER: usize = MIN_SERVER_VER_REPLACE_FA_END;
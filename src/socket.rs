@@ -1,18 +1,14 @@
-use crate::message::{InboundMessage, Message};
+use crate::message::Message;
+use crate::reader::Reader;
 use anyhow::Error;
 use anyhow::*;
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use log::{debug, error, info};
+use std::io::{Read, Write};
 use std::net::TcpStream;
-use std::{
-    io::Write,
-    sync::{Arc, Mutex},
-};
-use std::{
-    io::{self, Read},
-    thread::park,
-};
-static PRODUCTION_HOST: &'static str = "localhost";
+use std::sync::{Arc, Mutex};
+use std::thread;
+static PRODUCTION_HOST: &str = "localhost";
 static PRODUCTION_PORT: u16 = 7496;
 type RxChan = Arc<Mutex<Receiver<Message>>>;
 type TxChan = Arc<Mutex<Sender<Message>>>;
@@ -35,7 +31,7 @@ impl IBSocket {
         Self {
             stream: None,
             host: host.into(),
-            port: port,
+            port,
             outbound_rx: Some(Arc::new(Mutex::new(orx))),
             outbound_tx: Some(Arc::new(Mutex::new(otx))),
             inbound_rx: Some(Arc::new(Mutex::new(irx))),
@@ -50,11 +46,9 @@ impl IBSocket {
             Ok(stream) => {
                 self.stream = Some(stream);
                 info!("Socket connected!");
-                return Ok(());
-            }
-            Err(e) => {
-                return Err(e.into());
+                Ok(())
             }
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -62,7 +56,7 @@ impl IBSocket {
     pub fn send(&mut self, message: &Message) -> Result<(), Error> {
         match &mut self.stream {
             Some(ref mut s) => {
-                s.write(&message.to_bytes())?;
+                s.write_all(&message.to_bytes())?;
                 Ok(())
             }
             None => Err(anyhow!("No stream to API server exists")),
@@ -72,8 +66,8 @@ impl IBSocket {
     pub fn send_raw(&mut self, message: &[u8]) -> Result<(), Error> {
         match &mut self.stream {
             Some(ref mut s) => {
-                let b = s.write(&message)?;
-                debug!("Wrote {} bytes", b);
+                s.write_all(message)?;
+                debug!("Wrote {} bytes", message.len());
                 Ok(())
             }
             None => Err(anyhow!("No stream to API server exists")),
@@ -98,82 +92,79 @@ impl IBSocket {
         }
     }
 
-    /// Starts the send and receive loop
+    /// Receives exactly one length-prefixed message, including the prefix. Will block until it does.
+    pub fn receive_message(&mut self) -> Result<Vec<u8>, Error> {
+        match &mut self.stream {
+            Some(ref mut s) => Reader::read_frame(s),
+            None => Err(anyhow!("No stream to API server exists")),
+        }
+    }
+
+    /// Starts the send and receive threads. Inbound messages are read into the inbound channel,
+    /// and anything put on the outbound channel is written to the server.
     pub fn start(&self) -> Result<(), Error> {
-        let mut s = if let Some(s) = &self.stream {
+        let s = if let Some(s) = &self.stream {
             s
         } else {
             return Err(anyhow!("No connected socket found!"));
         };
-        s.set_nonblocking(true)?;
 
-        loop {
-            let mut buf: String = String::new();
-            match s.read_to_string(&mut buf) {
-                Ok(b) => {
-                    debug!("Read {} bytes from server!", b);
-                    match InboundMessage::from_bytes(&buf.into_bytes()) {
-                        Ok(msg) => match &self.inbound_tx {
-                            Some(tx) => match tx.lock() {
-                                Ok(l) => match l.send(Message::Inbound(msg)) {
-                                    Ok(_) => {}
-                                    Err(e) => {
-                                        error!("Error dispatching InboundMessage: {}", e);
-                                    }
-                                },
-                                Err(e) => {
-                                    error!("Error dispatching received message: {}", e);
-                                    break;
-                                }
-                            },
-                            None => {
-                                error!("No inbound_tx channel found!");
-                                break;
-                            }
-                        },
-                        Err(e) => {
-                            error!("Error extracting InboundMessage: {}", e);
-                        }
-                    }
-                }
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    continue;
-                }
-                Err(e) => {
-                    error!("Error receiving data: {}", e);
-                    break;
-                }
+        let inbound_tx = match &self.inbound_tx {
+            Some(tx) => lock(tx)?,
+            None => return Err(anyhow!("No inbound_tx channel found!")),
+        };
+        let outbound_rx = match &self.outbound_rx {
+            Some(rx) => lock(rx)?,
+            None => return Err(anyhow!("No outbound_rx channel found!")),
+        };
+
+        let mut reader = Reader::new(s.try_clone()?, inbound_tx);
+        thread::spawn(move || {
+            if let Err(e) = reader.run() {
+                error!("Error receiving data: {}", e);
             }
+        });
 
-            match &self.outbound_rx {
-                Some(rx) => match rx.lock() {
-                    Ok(l) => {
-                        while l.len() > 0 {
-                            match l.try_recv() {
-                                Ok(msg) => match s.write(&msg.to_bytes()) {
-                                    Ok(b) => {
-                                        debug!("Write {} bytes to socket", b);
-                                    }
-                                    Err(e) => {
-                                        anyhow!("Error writing to socket: {}", e);
-                                    }
-                                },
-                                Err(e) => {
-                                    error!("Error receiving outbound message to socket: {}", e);
-                                }
-                            }
-                        }
+        let mut writer = s.try_clone()?;
+        thread::spawn(move || {
+            for msg in outbound_rx.iter() {
+                match writer.write_all(&msg.to_bytes()) {
+                    Ok(_) => {
+                        debug!("Wrote message to socket");
                     }
                     Err(e) => {
-                        error!("Error locking outbound RX channel: {}", e);
+                        error!("Error writing to socket: {}", e);
+                        break;
                     }
-                },
-                None => {}
+                }
             }
-            park();
-        }
+        });
         Ok(())
     }
+
+    /// Returns a handle for queueing messages to be written by the send thread
+    pub fn sender(&self) -> Result<Sender<Message>, Error> {
+        match &self.outbound_tx {
+            Some(tx) => lock(tx),
+            None => Err(anyhow!("No outbound_tx channel found!")),
+        }
+    }
+
+    /// Returns a handle for the messages read by the receive thread
+    pub fn receiver(&self) -> Result<Receiver<Message>, Error> {
+        match &self.inbound_rx {
+            Some(rx) => lock(rx),
+            None => Err(anyhow!("No inbound_rx channel found!")),
+        }
+    }
+}
+
+/// Clones a channel handle out of its mutex
+fn lock<T: Clone>(chan: &Arc<Mutex<T>>) -> Result<T, Error> {
+    match chan.lock() {
+        Ok(c) => Ok(c.clone()),
+        Err(e) => Err(anyhow!("Error locking channel: {}", e)),
+    }
 }
 
 impl Default for IBSocket {
@@ -192,7 +183,7 @@ impl Default for IBSocket {
 
 #[cfg(test)]
 mod tests {
-    static TEST_HOST: &'static str = "localhost";
+    static TEST_HOST: &str = "localhost";
     static TEST_PORT: u16 = 5555;
     use super::*;
     #[test]
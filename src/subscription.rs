@@ -0,0 +1,266 @@
+//! A handle on a streaming request. Responses for the request arrive on a channel and are turned
+//! into typed items; dropping the handle cancels the request.
+
+use crate::decoder::Response;
+use crate::dispatcher::{Dispatcher, RouteKey};
+use crate::message::Message;
+use anyhow::*;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use log::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub struct Subscription<T> {
+    route: RouteKey,
+    /// Set for routes shared by every request of a kind, which a newer subscription replaces
+    owner: Option<u64>,
+    responses: Receiver<Response>,
+    /// Sent to the server when the subscription is cancelled or dropped
+    cancel: Option<Message>,
+    outbound: Sender<Message>,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    /// Turns a response into an item; responses it returns `None` for are skipped
+    decode: fn(Response) -> Option<T>,
+    /// Whether an item is the last one the server will send for this request
+    is_end: fn(&T) -> bool,
+    finished: bool,
+}
+
+impl<T> Subscription<T> {
+    pub(crate) fn new(
+        request_id: i32,
+        responses: Receiver<Response>,
+        cancel: Option<Message>,
+        outbound: Sender<Message>,
+        dispatcher: Arc<Mutex<Dispatcher>>,
+        decode: fn(Response) -> Option<T>,
+        is_end: fn(&T) -> bool,
+    ) -> Subscription<T> {
+        Subscription {
+            route: RouteKey::Request(request_id),
+            owner: None,
+            responses,
+            cancel,
+            outbound,
+            dispatcher,
+            decode,
+            is_end,
+            finished: false,
+        }
+    }
+
+    /// Registers and subscribes to responses that don't carry a request id, of which there can
+    /// only be one at a time. A newer subscription to the same route takes it over; the older one
+    /// then ends, and dropping it neither removes the route nor cancels the request.
+    pub(crate) fn for_route(
+        route: RouteKey,
+        cancel: Option<Message>,
+        outbound: Sender<Message>,
+        dispatcher: Arc<Mutex<Dispatcher>>,
+        decode: fn(Response) -> Option<T>,
+        is_end: fn(&T) -> bool,
+    ) -> Result<Subscription<T>, Error> {
+        let (owner, responses) = match dispatcher.lock() {
+            Ok(mut d) => d.register_owned(route),
+            Err(e) => return Err(anyhow!("Error locking dispatcher: {}", e)),
+        };
+        Ok(Subscription {
+            route,
+            owner: Some(owner),
+            responses,
+            cancel,
+            outbound,
+            dispatcher,
+            decode,
+            is_end,
+            finished: false,
+        })
+    }
+
+    /// The id of the request, or -1 for subscriptions not tied to one
+    pub fn request_id(&self) -> i32 {
+        match self.route {
+            RouteKey::Request(id) => id,
+            _ => -1,
+        }
+    }
+
+    /// Whether the server has sent everything it will send for this request
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Blocks until the next item arrives. Returns `None` once the request has finished or was
+    /// cancelled, and an error if the server rejected the request.
+    pub fn next_item(&mut self) -> Option<Result<T, Error>> {
+        while !self.finished {
+            match self.responses.recv() {
+                Ok(response) => {
+                    if let Some(item) = self.process(response) {
+                        return Some(item);
+                    }
+                }
+                Err(_) => self.finished = true,
+            }
+        }
+        None
+    }
+
+    /// Like `next_item`, but gives up after `timeout`
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Result<T, Error>> {
+        while !self.finished {
+            match self.responses.recv_timeout(timeout) {
+                Ok(response) => {
+                    if let Some(item) = self.process(response) {
+                        return Some(item);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => self.finished = true,
+            }
+        }
+        None
+    }
+
+    /// Returns the next item if one has already arrived
+    pub fn try_next(&mut self) -> Option<Result<T, Error>> {
+        while !self.finished {
+            match self.responses.try_recv() {
+                Ok(response) => {
+                    if let Some(item) = self.process(response) {
+                        return Some(item);
+                    }
+                }
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+
+    fn process(&mut self, response: Response) -> Option<Result<T, Error>> {
+        if let Response::Error(e) = response {
+            if e.is_warning() {
+                info!("{}", e);
+                return None;
+            }
+            // The server stops sending data for a request it reports an error for
+            self.finished = true;
+            self.close();
+            return Some(Err(e.into()));
+        }
+        let item = (self.decode)(response)?;
+        if (self.is_end)(&item) {
+            self.finished = true;
+            self.close();
+        }
+        Some(Ok(item))
+    }
+
+    /// Cancels the request. Items already received are discarded.
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        if self.finished && self.cancel.is_none() {
+            return Ok(());
+        }
+        self.finished = true;
+        let cancel = self.cancel.take();
+        // Cancelling a route another subscription has taken over would stop its data too
+        if !self.close() {
+            return Ok(());
+        }
+        match cancel {
+            Some(msg) => self
+                .outbound
+                .send(msg)
+                .map_err(|e| anyhow!("Error queueing cancel message: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops routing responses for the request without telling the server. Returns false if the
+    /// route belongs to a newer subscription.
+    fn close(&mut self) -> bool {
+        self.cancel = None;
+        match self.dispatcher.lock() {
+            Ok(mut d) => match self.owner {
+                Some(owner) => d.unregister_owned(self.route, owner),
+                None => {
+                    d.unregister(self.route);
+                    true
+                }
+            },
+            Err(e) => {
+                error!("Error locking dispatcher: {}", e);
+                true
+            }
+        }
+    }
+}
+
+impl<T> Iterator for Subscription<T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_item()
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        if let Err(e) = self.cancel() {
+            debug!("Error cancelling {:?}: {}", self.route, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_data::Tick;
+    use crate::message::OutboundMessages;
+    use crossbeam_channel::unbounded;
+
+    fn snapshot_subscription(
+        dispatcher: &Arc<Mutex<Dispatcher>>,
+        outbound: Sender<Message>,
+    ) -> Subscription<Tick> {
+        let responses = dispatcher.lock().unwrap().register(RouteKey::Request(7));
+        Subscription::new(
+            7,
+            responses,
+            Some(Message::outbound(OutboundMessages::CancelMarketData)),
+            outbound,
+            dispatcher.clone(),
+            |r| match r {
+                Response::Tick { tick, .. } => Some(tick),
+                _ => None,
+            },
+            |t| *t == Tick::SnapshotEnd,
+        )
+    }
+
+    #[test]
+    fn finished_subscription_is_not_cancelled() {
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new(0)));
+        let (tx, rx) = unbounded();
+        let mut subscription = snapshot_subscription(&dispatcher, tx);
+        dispatcher.lock().unwrap().dispatch(Response::Tick {
+            request_id: 7,
+            tick: Tick::SnapshotEnd,
+        });
+
+        assert_eq!(subscription.try_next().unwrap().unwrap(), Tick::SnapshotEnd);
+        assert!(subscription.is_finished());
+        assert!(subscription.try_next().is_none());
+        drop(subscription);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn dropping_subscription_cancels_request() {
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new(0)));
+        let (tx, rx) = unbounded();
+        let subscription = snapshot_subscription(&dispatcher, tx);
+        drop(subscription);
+        assert!(rx.try_recv().is_ok());
+    }
+}
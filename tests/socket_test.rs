@@ -1,15 +1,10 @@
-
-
 use ibkrust::*;
-use env_logger;
 
 #[test]
+#[ignore = "needs TWS or IB Gateway listening on localhost:4001"]
 fn test_connect_to_tws() {
-  env_logger::init();
-  let mut test_client = client::EClient::new("localhost", 4001, 100);
-  assert_eq!(test_client.connect().is_ok(), true);
-  assert_eq!(test_client.start_api().is_ok(), true);
+    env_logger::init();
+    let mut test_client = client::EClient::new("localhost", 4001, 100);
+    assert!(test_client.connect().is_ok());
+    assert!(test_client.start_api().is_ok());
 }
-
-
-